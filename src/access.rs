@@ -0,0 +1,206 @@
+//! Memory access prediction
+//!
+//! rz80 performs memory accesses directly on `CPU::mem` without calling
+//! back into the bus, and silently drops writes to read-only pages. To
+//! catch such accesses we decode the next instruction before it executes
+//! and work out which memory it is going to read or write.
+//!
+//! Every Z80 instruction reads at most one contiguous region of one or two
+//! bytes besides its opcode and operands, and writes at most one, so each
+//! prediction is a single `(address, length)` pair.
+
+use rz80::CPU;
+
+/// A contiguous memory region touched by an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub addr: u16,
+    pub len: u8,
+}
+
+impl Region {
+    fn new(addr: u16, len: u8) -> Self {
+        Self { addr, len }
+    }
+
+    /// Addresses covered by the region (wrapping at $FFFF)
+    pub fn addrs(&self) -> impl Iterator<Item = u16> {
+        let addr = self.addr;
+        (0..self.len as u16).map(move |i| addr.wrapping_add(i))
+    }
+}
+
+fn r8(cpu: &CPU, addr: u16) -> u8 {
+    cpu.mem.r8(addr as i32) as u8
+}
+
+fn r16(cpu: &CPU, addr: u16) -> u16 {
+    (r8(cpu, addr.wrapping_add(1)) as u16) << 8 | r8(cpu, addr) as u16
+}
+
+/// Evaluate condition code `y` (NZ, Z, NC, C, PO, PE, P, M) against F
+fn condition(cpu: &CPU, y: u8) -> bool {
+    let f = cpu.reg.f() as u8;
+    let set = match y >> 1 {
+        0 => f & 0x40 != 0, // Z
+        1 => f & 0x01 != 0, // C
+        2 => f & 0x04 != 0, // P/V
+        _ => f & 0x80 != 0, // S
+    };
+    if y & 1 == 0 { !set } else { set }
+}
+
+/// Skip any run of DD/FD prefixes at PC; the last one wins. Returns the
+/// address of the opcode, and the index register value if prefixed.
+fn skip_prefixes(cpu: &CPU) -> (u16, Option<u16>) {
+    let mut addr = cpu.reg.pc() as u16;
+    let mut index: Option<u16> = None;
+    let mut op = r8(cpu, addr);
+    while op == 0xDD || op == 0xFD {
+        index = Some(if op == 0xDD { cpu.reg.ix() } else { cpu.reg.iy() } as u16);
+        addr = addr.wrapping_add(1);
+        op = r8(cpu, addr);
+    }
+    (addr, index)
+}
+
+/// Predict the memory region the instruction at PC will write, if any
+pub fn memory_write(cpu: &CPU) -> Option<Region> {
+    let sp = cpu.reg.sp() as u16;
+    let hl = cpu.reg.hl() as u16;
+    let (addr, index) = skip_prefixes(cpu);
+    let op = r8(cpu, addr);
+
+    let imm16 = r16(cpu, addr.wrapping_add(1));
+
+    // (HL), or (IX+d)/(IY+d) with the displacement at `disp_at`
+    let indexed = |disp_at: u16| match index {
+        Some(base) => base.wrapping_add(r8(cpu, disp_at) as i8 as u16),
+        None => hl,
+    };
+
+    let x = op >> 6;
+    let y = (op >> 3) & 7;
+    let z = op & 7;
+
+    match (x, y, z) {
+        // LD (BC),A / LD (DE),A
+        (0, 0, 2) => Some(Region::new(cpu.reg.bc() as u16, 1)),
+        (0, 2, 2) => Some(Region::new(cpu.reg.de() as u16, 1)),
+        // LD (nn),HL / LD (nn),IX / LD (nn),IY
+        (0, 4, 2) => Some(Region::new(imm16, 2)),
+        // LD (nn),A
+        (0, 6, 2) => Some(Region::new(imm16, 1)),
+        // INC (HL) / DEC (HL) / LD (HL),n
+        (0, 6, 4) | (0, 6, 5) | (0, 6, 6) => Some(Region::new(indexed(addr.wrapping_add(1)), 1)),
+        // HALT
+        (1, 6, 6) => None,
+        // LD (HL),r
+        (1, 6, _) => Some(Region::new(indexed(addr.wrapping_add(1)), 1)),
+        // CALL cc,nn
+        (3, _, 4) if condition(cpu, y) => Some(Region::new(sp.wrapping_sub(2), 2)),
+        // PUSH rr / CALL nn
+        (3, 0 | 2 | 4 | 6 | 1, 5) => Some(Region::new(sp.wrapping_sub(2), 2)),
+        // RST n
+        (3, _, 7) => Some(Region::new(sp.wrapping_sub(2), 2)),
+        // EX (SP),HL
+        (3, 4, 3) => Some(Region::new(sp, 2)),
+        // CB prefix: rotates, shifts, RES and SET on (HL) or (IX+d)
+        (3, 1, 3) => {
+            if index.is_some() {
+                // DD CB d op: every non-BIT form writes (IX+d)
+                let cb = r8(cpu, addr.wrapping_add(2));
+                (cb >> 6 != 1).then(|| Region::new(indexed(addr.wrapping_add(1)), 1))
+            } else {
+                let cb = r8(cpu, addr.wrapping_add(1));
+                (cb & 7 == 6 && cb >> 6 != 1).then(|| Region::new(hl, 1))
+            }
+        }
+        // ED prefix (index prefixes have no effect)
+        (3, 5, 5) => {
+            let ed = r8(cpu, addr.wrapping_add(1));
+            let nn = r16(cpu, addr.wrapping_add(2));
+            match ed {
+                // LD (nn),BC/DE/HL/SP
+                0x43 | 0x53 | 0x63 | 0x73 => Some(Region::new(nn, 2)),
+                // RRD / RLD
+                0x67 | 0x6F => Some(Region::new(cpu.reg.hl() as u16, 1)),
+                // LDI / LDD / LDIR / LDDR
+                0xA0 | 0xA8 | 0xB0 | 0xB8 => Some(Region::new(cpu.reg.de() as u16, 1)),
+                // INI / IND / INIR / INDR
+                0xA2 | 0xAA | 0xB2 | 0xBA => Some(Region::new(cpu.reg.hl() as u16, 1)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Predict the memory region the instruction at PC will read as data, if
+/// any. Opcode and operand fetches are not counted.
+pub fn memory_read(cpu: &CPU) -> Option<Region> {
+    let sp = cpu.reg.sp() as u16;
+    let hl = cpu.reg.hl() as u16;
+    let (addr, index) = skip_prefixes(cpu);
+    let op = r8(cpu, addr);
+
+    let imm16 = r16(cpu, addr.wrapping_add(1));
+    let indexed = |disp_at: u16| match index {
+        Some(base) => base.wrapping_add(r8(cpu, disp_at) as i8 as u16),
+        None => hl,
+    };
+
+    let x = op >> 6;
+    let y = (op >> 3) & 7;
+    let z = op & 7;
+
+    match (x, y, z) {
+        // LD A,(BC) / LD A,(DE)
+        (0, 1, 2) => Some(Region::new(cpu.reg.bc() as u16, 1)),
+        (0, 3, 2) => Some(Region::new(cpu.reg.de() as u16, 1)),
+        // LD HL,(nn) / LD IX,(nn) / LD IY,(nn)
+        (0, 5, 2) => Some(Region::new(imm16, 2)),
+        // LD A,(nn)
+        (0, 7, 2) => Some(Region::new(imm16, 1)),
+        // INC (HL) / DEC (HL)
+        (0, 6, 4) | (0, 6, 5) => Some(Region::new(indexed(addr.wrapping_add(1)), 1)),
+        // HALT
+        (1, 6, 6) => None,
+        // LD r,(HL) / ALU A,(HL)
+        (1, _, 6) | (2, _, 6) => Some(Region::new(indexed(addr.wrapping_add(1)), 1)),
+        // RET cc
+        (3, _, 0) if condition(cpu, y) => Some(Region::new(sp, 2)),
+        // POP rr / RET
+        (3, 0 | 2 | 4 | 6 | 1, 1) => Some(Region::new(sp, 2)),
+        // EX (SP),HL
+        (3, 4, 3) => Some(Region::new(sp, 2)),
+        // CB prefix: everything on (HL) or (IX+d) reads it, BIT included
+        (3, 1, 3) => {
+            if index.is_some() {
+                Some(Region::new(indexed(addr.wrapping_add(1)), 1))
+            } else {
+                let cb = r8(cpu, addr.wrapping_add(1));
+                (cb & 7 == 6).then(|| Region::new(hl, 1))
+            }
+        }
+        // ED prefix (index prefixes have no effect)
+        (3, 5, 5) => {
+            let ed = r8(cpu, addr.wrapping_add(1));
+            let nn = r16(cpu, addr.wrapping_add(2));
+            match ed {
+                // LD BC/DE/HL/SP,(nn)
+                0x4B | 0x5B | 0x6B | 0x7B => Some(Region::new(nn, 2)),
+                // RETN / RETI and their mirrors
+                _ if ed & 0xC7 == 0x45 => Some(Region::new(sp, 2)),
+                // RRD / RLD
+                0x67 | 0x6F => Some(Region::new(hl, 1)),
+                // LDI / CPI / OUTI and their decrementing and repeating forms
+                0xA0 | 0xA8 | 0xB0 | 0xB8 | 0xA1 | 0xA9 | 0xB1 | 0xB9 | 0xA3 | 0xAB | 0xB3 | 0xBB => {
+                    Some(Region::new(hl, 1))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,281 @@
+//! PC breakpoints
+//!
+//! A breakpoint stops execution when the CPU is about to execute the
+//! instruction at its address. It may carry a condition (an `expr`
+//! expression, checked each time the address is reached) and a hit count:
+//! how many times it has to be reached, with the condition true, before it
+//! stops.
+//!
+//! Step over, step out, run-to and stepping N instructions are runs with a
+//! `Goal`, checked after each instruction alongside the breakpoints.
+
+use std::fmt;
+
+use rz80::CPU;
+
+use crate::disasm::{disassemble, Flow};
+use crate::expr::{self, Expr};
+use crate::symbols::Symbols;
+
+#[derive(Debug, Clone)]
+pub struct Breakpoint {
+    pub id: u32,
+    pub addr: u16,
+    pub enabled: bool,
+    pub condition: Option<Expr>,
+    /// Stop on this hit and every one after it (1 stops every time)
+    pub after: u64,
+    /// Times the address was reached with the condition true
+    pub hits: u64,
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} ${:04X}", self.id, self.addr)?;
+        if let Some(condition) = &self.condition {
+            write!(f, " if {}", condition)?;
+        }
+        if self.after > 1 {
+            write!(f, " after {}", self.after)?;
+        }
+        write!(f, " ({} hit{})", self.hits, if self.hits == 1 { "" } else { "s" })?;
+        if !self.enabled {
+            write!(f, " off")?;
+        }
+        Ok(())
+    }
+}
+
+/// The breakpoints of one debugging session, numbered from 1
+#[derive(Debug, Default)]
+pub struct Breakpoints {
+    list: Vec<Breakpoint>,
+    last_id: u32,
+}
+
+impl Breakpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a breakpoint and return its number
+    pub fn add(&mut self, addr: u16, condition: Option<Expr>, after: u64) -> u32 {
+        self.last_id += 1;
+        self.list.push(Breakpoint {
+            id: self.last_id,
+            addr,
+            enabled: true,
+            condition,
+            after: after.max(1),
+            hits: 0,
+        });
+        self.last_id
+    }
+
+    pub fn remove(&mut self, id: u32) -> Result<Breakpoint, String> {
+        let index = self.index(id)?;
+        Ok(self.list.remove(index))
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+
+    /// Enable or disable a breakpoint. Returns whether it is now enabled.
+    pub fn toggle(&mut self, id: u32) -> Result<bool, String> {
+        let index = self.index(id)?;
+        let bp = &mut self.list[index];
+        bp.enabled = !bp.enabled;
+        Ok(bp.enabled)
+    }
+
+    fn index(&self, id: u32) -> Result<usize, String> {
+        self.list
+            .iter()
+            .position(|bp| bp.id == id)
+            .ok_or_else(|| format!("no breakpoint #{}", id))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.list.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    /// The breakpoint at an address, preferring an enabled one
+    pub fn at(&self, addr: u16) -> Option<&Breakpoint> {
+        let mut here = self.list.iter().filter(|bp| bp.addr == addr);
+        let first = here.clone().next();
+        here.find(|bp| bp.enabled).or(first)
+    }
+
+    /// Count a hit on every enabled breakpoint at PC whose condition
+    /// holds, and return the first one that should stop
+    pub fn check(&mut self, cpu: &CPU) -> Option<&Breakpoint> {
+        let pc = cpu.reg.pc() as u16;
+        let mut stop = None;
+        for (i, bp) in self.list.iter_mut().enumerate() {
+            if !bp.enabled || bp.addr != pc || !bp.condition.as_ref().is_none_or(|c| c.is_true(cpu)) {
+                continue;
+            }
+            bp.hits += 1;
+            if bp.hits >= bp.after && stop.is_none() {
+                stop = Some(i);
+            }
+        }
+        stop.map(|i| &self.list[i])
+    }
+}
+
+/// Parse `ADDR [if CONDITION] [after N]`. ADDR is an expression evaluated
+/// now (so `pc` means the current PC); CONDITION is kept for later.
+pub fn parse_spec(spec: &str, cpu: &CPU) -> Result<(u16, Option<Expr>, u64), String> {
+    parse_spec_with(spec, cpu, 10, &Symbols::new())
+}
+
+/// Parse a breakpoint with plain numbers, N included, in `radix` and names
+/// looked up in `symbols`
+pub fn parse_spec_with(spec: &str, cpu: &CPU, radix: u32, symbols: &Symbols) -> Result<(u16, Option<Expr>, u64), String> {
+    let words: Vec<&str> = spec.split_whitespace().collect();
+    let if_pos = words.iter().position(|w| w.eq_ignore_ascii_case("if"));
+    let after_pos = words.iter().position(|w| w.eq_ignore_ascii_case("after"));
+    let addr_end = if_pos.or(after_pos).unwrap_or(words.len());
+
+    if addr_end == 0 {
+        return Err("missing address".to_string());
+    }
+    let addr = Expr::parse_with(&words[..addr_end].join(" "), radix, symbols)?.eval(cpu);
+    let addr = u16::try_from(addr).map_err(|_| format!("address {} is out of range", addr))?;
+
+    let condition = match if_pos {
+        Some(start) => {
+            let end = after_pos.filter(|&a| a > start).unwrap_or(words.len());
+            if end == start + 1 {
+                return Err("missing condition after \"if\"".to_string());
+            }
+            Some(Expr::parse_with(&words[start + 1..end].join(" "), radix, symbols)?)
+        }
+        None => None,
+    };
+
+    let after = match after_pos {
+        Some(start) => {
+            if if_pos.is_some_and(|i| i > start) {
+                return Err("\"if\" must come before \"after\"".to_string());
+            }
+            match &words[start + 1..] {
+                [n] => expr::parse_number(n, radix)
+                    .and_then(|n| u64::try_from(n).ok())
+                    .ok_or_else(|| format!("invalid hit count \"{}\"", n))?,
+                _ => return Err("\"after\" takes one hit count".to_string()),
+            }
+        }
+        None => 1,
+    };
+
+    Ok((addr, condition, after))
+}
+
+/// Where a run started by step over, step out, run-to or `s N` stops by
+/// itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Goal {
+    /// PC at `addr` with SP at or above `sp`, so the same address reached
+    /// deeper in a recursion does not count
+    Reach { addr: u16, sp: u16 },
+    /// A return that leaves SP at or above `sp`: the return from the call
+    /// being stepped over, or from the current routine
+    Return { sp: u32 },
+    /// Past a CALL: `Reach` for the instruction after it at `addr`, as a
+    /// CALL cc not taken does, or `Return` from a call that was made
+    Call { addr: u16, sp: u16 },
+    /// After `left` more instructions, counted down with `count_step`
+    Steps { left: u32 },
+}
+
+impl Goal {
+    /// Step over the instruction at PC: run a CALL or RST until it returns,
+    /// and a repeating block instruction or DJNZ until it falls through.
+    /// Returns `None` for other instructions, which are simply stepped.
+    pub fn step_over(cpu: &CPU) -> Option<Goal> {
+        let pc = cpu.reg.pc() as u16;
+        let sp = cpu.reg.sp() as u16;
+        let read = |a: u16| cpu.mem.r8(a as i32) as u8;
+        let ins = disassemble(read, pc);
+
+        // LDIR, CPIR, INIR, OTIR and their decrementing forms
+        let repeating = read(pc) == 0xED && read(pc.wrapping_add(1)) & 0xF4 == 0xB0;
+        let djnz = read(pc) == 0x10;
+        match ins.flow {
+            // Returning from the call pops SP back to where it is now, even
+            // if the routine adjusts the return address to skip inline data.
+            // A CALL cc whose condition fails simply moves on.
+            Flow::Call => Some(Goal::Call {
+                addr: pc.wrapping_add(ins.len as u16),
+                sp,
+            }),
+            Flow::Restart => Some(Goal::Return { sp: sp as u32 }),
+            _ if repeating || djnz => Some(Goal::Reach {
+                addr: pc.wrapping_add(ins.len as u16),
+                sp,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Run until the current routine returns: the first return that pops
+    /// SP above where it is now. Calls made meanwhile return to the same
+    /// SP or below.
+    pub fn step_out(cpu: &CPU) -> Goal {
+        Goal::Return { sp: cpu.reg.sp() as u32 + 1 }
+    }
+
+    /// Run until PC reaches an address, at any stack depth
+    pub fn run_to(addr: u16) -> Goal {
+        Goal::Reach { addr, sp: 0 }
+    }
+
+    /// Run `count` instructions
+    pub fn steps(count: u32) -> Goal {
+        Goal::Steps { left: count }
+    }
+
+    /// Count an executed instruction that did not meet the goal
+    pub fn count_step(&mut self) {
+        if let Goal::Steps { left } = self {
+            *left = left.saturating_sub(1);
+        }
+    }
+
+    /// Whether the goal is met by a return, so the runner has to tell
+    /// `reached` whether each instruction was one
+    pub fn awaits_return(&self) -> bool {
+        matches!(self, Goal::Return { .. } | Goal::Call { .. })
+    }
+
+    /// Whether the goal is met once an instruction has executed. `returned`
+    /// tells whether that instruction was a return (see `is_return`).
+    pub fn reached(&self, cpu: &CPU, returned: bool) -> bool {
+        let pc = cpu.reg.pc() as u16;
+        let sp = cpu.reg.sp() as u16;
+        match *self {
+            Goal::Reach { addr, sp: min } => pc == addr && sp >= min,
+            Goal::Return { sp: min } => returned && sp as u32 >= min,
+            Goal::Call { addr, sp: min } => sp >= min && (pc == addr || returned),
+            Goal::Steps { left } => left <= 1,
+        }
+    }
+}
+
+/// Whether the instruction at PC is a return (RET cc counts whether or
+/// not it is taken; a return not taken leaves SP where it was)
+pub fn is_return(cpu: &CPU) -> bool {
+    let ins = disassemble(|a| cpu.mem.r8(a as i32) as u8, cpu.reg.pc() as u16);
+    matches!(ins.flow, Flow::Return | Flow::ConditionalReturn)
+}
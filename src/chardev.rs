@@ -0,0 +1,802 @@
+//! Host-side byte streams for the serial console
+//!
+//! A `CharDevice` is where serial input comes from and output goes to. The
+//! console polls it for input and hands it every transmitted byte, so the
+//! chip models work the same whether the other end is an in-memory queue
+//! (TUI, WASM, tests), the process's stdin/stdout, files, named pipes, a
+//! Unix socket, a client of a TCP or Unix socket the emulator serves, or a
+//! host pseudo-terminal.
+//!
+//! Reads never block: a device returns `None` when no byte is ready.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(unix)]
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+
+/// Host end of a serial line
+pub trait CharDevice {
+    /// Take the next byte if one is ready (never blocks)
+    fn read(&mut self) -> Option<u8>;
+
+    /// Send a byte to the host
+    fn write(&mut self, c: u8);
+
+    /// Push out any buffered output
+    fn flush(&mut self) {}
+
+    /// Where users can reach the device, if it serves an address or path
+    /// (for the front-end to tell them)
+    fn describe(&self) -> Option<String> {
+        None
+    }
+
+    /// Report clients coming and going on stderr
+    fn set_debug(&mut self, _debug: bool) {}
+}
+
+//=============================================================================
+// In-memory queue
+//=============================================================================
+
+/// Pair of in-memory queues. Clones share the same queues, so a front-end
+/// can keep one handle while the console owns another.
+#[derive(Clone, Default)]
+pub struct QueueDevice {
+    rx: Rc<RefCell<VecDeque<u8>>>,
+    tx: Rc<RefCell<VecDeque<u8>>>,
+}
+
+impl QueueDevice {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue bytes for the emulated system to receive
+    pub fn push_input(&self, bytes: &[u8]) {
+        self.rx.borrow_mut().extend(bytes);
+    }
+
+    /// Take the next transmitted byte
+    pub fn pop_output(&self) -> Option<u8> {
+        self.tx.borrow_mut().pop_front()
+    }
+
+    /// Take all transmitted bytes
+    pub fn take_output(&self) -> Vec<u8> {
+        self.tx.borrow_mut().drain(..).collect()
+    }
+
+    /// Get number of pending output bytes
+    pub fn pending_output(&self) -> usize {
+        self.tx.borrow().len()
+    }
+
+    /// Discard any queued input and output
+    pub fn clear(&self) {
+        self.rx.borrow_mut().clear();
+        self.tx.borrow_mut().clear();
+    }
+}
+
+impl CharDevice for QueueDevice {
+    fn read(&mut self) -> Option<u8> {
+        self.rx.borrow_mut().pop_front()
+    }
+
+    fn write(&mut self, c: u8) {
+        self.tx.borrow_mut().push_back(c);
+    }
+}
+
+//=============================================================================
+// Process stdin/stdout
+//=============================================================================
+
+/// The emulator's own stdin and stdout
+#[derive(Default)]
+pub struct StdioDevice;
+
+impl StdioDevice {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Wait for the next byte from stdin (`None` at end of file)
+    pub fn read_blocking(&mut self) -> Option<u8> {
+        #[cfg(unix)]
+        {
+            read_fd(libc::STDIN_FILENO)
+        }
+
+        #[cfg(not(unix))]
+        {
+            read_byte(&mut io::stdin())
+        }
+    }
+}
+
+impl CharDevice for StdioDevice {
+    fn read(&mut self) -> Option<u8> {
+        #[cfg(unix)]
+        {
+            if !readable(libc::STDIN_FILENO) {
+                return None;
+            }
+            // Straight from the descriptor: bytes left in `io::stdin()`'s
+            // buffer would be invisible to poll()
+            read_fd(libc::STDIN_FILENO)
+        }
+
+        #[cfg(not(unix))]
+        {
+            None // TODO: Windows implementation
+        }
+    }
+
+    fn write(&mut self, c: u8) {
+        let _ = io::stdout().write_all(&[c]);
+    }
+
+    fn flush(&mut self) {
+        let _ = io::stdout().flush();
+    }
+}
+
+//=============================================================================
+// Files
+//=============================================================================
+
+/// Input read from a file (as if typed, then silence at end of file),
+/// output written to a file or stdout
+pub struct FileDevice {
+    input: Option<BufReader<File>>,
+    output: Box<dyn Write>,
+}
+
+impl FileDevice {
+    /// Open the input file and create the output file. Without an input
+    /// file nothing is received; without an output file output goes to
+    /// stdout.
+    pub fn open(input: Option<&Path>, output: Option<&Path>) -> io::Result<Self> {
+        let input = input.map(File::open).transpose()?.map(BufReader::new);
+        let output: Box<dyn Write> = match output {
+            Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+            None => Box::new(io::stdout()),
+        };
+        Ok(Self { input, output })
+    }
+}
+
+impl CharDevice for FileDevice {
+    fn read(&mut self) -> Option<u8> {
+        read_byte(self.input.as_mut()?)
+    }
+
+    fn write(&mut self, c: u8) {
+        let _ = self.output.write_all(&[c]);
+    }
+
+    fn flush(&mut self) {
+        let _ = self.output.flush();
+    }
+}
+
+//=============================================================================
+// Named pipes and Unix sockets
+//=============================================================================
+
+/// A pair of named pipes (FIFOs): one the emulator reads, one it writes
+#[cfg(unix)]
+pub struct PipeDevice {
+    input: File,
+    output: File,
+}
+
+#[cfg(unix)]
+impl PipeDevice {
+    /// Open both FIFOs. Opening the output waits until something opens it
+    /// for reading.
+    pub fn open(input: &Path, output: &Path) -> io::Result<Self> {
+        use std::fs::OpenOptions;
+        use std::os::unix::fs::OpenOptionsExt;
+
+        // Don't wait for a writer on the input side
+        let input = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(input)?;
+        let output = OpenOptions::new().write(true).open(output)?;
+        Ok(Self { input, output })
+    }
+}
+
+#[cfg(unix)]
+impl CharDevice for PipeDevice {
+    fn read(&mut self) -> Option<u8> {
+        if !readable(self.input.as_raw_fd()) {
+            return None;
+        }
+        read_byte(&mut self.input)
+    }
+
+    fn write(&mut self, c: u8) {
+        let _ = self.output.write_all(&[c]);
+    }
+}
+
+/// A connected Unix domain stream socket
+#[cfg(unix)]
+pub struct UnixSocketDevice {
+    stream: UnixStream,
+}
+
+#[cfg(unix)]
+impl UnixSocketDevice {
+    /// Connect to a listening socket
+    pub fn connect(path: &Path) -> io::Result<Self> {
+        Ok(Self::from_stream(UnixStream::connect(path)?))
+    }
+
+    pub fn from_stream(stream: UnixStream) -> Self {
+        Self { stream }
+    }
+}
+
+#[cfg(unix)]
+impl CharDevice for UnixSocketDevice {
+    fn read(&mut self) -> Option<u8> {
+        if !readable(self.stream.as_raw_fd()) {
+            return None;
+        }
+        read_byte(&mut self.stream)
+    }
+
+    fn write(&mut self, c: u8) {
+        let _ = self.stream.write_all(&[c]);
+    }
+}
+
+//=============================================================================
+// Socket server (telnet-style console)
+//=============================================================================
+
+/// Telnet protocol bytes
+#[cfg(unix)]
+mod telnet {
+    pub const IAC: u8 = 255;   // Interpret As Command
+    pub const SB: u8 = 250;    // Subnegotiation begin
+    pub const SE: u8 = 240;    // Subnegotiation end
+    pub const WILL: u8 = 251;
+    pub const DONT: u8 = 254;
+    pub const ECHO: u8 = 1;
+    pub const SGA: u8 = 3;     // Suppress Go Ahead
+
+    /// Sent on connect: the firmware echoes, and there is no line mode
+    pub const CHARACTER_MODE: [u8; 6] = [IAC, WILL, ECHO, IAC, WILL, SGA];
+}
+
+/// Where an incoming telnet byte falls
+#[cfg(unix)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum TelnetState {
+    #[default]
+    Data,
+    /// Data, last byte was CR (a NUL after it is padding)
+    Cr,
+    Iac,
+    /// Option byte of WILL/WONT/DO/DONT
+    Option,
+    Sub,
+    SubIac,
+}
+
+#[cfg(unix)]
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+}
+
+#[cfg(unix)]
+enum Client {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+#[cfg(unix)]
+impl Listener {
+    fn accept(&self) -> io::Result<Client> {
+        match self {
+            Listener::Tcp(l) => l.accept().map(|(s, _)| Client::Tcp(s)),
+            Listener::Unix(l, _) => l.accept().map(|(s, _)| Client::Unix(s)),
+        }
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Listener::Tcp(l) => l.set_nonblocking(nonblocking),
+            Listener::Unix(l, _) => l.set_nonblocking(nonblocking),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Listener::Tcp(l) => l.local_addr().map_or("TCP".to_string(), |a| a.to_string()),
+            Listener::Unix(_, path) => path.display().to_string(),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Client {
+    fn stream(&mut self) -> &mut dyn ReadWriteFd {
+        match self {
+            Client::Tcp(s) => s,
+            Client::Unix(s) => s,
+        }
+    }
+}
+
+#[cfg(unix)]
+trait ReadWriteFd: Read + Write + AsRawFd {}
+#[cfg(unix)]
+impl<T: Read + Write + AsRawFd> ReadWriteFd for T {}
+
+/// Serves the console on a TCP port or Unix socket, one client at a time.
+/// Output is dropped while nobody is connected, unless `wait` is set, in
+/// which case the device (and so the CPU) blocks until a client attaches.
+/// With `telnet` set, the client is put in character mode and telnet
+/// commands are stripped from its input.
+#[cfg(unix)]
+pub struct SocketServerDevice {
+    listener: Listener,
+    client: Option<Client>,
+    wait: bool,
+    telnet: bool,
+    telnet_state: TelnetState,
+    debug: bool,
+}
+
+#[cfg(unix)]
+impl SocketServerDevice {
+    /// Listen on a TCP address such as "127.0.0.1:2323" (port 0 picks a
+    /// free port)
+    pub fn tcp(addr: &str, wait: bool, telnet: bool) -> io::Result<Self> {
+        Self::new(Listener::Tcp(TcpListener::bind(addr)?), wait, telnet)
+    }
+
+    /// Listen on a Unix socket, replacing a stale socket file
+    pub fn unix(path: &Path, wait: bool, telnet: bool) -> io::Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+
+        if std::fs::metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        Self::new(Listener::Unix(listener, path.to_path_buf()), wait, telnet)
+    }
+
+    fn new(listener: Listener, wait: bool, telnet: bool) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            client: None,
+            wait,
+            telnet,
+            telnet_state: TelnetState::Data,
+            debug: false,
+        })
+    }
+
+    /// Address or path being served
+    pub fn local_addr(&self) -> String {
+        self.listener.describe()
+    }
+
+    /// Check if a client is attached
+    pub fn connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Pick up a new client, or turn away extra ones. Blocks for the first
+    /// client in `wait` mode.
+    fn check_clients(&mut self) {
+        if self.client.is_none() && self.wait {
+            if self.debug {
+                eprintln!("Waiting for a client on {}...", self.listener.describe());
+            }
+            let _ = self.listener.set_nonblocking(false);
+            let accepted = self.listener.accept();
+            let _ = self.listener.set_nonblocking(true);
+            if let Ok(client) = accepted {
+                self.attach(client);
+            }
+            return;
+        }
+
+        while let Ok(mut client) = self.listener.accept() {
+            if self.client.is_none() {
+                self.attach(client);
+            } else {
+                let _ = client.stream().write_all(b"Serial port busy\r\n");
+            }
+        }
+    }
+
+    fn attach(&mut self, mut client: Client) {
+        if self.debug {
+            eprintln!("Serial console client connected");
+        }
+        if self.telnet {
+            let _ = client.stream().write_all(&telnet::CHARACTER_MODE);
+        }
+        self.telnet_state = TelnetState::Data;
+        self.client = Some(client);
+    }
+
+    fn detach(&mut self) {
+        if self.client.take().is_some() && self.debug {
+            eprintln!("Serial console client disconnected");
+        }
+    }
+
+    /// Pass a byte from the client through the telnet decoder
+    fn telnet_in(&mut self, c: u8) -> Option<u8> {
+        use telnet::*;
+
+        let (next, data) = match (self.telnet_state, c) {
+            (TelnetState::Data | TelnetState::Cr, IAC) => (TelnetState::Iac, None),
+            (TelnetState::Cr, 0) => (TelnetState::Data, None),
+            (TelnetState::Data | TelnetState::Cr, b'\r') => (TelnetState::Cr, Some(c)),
+            (TelnetState::Data | TelnetState::Cr, _) => (TelnetState::Data, Some(c)),
+            (TelnetState::Iac, IAC) => (TelnetState::Data, Some(IAC)),
+            (TelnetState::Iac, WILL..=DONT) => (TelnetState::Option, None),
+            (TelnetState::Iac, SB) => (TelnetState::Sub, None),
+            (TelnetState::Iac | TelnetState::Option, _) => (TelnetState::Data, None),
+            (TelnetState::Sub, IAC) => (TelnetState::SubIac, None),
+            (TelnetState::Sub, _) => (TelnetState::Sub, None),
+            (TelnetState::SubIac, SE) => (TelnetState::Data, None),
+            (TelnetState::SubIac, _) => (TelnetState::Sub, None),
+        };
+        self.telnet_state = next;
+        data
+    }
+}
+
+#[cfg(unix)]
+impl CharDevice for SocketServerDevice {
+    fn read(&mut self) -> Option<u8> {
+        self.check_clients();
+        loop {
+            let stream = self.client.as_mut()?.stream();
+            if !readable(stream.as_raw_fd()) {
+                return None;
+            }
+            let mut buf = [0u8; 1];
+            match stream.read(&mut buf) {
+                Ok(1) if !self.telnet => return Some(buf[0]),
+                Ok(1) => {
+                    if let Some(c) = self.telnet_in(buf[0]) {
+                        return Some(c);
+                    }
+                }
+                _ => {
+                    self.detach();
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, c: u8) {
+        if self.client.is_none() {
+            self.check_clients();
+        }
+        let Some(client) = self.client.as_mut() else {
+            return;
+        };
+        let result = if self.telnet && c == telnet::IAC {
+            client.stream().write_all(&[telnet::IAC, telnet::IAC])
+        } else {
+            client.stream().write_all(&[c])
+        };
+        if result.is_err() {
+            self.detach();
+        }
+    }
+
+    fn describe(&self) -> Option<String> {
+        Some(self.local_addr())
+    }
+
+    fn set_debug(&mut self, debug: bool) {
+        self.debug = debug;
+    }
+}
+
+//=============================================================================
+// Pseudo-terminal
+//=============================================================================
+
+/// How long a write waits for a full PTY buffer to drain before output is
+/// dropped (until the reader catches up)
+#[cfg(unix)]
+const PTY_WRITE_TIMEOUT_MS: i32 = 100;
+
+/// A host pseudo-terminal that terminal programs open like a USB serial
+/// port. The emulator keeps the slave side open in raw mode, so programs
+/// can open and close it at will.
+#[cfg(unix)]
+pub struct PtyDevice {
+    master: File,
+    _slave: File,
+    path: PathBuf,
+    link: Option<PathBuf>,
+    /// Nobody is reading: drop output instead of waiting
+    stalled: bool,
+}
+
+#[cfg(unix)]
+impl PtyDevice {
+    /// Allocate a PTY, optionally symlinked from `link` for a stable name
+    pub fn open(link: Option<&Path>) -> io::Result<Self> {
+        use std::ffi::{CStr, OsStr};
+        use std::fs::OpenOptions;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::fs::OpenOptionsExt;
+        use std::os::unix::io::FromRawFd;
+
+        let (master, path) = unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let master = File::from_raw_fd(fd);
+            if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let name = libc::ptsname(fd);
+            if name.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let path = PathBuf::from(OsStr::from_bytes(CStr::from_ptr(name).to_bytes()));
+            (master, path)
+        };
+
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&path)?;
+        set_raw(slave.as_raw_fd())?;
+        set_nonblocking(master.as_raw_fd())?;
+
+        if let Some(link) = link {
+            if std::fs::symlink_metadata(link).is_ok_and(|m| m.file_type().is_symlink()) {
+                std::fs::remove_file(link)?;
+            }
+            std::os::unix::fs::symlink(&path, link)?;
+        }
+
+        Ok(Self {
+            master,
+            _slave: slave,
+            path,
+            link: link.map(Path::to_path_buf),
+            stalled: false,
+        })
+    }
+
+    /// Device path of the slave side (/dev/pts/N)
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(unix)]
+impl Drop for PtyDevice {
+    fn drop(&mut self) {
+        if let Some(link) = &self.link {
+            let _ = std::fs::remove_file(link);
+        }
+    }
+}
+
+#[cfg(unix)]
+impl CharDevice for PtyDevice {
+    fn read(&mut self) -> Option<u8> {
+        if !readable(self.master.as_raw_fd()) {
+            return None;
+        }
+        read_byte(&mut self.master)
+    }
+
+    fn write(&mut self, c: u8) {
+        loop {
+            match self.master.write(&[c]) {
+                Ok(_) => {
+                    self.stalled = false;
+                    return;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock && !self.stalled => {
+                    if !writable(self.master.as_raw_fd(), PTY_WRITE_TIMEOUT_MS) {
+                        self.stalled = true;
+                    }
+                }
+                Err(_) => return,
+            }
+        }
+    }
+
+    fn describe(&self) -> Option<String> {
+        Some(match &self.link {
+            Some(link) => format!("{} ({})", self.path.display(), link.display()),
+            None => self.path.display().to_string(),
+        })
+    }
+}
+
+//=============================================================================
+// Device selection
+//=============================================================================
+
+/// Open a device from a command-line spec:
+///
+/// - `stdio`: the emulator's stdin and stdout
+/// - `file:IN[,OUT]`: input from a file, output to a file (or stdout)
+/// - `pipe:IN,OUT`: a pair of named pipes
+/// - `unix:PATH`: connect to a Unix socket
+/// - `tcp-listen:[HOST:]PORT[,wait][,telnet]`: serve a TCP port
+///   (127.0.0.1 unless a host is given)
+/// - `unix-listen:PATH[,wait][,telnet]`: serve a Unix socket
+/// - `pty[:LINK]`: a new pseudo-terminal, optionally symlinked from LINK
+pub fn open(spec: &str) -> io::Result<Box<dyn CharDevice>> {
+    let (kind, arg) = spec.split_once(':').unwrap_or((spec, ""));
+    let paths: Vec<&Path> = arg.split(',').map(Path::new).collect();
+    let given = |i: usize| paths.get(i).copied().filter(|p| !p.as_os_str().is_empty());
+    #[cfg(unix)]
+    let missing = |what: &str| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("{} needs {}", kind, what))
+    };
+
+    match kind {
+        "stdio" => Ok(Box::new(StdioDevice::new())),
+        "file" => Ok(Box::new(FileDevice::open(given(0), given(1))?)),
+        #[cfg(unix)]
+        "pipe" => {
+            let input = given(0).ok_or_else(|| missing("an input FIFO"))?;
+            let output = given(1).ok_or_else(|| missing("an output FIFO"))?;
+            Ok(Box::new(PipeDevice::open(input, output)?))
+        }
+        #[cfg(unix)]
+        "unix" => {
+            let path = given(0).ok_or_else(|| missing("a socket path"))?;
+            Ok(Box::new(UnixSocketDevice::connect(path)?))
+        }
+        #[cfg(unix)]
+        "pty" => Ok(Box::new(PtyDevice::open(given(0))?)),
+        #[cfg(unix)]
+        "tcp-listen" | "unix-listen" => {
+            let mut options = arg.split(',');
+            let addr = options.next().filter(|a| !a.is_empty());
+            let addr = addr.ok_or_else(|| missing("an address"))?;
+            let (mut wait, mut telnet) = (false, false);
+            for option in options {
+                match option {
+                    "wait" => wait = true,
+                    "telnet" => telnet = true,
+                    other => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("unknown {} option \"{}\"", kind, other),
+                        ))
+                    }
+                }
+            }
+            if kind == "unix-listen" {
+                return Ok(Box::new(SocketServerDevice::unix(Path::new(addr), wait, telnet)?));
+            }
+            let addr = if addr.contains(':') {
+                addr.to_string()
+            } else {
+                format!("127.0.0.1:{}", addr)
+            };
+            Ok(Box::new(SocketServerDevice::tcp(&addr, wait, telnet)?))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown serial device \"{}\"", spec),
+        )),
+    }
+}
+
+//=============================================================================
+// Helpers
+//=============================================================================
+
+/// Read one byte. End of file, a would-block on a non-blocking descriptor
+/// and errors all mean nothing is ready.
+fn read_byte<R: Read>(reader: &mut R) -> Option<u8> {
+    let mut buf = [0u8; 1];
+    match reader.read(&mut buf) {
+        Ok(1) => Some(buf[0]),
+        _ => None,
+    }
+}
+
+/// Read one byte from a descriptor, unbuffered
+#[cfg(unix)]
+fn read_fd(fd: RawFd) -> Option<u8> {
+    let mut buf = [0u8; 1];
+    let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, 1) };
+    (n == 1).then_some(buf[0])
+}
+
+/// Check if a descriptor has data (or end of file) waiting, without blocking
+#[cfg(unix)]
+fn readable(fd: RawFd) -> bool {
+    let mut pfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut pfd, 1, 0) > 0 }
+}
+
+/// Wait up to `timeout_ms` for a descriptor to accept output
+#[cfg(unix)]
+fn writable(fd: RawFd, timeout_ms: i32) -> bool {
+    let mut pfd = libc::pollfd {
+        fd,
+        events: libc::POLLOUT,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut pfd, 1, timeout_ms) > 0 }
+}
+
+#[cfg(unix)]
+fn set_nonblocking(fd: RawFd) -> io::Result<()> {
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Put a terminal in raw mode: no echo, line editing or CR/LF translation
+#[cfg(unix)]
+fn set_raw(fd: RawFd) -> io::Result<()> {
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        libc::cfmakeraw(&mut termios);
+        if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,447 @@
+//! Debugger console commands
+//!
+//! The command line of the TUI debugger, in the style of a monitor prompt.
+//! Every number is hex unless written `#decimal`; addresses and values are
+//! expressions (see `expr`) written without spaces:
+//!
+//! ```text
+//! b [ADDR [if COND] [after N]]    set a breakpoint, or list them
+//! d N|all   t N                   delete, or disable/enable, breakpoints
+//! w [[io] ADDR [LEN] [r|w|rw]]    set a watchpoint, or list them
+//! wd N|all  wt N                  delete, or disable/enable, watchpoints
+//! g [ADDR]                        run, or run until PC reaches ADDR
+//! s [N]  over  out  reset         step N instructions, over, out; reset
+//! line  next                      step a source line, into or over calls
+//! m [ADDR [LEN]]                  dump memory (continues the last dump)
+//! fill START END BYTE             fill START up to (not including) END
+//! copy START END DEST             copy START up to END to DEST
+//! load FILE ADDR                  load a file into memory
+//! save START END FILE             save START up to END to a file
+//! r [NAME=VALUE ...]              show or set registers, im, iff1, iff2
+//! dis [ADDR [COUNT]]              disassemble (continues the last listing)
+//! trace on [FILE] | off           log every instruction executed
+//! help
+//! ```
+//!
+//! Commands are parsed here and carried out by the debugger, which owns the
+//! breakpoints and the run state. With a listing loaded, the debugger turns
+//! `FILE:LINE` words into addresses first (see `listing`).
+
+use std::fs;
+use std::path::Path;
+
+use rz80::CPU;
+
+use crate::breakpoint;
+use crate::disasm::disassemble;
+use crate::expr::{self, Expr};
+use crate::symbols::Symbols;
+use crate::watchpoint::{self, Watch};
+
+/// Radix of plain numbers at the console
+pub const RADIX: u32 = 16;
+
+/// Bytes `m` dumps when no length is given
+pub const DUMP_LEN: u32 = 0x40;
+
+/// Instructions `dis` lists when no count is given
+pub const DIS_COUNT: u32 = 16;
+
+/// Command names, for completion
+pub const COMMANDS: [&str; 23] = [
+    "b", "d", "t", "w", "wd", "wt", "g", "s", "over", "out", "line", "next", "reset", "m", "fill", "copy", "load",
+    "save", "r", "dis", "trace", "help", "?",
+];
+
+/// One line per command, for `help`
+pub const HELP: [&str; 16] = [
+    "b [ADDR [if COND] [after N]]   set a breakpoint, or list them",
+    "d N|all  t N                   delete, disable/enable breakpoints",
+    "w [[io] ADDR [LEN] [r|w|rw]]   set a watchpoint, or list them",
+    "wd N|all  wt N                 delete, disable/enable watchpoints",
+    "g [ADDR]                       run, or run to ADDR",
+    "s [N]  over  out  reset        step N, step over, step out, reset",
+    "line  next                     step a source line, into or over calls",
+    "m [ADDR [LEN]]                 dump memory",
+    "fill START END BYTE            fill START up to END",
+    "copy START END DEST            copy START up to END to DEST",
+    "load FILE ADDR                 load a file into memory",
+    "save START END FILE            save START up to END to a file",
+    "r [NAME=VALUE ...]             show or set registers",
+    "dis [ADDR [COUNT]]             disassemble",
+    "trace on [FILE] | off          log every instruction executed",
+    "Numbers are hex (#10 is decimal); Tab completes, Up/Down recall",
+];
+
+/// Names `r` can set besides the registers
+const STATE: [&str; 3] = ["im", "iff1", "iff2"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Help,
+    /// Add a breakpoint, or list them (`None`)
+    Break(Option<(u16, Option<Expr>, u64)>),
+    /// Delete a breakpoint, or all of them (`None`)
+    Delete(Option<u32>),
+    Toggle(u32),
+    Watch(Option<Watch>),
+    WatchDelete(Option<u32>),
+    WatchToggle(u32),
+    Go(Option<u16>),
+    Step(u32),
+    Over,
+    Out,
+    /// Step a source line, into calls
+    Line,
+    /// Step a source line, over calls
+    Next,
+    Reset,
+    /// Dump memory; without an address, from where the last dump ended
+    Memory(Option<u16>, u32),
+    /// A range from `start` up to (not including) `end`, which may be $10000
+    Fill { start: u16, end: u32, byte: u8 },
+    Copy { start: u16, end: u32, dest: u16 },
+    Load { path: String, addr: u16 },
+    Save { start: u16, end: u32, path: String },
+    /// Set registers (and `im`, `iff1`, `iff2`), or show them if empty
+    Registers(Vec<(String, u16)>),
+    /// Disassemble; without an address, from where the last listing ended
+    Disassemble(Option<u16>, u32),
+    /// Trace to the console, or to a file
+    TraceOn(Option<String>),
+    TraceOff,
+}
+
+impl Command {
+    /// Parse a console line, where expressions may name `symbols`.
+    /// Returns `None` for a blank line.
+    pub fn parse(line: &str, cpu: &CPU, symbols: &Symbols) -> Result<Option<Command>, String> {
+        let line = line.trim();
+        let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        let args: Vec<&str> = rest.split_whitespace().collect();
+        let value = |word: &str| -> Result<i64, String> { Ok(Expr::parse_with(word, RADIX, symbols)?.eval(cpu)) };
+        let id = |word: &str| {
+            expr::parse_number(word, 10)
+                .and_then(|n| u32::try_from(n).ok())
+                .ok_or_else(|| format!("invalid number \"{}\"", word))
+        };
+
+        let command = match (name, &args[..]) {
+            ("", _) => return Ok(None),
+            ("help" | "?", []) => Command::Help,
+            ("b", []) => Command::Break(None),
+            ("b", _) => Command::Break(Some(breakpoint::parse_spec_with(rest, cpu, RADIX, symbols)?)),
+            ("d", ["all"]) => Command::Delete(None),
+            ("d", [n]) => Command::Delete(Some(id(n)?)),
+            ("t", [n]) => Command::Toggle(id(n)?),
+            ("w", []) => Command::Watch(None),
+            ("w", _) => Command::Watch(Some(watchpoint::parse_spec_with(rest, cpu, RADIX, symbols)?)),
+            ("wd", ["all"]) => Command::WatchDelete(None),
+            ("wd", [n]) => Command::WatchDelete(Some(id(n)?)),
+            ("wt", [n]) => Command::WatchToggle(id(n)?),
+            ("g", []) => Command::Go(None),
+            ("g", [addr]) => Command::Go(Some(address(value(addr)?)?)),
+            ("s", []) => Command::Step(1),
+            ("s", [n]) => Command::Step(count(value(n)?)?),
+            ("over", []) => Command::Over,
+            ("out", []) => Command::Out,
+            ("line", []) => Command::Line,
+            ("next", []) => Command::Next,
+            ("reset", []) => Command::Reset,
+            ("m", []) => Command::Memory(None, DUMP_LEN),
+            ("m", [addr]) => Command::Memory(Some(address(value(addr)?)?), DUMP_LEN),
+            ("m", [addr, len]) => Command::Memory(Some(address(value(addr)?)?), count(value(len)?)?),
+            ("fill", [start, end, byte]) => {
+                let (start, end) = range(value(start)?, value(end)?)?;
+                let byte = value(byte)?;
+                let byte = u8::try_from(byte).map_err(|_| format!("byte {} is out of range", byte))?;
+                Command::Fill { start, end, byte }
+            }
+            ("copy", [start, end, dest]) => {
+                let (start, end) = range(value(start)?, value(end)?)?;
+                let dest = address(value(dest)?)?;
+                if dest as u32 + (end - start as u32) > 0x10000 {
+                    return Err("copy runs past the top of memory".to_string());
+                }
+                Command::Copy { start, end, dest }
+            }
+            ("load", [path, addr]) => Command::Load { path: path.to_string(), addr: address(value(addr)?)? },
+            ("save", [start, end, path]) => {
+                let (start, end) = range(value(start)?, value(end)?)?;
+                Command::Save { start, end, path: path.to_string() }
+            }
+            ("r", assignments) => Command::Registers(
+                assignments
+                    .iter()
+                    .map(|a| {
+                        let (name, v) = a.split_once('=').ok_or_else(|| format!("expected NAME=VALUE, found \"{}\"", a))?;
+                        let name = name.to_ascii_lowercase();
+                        if !expr::REGISTERS.contains(&name.as_str()) && !STATE.contains(&name.as_str()) {
+                            return Err(format!("unknown register \"{}\"", name));
+                        }
+                        let v = value(v)?;
+                        let v = u16::try_from(v).map_err(|_| format!("value {} is out of range", v))?;
+                        Ok((name, v))
+                    })
+                    .collect::<Result<_, String>>()?,
+            ),
+            ("dis", []) => Command::Disassemble(None, DIS_COUNT),
+            ("dis", [addr]) => Command::Disassemble(Some(address(value(addr)?)?), DIS_COUNT),
+            ("dis", [addr, n]) => Command::Disassemble(Some(address(value(addr)?)?), count(value(n)?)?),
+            ("trace", ["on"]) => Command::TraceOn(None),
+            ("trace", ["on", path]) => Command::TraceOn(Some(path.to_string())),
+            ("trace", ["off"]) => Command::TraceOff,
+            _ if COMMANDS.contains(&name) => return Err(usage(name)),
+            _ => return Err(format!("unknown command \"{}\" (try help)", name)),
+        };
+        Ok(Some(command))
+    }
+}
+
+/// What a command takes, for errors
+fn usage(name: &str) -> String {
+    let usage = match name {
+        "d" => "d N|all",
+        "t" => "t N",
+        "wd" => "wd N|all",
+        "wt" => "wt N",
+        "g" => "g [ADDR]",
+        "s" => "s [N]",
+        "m" => "m [ADDR [LEN]]",
+        "fill" => "fill START END BYTE",
+        "copy" => "copy START END DEST",
+        "load" => "load FILE ADDR",
+        "save" => "save START END FILE",
+        "dis" => "dis [ADDR [COUNT]]",
+        "trace" => "trace on [FILE] | off",
+        _ => return format!("{} takes no arguments", name),
+    };
+    format!("usage: {}", usage)
+}
+
+fn address(value: i64) -> Result<u16, String> {
+    u16::try_from(value).map_err(|_| format!("address {} is out of range", value))
+}
+
+fn count(value: i64) -> Result<u32, String> {
+    u32::try_from(value).ok().filter(|&n| n > 0).ok_or_else(|| format!("count {} is out of range", value))
+}
+
+/// A range from START up to END, which may be the top of memory ($10000)
+fn range(start: i64, end: i64) -> Result<(u16, u32), String> {
+    let start = address(start)?;
+    if end <= start as i64 || end > 0x10000 {
+        return Err(format!("end ${:X} is not after start ${:04X}", end, start));
+    }
+    Ok((start, end as u32))
+}
+
+/// Set a register by name, or `im`, `iff1` or `iff2`
+pub fn set_register(cpu: &mut CPU, name: &str, value: u16) -> Result<(), String> {
+    match name.to_ascii_lowercase().as_str() {
+        "im" if value <= 2 => cpu.reg.im = value as i32,
+        "im" => return Err(format!("IM {} is not 0, 1 or 2", value)),
+        "iff1" => cpu.iff1 = value != 0,
+        "iff2" => cpu.iff2 = value != 0,
+        _ => expr::set_register(cpu, name, value)?,
+    }
+    Ok(())
+}
+
+/// The registers and interrupt state, as `r` shows them
+pub fn registers(cpu: &CPU) -> [String; 3] {
+    let reg = &cpu.reg;
+    [
+        format!(
+            "PC={:04X} SP={:04X} AF={:04X} BC={:04X} DE={:04X} HL={:04X} IX={:04X} IY={:04X}",
+            reg.pc(),
+            reg.sp(),
+            reg.af(),
+            reg.bc(),
+            reg.de(),
+            reg.hl(),
+            reg.ix(),
+            reg.iy()
+        ),
+        format!(
+            "AF'={:04X} BC'={:04X} DE'={:04X} HL'={:04X} I={:02X} R={:02X} IM={} IFF1={} IFF2={}",
+            reg.af_(),
+            reg.bc_(),
+            reg.de_(),
+            reg.hl_(),
+            reg.i,
+            reg.r,
+            reg.im,
+            cpu.iff1 as u8,
+            cpu.iff2 as u8
+        ),
+        format!("Flags: {}", flags(reg.f() as u8)),
+    ]
+}
+
+fn flags(f: u8) -> String {
+    "SZ-H-PNC"
+        .chars()
+        .enumerate()
+        .map(|(i, name)| if f & (0x80 >> i) != 0 { name } else { '-' })
+        .collect()
+}
+
+/// Hex and ASCII dump of `len` bytes from `start`, 16 to a line
+pub fn dump(cpu: &CPU, start: u16, len: u32) -> Vec<String> {
+    let end = (start as u32 + len).min(0x10000);
+    (start as u32..end)
+        .step_by(16)
+        .map(|line| {
+            let addrs = line..(line + 16).min(end);
+            let bytes: Vec<u8> = addrs.map(|a| cpu.mem.r8(a as i32) as u8).collect();
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let ascii: String = bytes.iter().map(|&b| if (0x20..0x7F).contains(&b) { b as char } else { '.' }).collect();
+            format!("{:04X}: {:<47}  {}", line, hex.join(" "), ascii)
+        })
+        .collect()
+}
+
+/// The instruction at an address as a listing line, with jump and call
+/// targets named by their labels, and its length
+pub fn listing_line(cpu: &CPU, addr: u16, symbols: &Symbols) -> (String, u16) {
+    let read = |a: u16| cpu.mem.r8(a as i32) as u8;
+    let ins = disassemble(read, addr);
+    let bytes: Vec<String> = (0..ins.len as u16).map(|i| format!("{:02X}", read(addr.wrapping_add(i)))).collect();
+    let text = match ins.target.and_then(|t| symbols.label(t)) {
+        Some(name) => ins.text_with_target(name),
+        None => ins.text.clone(),
+    };
+    (format!("{:04X}: {:<12}{}", addr, bytes.join(" "), text), ins.len as u16)
+}
+
+/// `count` instructions from an address, each preceded by its label if it
+/// has one, and the address after them
+pub fn disassembly(cpu: &CPU, addr: u16, count: u32, symbols: &Symbols) -> (Vec<String>, u16) {
+    let mut addr = addr;
+    let mut lines = Vec::new();
+    for _ in 0..count {
+        if let Some(name) = symbols.label(addr) {
+            lines.push(format!("{}:", name));
+        }
+        let (line, len) = listing_line(cpu, addr, symbols);
+        lines.push(line);
+        addr = addr.wrapping_add(len);
+    }
+    (lines, addr)
+}
+
+//=============================================================================
+// History and completion
+//=============================================================================
+
+/// Lines entered, recalled with Up and Down
+#[derive(Debug, Default)]
+pub struct History {
+    lines: Vec<String>,
+    /// Index of the line being recalled; `lines.len()` when none is
+    pos: usize,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remember a line (unless blank or the same as the last one) and stop
+    /// recalling
+    pub fn push(&mut self, line: &str) {
+        if !line.trim().is_empty() && self.lines.last().map(String::as_str) != Some(line) {
+            self.lines.push(line.to_string());
+        }
+        self.pos = self.lines.len();
+    }
+
+    /// The line before the one recalled, if any
+    pub fn older(&mut self) -> Option<&str> {
+        self.pos = self.pos.checked_sub(1)?;
+        Some(&self.lines[self.pos])
+    }
+
+    /// The line after the one recalled; past the newest, an empty line
+    pub fn newer(&mut self) -> Option<&str> {
+        if self.pos >= self.lines.len() {
+            return None;
+        }
+        self.pos += 1;
+        Some(self.lines.get(self.pos).map_or("", String::as_str))
+    }
+}
+
+/// The result of completing a line with Tab
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    /// The line, with the last word completed as far as it is unambiguous
+    pub line: String,
+    /// Every candidate, when more than one matched
+    pub candidates: Vec<String>,
+}
+
+/// Complete the last word of a line: a command name, a register for `r`,
+/// a file name for `load`, `save` and `trace on`, otherwise a symbol at
+/// the end of an expression
+pub fn complete(line: &str, symbols: &Symbols) -> Completion {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let new_word = line.is_empty() || line.ends_with(char::is_whitespace);
+    let index = if new_word { words.len() } else { words.len() - 1 };
+    let word = if new_word { "" } else { words[index] };
+    let head = &line[..line.len() - word.len()];
+
+    // Candidates for the word, each with what follows it once complete
+    let (prefix, candidates): (&str, Vec<String>) = match (words.first().copied(), index) {
+        (_, 0) => ("", COMMANDS.iter().map(|c| format!("{} ", c)).collect()),
+        (Some("r"), _) => {
+            let names = expr::REGISTERS.iter().chain(STATE.iter());
+            ("", names.map(|r| format!("{}=", r)).collect())
+        }
+        (Some("load"), 1) | (Some("save"), 3) | (Some("trace"), 2) => {
+            let dir_end = word.rfind('/').map_or(0, |i| i + 1);
+            (&word[..dir_end], files(&word[..dir_end]))
+        }
+        _ => {
+            let start = word.rfind(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '.'))).map_or(0, |i| i + 1);
+            (&word[..start], symbols.names().map(|name| format!("{} ", name)).collect())
+        }
+    };
+    let rest = &word[prefix.len()..];
+    let matches: Vec<String> = candidates.into_iter().filter(|c| c.starts_with(rest)).collect();
+
+    match &matches[..] {
+        [] => Completion { line: line.to_string(), candidates: Vec::new() },
+        [only] => Completion { line: format!("{}{}{}", head, prefix, only), candidates: Vec::new() },
+        _ => {
+            let common = matches.iter().skip(1).fold(matches[0].as_str(), |common, m| {
+                let len = common.chars().zip(m.chars()).take_while(|(a, b)| a == b).map(|(a, _)| a.len_utf8()).sum();
+                &common[..len]
+            });
+            let mut candidates: Vec<String> = matches.iter().map(|m| m.trim_end_matches([' ', '=']).to_string()).collect();
+            candidates.sort();
+            Completion { line: format!("{}{}{}", head, prefix, common), candidates }
+        }
+    }
+}
+
+/// Entries of a directory (the current one if `dir` is empty), with `/`
+/// after subdirectories and a space after files
+fn files(dir: &str) -> Vec<String> {
+    let path = if dir.is_empty() { Path::new(".") } else { Path::new(dir) };
+    let Ok(entries) = fs::read_dir(path) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            match entry.file_type().is_ok_and(|t| t.is_dir()) {
+                true => format!("{}/", name),
+                false => format!("{} ", name),
+            }
+        })
+        .collect()
+}
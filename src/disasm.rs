@@ -0,0 +1,422 @@
+//! Z80 disassembler
+//!
+//! Decodes every documented and undocumented instruction, including the
+//! CB, ED, DD/FD (IX/IY, IXH/IXL/IYH/IYL) and DDCB/FDCB prefixes. Opcodes
+//! are split into their x/y/z/p/q fields and looked up in the operand
+//! tables below, as laid out in "Decoding Z80 Opcodes" (C. Dinu).
+//!
+//! Conventions:
+//! - Numbers are hex with a `$` prefix; relative jumps show the target.
+//! - A DD/FD prefix that does not change the next instruction, or is
+//!   followed by another prefix, is shown on its own as `DB $DD`.
+//! - Undefined ED opcodes (two-byte NOPs on the Z80) are shown as
+//!   `DB $ED,$xx`.
+//! - Undocumented DDCB/FDCB forms that also copy the result to a register
+//!   are shown as `RLC (IX+$05),B`.
+
+/// 8-bit operands by the r field (6 is the memory operand)
+const R: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+/// 16-bit register pairs by the p field
+const RP: [&str; 4] = ["BC", "DE", "HL", "SP"];
+/// Register pairs for PUSH and POP
+const RP2: [&str; 4] = ["BC", "DE", "HL", "AF"];
+/// Conditions
+const CC: [&str; 8] = ["NZ", "Z", "NC", "C", "PO", "PE", "P", "M"];
+/// Arithmetic and logic operations
+const ALU: [&str; 8] = ["ADD A,", "ADC A,", "SUB ", "SBC A,", "AND ", "XOR ", "OR ", "CP "];
+/// CB-prefixed rotates and shifts
+const ROT: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SLL", "SRL"];
+/// Unprefixed x=0, z=7 operations
+const ACC: [&str; 8] = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+/// Interrupt modes by the y field (the undocumented ones repeat)
+const IM: [&str; 8] = ["0", "0", "1", "2", "0", "0", "1", "2"];
+/// Block instructions by y (4-7) and z (0-3)
+const BLOCK: [[&str; 4]; 4] = [
+    ["LDI", "CPI", "INI", "OUTI"],
+    ["LDD", "CPD", "IND", "OUTD"],
+    ["LDIR", "CPIR", "INIR", "OTIR"],
+    ["LDDR", "CPDR", "INDR", "OTDR"],
+];
+
+/// Longest instruction (DD CB d op, or DD 36 d n)
+pub const MAX_INSTRUCTION_LEN: u8 = 4;
+
+/// How an instruction affects the flow of control
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    /// Always continues with the next instruction
+    Next,
+    /// JP or JR: always goes to the target (unknown for `JP (HL)`)
+    Jump,
+    /// Conditional JP or JR, or DJNZ: the target or the next instruction
+    Branch,
+    /// CALL or conditional CALL: the target, returning to the next one
+    Call,
+    /// RST: a one-byte call to a fixed vector
+    Restart,
+    /// RET, RETI or RETN
+    Return,
+    /// Conditional RET: returns or continues with the next instruction
+    ConditionalReturn,
+}
+
+/// One decoded instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub addr: u16,
+    /// Length in bytes, 1 to `MAX_INSTRUCTION_LEN`
+    pub len: u8,
+    /// Assembler text, e.g. `LD (IX+$05),$2A`
+    pub text: String,
+    /// Address a jump, call or RST transfers to, when it is fixed
+    pub target: Option<u16>,
+    pub flow: Flow,
+}
+
+impl Instruction {
+    /// The text with the target address of a jump or call replaced by a
+    /// name, e.g. `CALL PRINT` for `CALL $0123`
+    pub fn text_with_target(&self, name: &str) -> String {
+        match (self.flow, self.target) {
+            (Flow::Jump | Flow::Branch | Flow::Call, Some(target)) => {
+                self.text.replace(&format!("${:04X}", target), name)
+            }
+            _ => self.text.clone(),
+        }
+    }
+}
+
+/// Decode the instruction at `addr`, reading memory through `read`
+pub fn disassemble(read: impl Fn(u16) -> u8, addr: u16) -> Instruction {
+    let mut decoder = Decoder {
+        read: &read,
+        addr,
+        len: 0,
+        index: None,
+        index_used: false,
+        target: None,
+        flow: Flow::Next,
+    };
+    let text = decoder.decode();
+    Instruction {
+        addr,
+        len: decoder.len,
+        text,
+        target: decoder.target,
+        flow: decoder.flow,
+    }
+}
+
+/// Decode the instruction at the start of `bytes`, which is at `addr`.
+/// Bytes past the end of the slice read as zero.
+pub fn disassemble_bytes(bytes: &[u8], addr: u16) -> Instruction {
+    disassemble(|a| bytes.get(a.wrapping_sub(addr) as usize).copied().unwrap_or(0), addr)
+}
+
+/// Find where to start disassembling so that up to `count` instructions
+/// are shown before `addr` and the listing lands exactly on it. Z80 code
+/// cannot be decoded backwards, so this tries each earlier start address
+/// and keeps the furthest one that falls in step with `addr`.
+pub fn back_up(read: impl Fn(u16) -> u8, addr: u16, count: usize) -> u16 {
+    let span = (count * MAX_INSTRUCTION_LEN as usize) as u16;
+    for back in (1..=span).rev() {
+        let mut starts = Vec::new();
+        let mut offset = 0;
+        while offset < back {
+            starts.push(addr.wrapping_sub(back - offset));
+            offset += disassemble(&read, addr.wrapping_sub(back - offset)).len as u16;
+        }
+        if offset == back {
+            return starts[starts.len().saturating_sub(count)];
+        }
+    }
+    addr
+}
+
+//=============================================================================
+// Decoder
+//=============================================================================
+
+struct Decoder<'a> {
+    read: &'a dyn Fn(u16) -> u8,
+    addr: u16,
+    /// Bytes consumed so far
+    len: u8,
+    /// "IX" or "IY" after a DD or FD prefix
+    index: Option<&'static str>,
+    /// Whether the prefix changed the instruction
+    index_used: bool,
+    target: Option<u16>,
+    flow: Flow,
+}
+
+impl Decoder<'_> {
+    fn peek(&self) -> u8 {
+        (self.read)(self.addr.wrapping_add(self.len as u16))
+    }
+
+    fn byte(&mut self) -> u8 {
+        let b = self.peek();
+        self.len += 1;
+        b
+    }
+
+    fn imm8(&mut self) -> String {
+        format!("${:02X}", self.byte())
+    }
+
+    fn imm16(&mut self) -> String {
+        format!("${:04X}", self.word())
+    }
+
+    fn word(&mut self) -> u16 {
+        let lo = self.byte() as u16;
+        let hi = self.byte() as u16;
+        hi << 8 | lo
+    }
+
+    /// Absolute address (for JP and CALL), remembered as the target
+    fn address(&mut self, flow: Flow) -> String {
+        let target = self.word();
+        self.target = Some(target);
+        self.flow = flow;
+        format!("${:04X}", target)
+    }
+
+    /// Relative jump displacement, shown as the target address
+    fn relative(&mut self, flow: Flow) -> String {
+        let d = self.byte() as i8;
+        let target = self.addr.wrapping_add(self.len as u16).wrapping_add(d as u16);
+        self.target = Some(target);
+        self.flow = flow;
+        format!("${:04X}", target)
+    }
+
+    /// `(IX+d)` from a displacement byte
+    fn indexed(&mut self, index: &str) -> String {
+        let d = self.byte() as i8;
+        self.index_used = true;
+        if d < 0 {
+            format!("({}-${:02X})", index, d.unsigned_abs())
+        } else {
+            format!("({}+${:02X})", index, d)
+        }
+    }
+
+    /// 8-bit operand. Under a prefix, H and L become the index halves
+    /// unless the instruction also has a memory operand.
+    fn r(&mut self, i: u8, memory_operand: bool) -> String {
+        match (i, self.index) {
+            (6, Some(index)) => self.indexed(index),
+            (4 | 5, Some(index)) if !memory_operand => {
+                self.index_used = true;
+                format!("{}{}", index, if i == 4 { 'H' } else { 'L' })
+            }
+            _ => R[i as usize].to_string(),
+        }
+    }
+
+    /// HL, or the index register under a prefix
+    fn hl(&mut self) -> &'static str {
+        match self.index {
+            Some(index) => {
+                self.index_used = true;
+                index
+            }
+            None => "HL",
+        }
+    }
+
+    fn rp(&mut self, p: u8) -> &'static str {
+        if p == 2 { self.hl() } else { RP[p as usize] }
+    }
+
+    fn rp2(&mut self, p: u8) -> &'static str {
+        if p == 2 { self.hl() } else { RP2[p as usize] }
+    }
+
+    fn decode(&mut self) -> String {
+        let op = self.byte();
+        match op {
+            0xCB => self.cb(),
+            0xED => self.ed(),
+            0xDD | 0xFD => {
+                let prefix = op;
+                // A prefix before another prefix only costs time
+                if matches!(self.peek(), 0xDD | 0xED | 0xFD) {
+                    return format!("DB ${:02X}", prefix);
+                }
+                let index = if prefix == 0xDD { "IX" } else { "IY" };
+                self.index = Some(index);
+                if self.peek() == 0xCB {
+                    self.byte();
+                    return self.index_cb(index);
+                }
+                let op = self.byte();
+                let text = self.unprefixed(op);
+                if self.index_used {
+                    text
+                } else {
+                    // The prefix has no effect: show it alone and decode
+                    // the instruction after it separately
+                    self.len = 1;
+                    self.target = None;
+                    self.flow = Flow::Next;
+                    format!("DB ${:02X}", prefix)
+                }
+            }
+            _ => self.unprefixed(op),
+        }
+    }
+
+    fn unprefixed(&mut self, op: u8) -> String {
+        let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+        let (p, q) = (y >> 1, y & 1);
+
+        match (x, z) {
+            (0, 0) => match y {
+                0 => "NOP".to_string(),
+                1 => "EX AF,AF'".to_string(),
+                2 => format!("DJNZ {}", self.relative(Flow::Branch)),
+                3 => format!("JR {}", self.relative(Flow::Jump)),
+                _ => format!("JR {},{}", CC[(y - 4) as usize], self.relative(Flow::Branch)),
+            },
+            (0, 1) if q == 0 => {
+                let rp = self.rp(p);
+                format!("LD {},{}", rp, self.imm16())
+            }
+            (0, 1) => {
+                let hl = self.hl();
+                format!("ADD {},{}", hl, self.rp(p))
+            }
+            (0, 2) => match (p, q) {
+                (0, 0) => "LD (BC),A".to_string(),
+                (1, 0) => "LD (DE),A".to_string(),
+                (2, 0) => {
+                    let hl = self.hl();
+                    format!("LD ({}),{}", self.imm16(), hl)
+                }
+                (3, 0) => format!("LD ({}),A", self.imm16()),
+                (0, _) => "LD A,(BC)".to_string(),
+                (1, _) => "LD A,(DE)".to_string(),
+                (2, _) => {
+                    let hl = self.hl();
+                    format!("LD {},({})", hl, self.imm16())
+                }
+                _ => format!("LD A,({})", self.imm16()),
+            },
+            (0, 3) => format!("{} {}", if q == 0 { "INC" } else { "DEC" }, self.rp(p)),
+            (0, 4) => format!("INC {}", self.r(y, false)),
+            (0, 5) => format!("DEC {}", self.r(y, false)),
+            (0, 6) => {
+                let r = self.r(y, false);
+                format!("LD {},{}", r, self.imm8())
+            }
+            (0, _) => ACC[y as usize].to_string(),
+
+            (1, _) if y == 6 && z == 6 => "HALT".to_string(),
+            (1, _) => {
+                let memory = y == 6 || z == 6;
+                let dst = self.r(y, memory);
+                format!("LD {},{}", dst, self.r(z, memory))
+            }
+
+            (2, _) => format!("{}{}", ALU[y as usize], self.r(z, false)),
+
+            (3, 0) => {
+                self.flow = Flow::ConditionalReturn;
+                format!("RET {}", CC[y as usize])
+            }
+            (3, 1) if q == 0 => format!("POP {}", self.rp2(p)),
+            (3, 1) => match p {
+                0 => {
+                    self.flow = Flow::Return;
+                    "RET".to_string()
+                }
+                1 => "EXX".to_string(),
+                2 => {
+                    self.flow = Flow::Jump;
+                    format!("JP ({})", self.hl())
+                }
+                _ => format!("LD SP,{}", self.hl()),
+            },
+            (3, 2) => format!("JP {},{}", CC[y as usize], self.address(Flow::Branch)),
+            (3, 3) => match y {
+                0 => format!("JP {}", self.address(Flow::Jump)),
+                // 1 is the CB prefix, handled before we get here
+                2 => format!("OUT ({}),A", self.imm8()),
+                3 => format!("IN A,({})", self.imm8()),
+                4 => format!("EX (SP),{}", self.hl()),
+                5 => "EX DE,HL".to_string(),
+                6 => "DI".to_string(),
+                _ => "EI".to_string(),
+            },
+            (3, 4) => format!("CALL {},{}", CC[y as usize], self.address(Flow::Call)),
+            (3, 5) if q == 0 => format!("PUSH {}", self.rp2(p)),
+            // The other p values are the DD, ED and FD prefixes
+            (3, 5) => format!("CALL {}", self.address(Flow::Call)),
+            (3, 6) => format!("{}{}", ALU[y as usize], self.imm8()),
+            _ => {
+                self.target = Some(y as u16 * 8);
+                self.flow = Flow::Restart;
+                format!("RST ${:02X}", y * 8)
+            }
+        }
+    }
+
+    fn cb(&mut self) -> String {
+        let op = self.byte();
+        let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+        let r = R[z as usize];
+        match x {
+            0 => format!("{} {}", ROT[y as usize], r),
+            1 => format!("BIT {},{}", y, r),
+            2 => format!("RES {},{}", y, r),
+            _ => format!("SET {},{}", y, r),
+        }
+    }
+
+    /// DDCB/FDCB: the displacement comes before the opcode. Register forms
+    /// other than BIT also copy the result into the register.
+    fn index_cb(&mut self, index: &str) -> String {
+        let mem = self.indexed(index);
+        let op = self.byte();
+        let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+        let op = match x {
+            0 => format!("{} {}", ROT[y as usize], mem),
+            1 => return format!("BIT {},{}", y, mem),
+            2 => format!("RES {},{}", y, mem),
+            _ => format!("SET {},{}", y, mem),
+        };
+        if z == 6 {
+            op
+        } else {
+            format!("{},{}", op, R[z as usize])
+        }
+    }
+
+    fn ed(&mut self) -> String {
+        let op = self.byte();
+        let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+        let (p, q) = (y >> 1, y & 1);
+
+        match (x, z) {
+            (1, 0) if y == 6 => "IN (C)".to_string(),
+            (1, 0) => format!("IN {},(C)", R[y as usize]),
+            (1, 1) if y == 6 => "OUT (C),0".to_string(),
+            (1, 1) => format!("OUT (C),{}", R[y as usize]),
+            (1, 2) => format!("{} HL,{}", if q == 0 { "SBC" } else { "ADC" }, RP[p as usize]),
+            (1, 3) if q == 0 => format!("LD ({}),{}", self.imm16(), RP[p as usize]),
+            (1, 3) => format!("LD {},({})", RP[p as usize], self.imm16()),
+            (1, 4) => "NEG".to_string(),
+            (1, 5) => {
+                self.flow = Flow::Return;
+                if y == 1 { "RETI" } else { "RETN" }.to_string()
+            }
+            (1, 6) => format!("IM {}", IM[y as usize]),
+            (1, 7) if y < 6 => ["LD I,A", "LD R,A", "LD A,I", "LD A,R", "RRD", "RLD"][y as usize].to_string(),
+            (2, 0..=3) if y >= 4 => BLOCK[(y - 4) as usize][z as usize].to_string(),
+            _ => format!("DB $ED,${:02X}", op),
+        }
+    }
+}
@@ -0,0 +1,430 @@
+//! RetroShield Z80 Emulator - ROM disassembler
+//!
+//! Disassembles a ROM image to assembly source that reassembles to the
+//! same bytes. Code is found by tracing every path from the reset address
+//! and the RST and NMI vectors, and from the IM 2 handlers of a table the
+//! code points I at; whatever is never reached is data, shown as strings
+//! where it is printable. Jump and call targets get labels,
+//! named from the assembler's symbol file when there is one.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use retro_z80_emulator::disasm::{disassemble, Flow, Instruction};
+use retro_z80_emulator::profile;
+use retro_z80_emulator::symbols::{self, Symbols};
+
+/// RST vectors, plus the NMI entry at $0066
+const VECTORS: [u16; 9] = [0x00, 0x08, 0x10, 0x18, 0x20, 0x28, 0x30, 0x38, 0x66];
+
+/// Shortest printable run shown as a string
+const MIN_STRING: usize = 4;
+
+/// Widest data line, in characters of operands
+const DATA_WIDTH: usize = 48;
+
+/// Column the address comments start at
+const COMMENT_COLUMN: usize = 40;
+
+/// What tracing found at each byte of the image
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Data,
+    /// First byte of an instruction
+    Code,
+    /// Operand byte of an instruction
+    Operand,
+}
+
+struct Rom {
+    data: Vec<u8>,
+    base: u16,
+}
+
+impl Rom {
+    fn contains(&self, addr: u16) -> bool {
+        (addr.wrapping_sub(self.base) as usize) < self.data.len()
+    }
+
+    fn offset(&self, addr: u16) -> usize {
+        addr.wrapping_sub(self.base) as usize
+    }
+
+    fn read(&self, addr: u16) -> u8 {
+        self.data.get(self.offset(addr)).copied().unwrap_or(0)
+    }
+
+    fn decode(&self, addr: u16) -> Instruction {
+        disassemble(|a| self.read(a), addr)
+    }
+
+    fn end(&self) -> u32 {
+        self.base as u32 + self.data.len() as u32
+    }
+}
+
+//=============================================================================
+// Tracing
+//=============================================================================
+
+/// Follow every path from the entry points, marking code. `LD A,n`
+/// followed by `LD I,A` sets up an IM 2 table, whose entries for the
+/// interrupt `vectors` are traced as handlers. Returns the byte kinds and
+/// every jump or call target and handler.
+fn trace(rom: &Rom, entries: &[u16], vectors: &[u8]) -> (Vec<Kind>, BTreeSet<u16>) {
+    let mut kinds = vec![Kind::Data; rom.data.len()];
+    let mut targets = BTreeSet::new();
+    let mut work: Vec<u16> = entries.to_vec();
+
+    while let Some(mut addr) = work.pop() {
+        let mut loaded_a: Option<u8> = None;
+        loop {
+            if !rom.contains(addr) || kinds[rom.offset(addr)] == Kind::Code {
+                break;
+            }
+            let ins = rom.decode(addr);
+            // Stop where the instruction would run off the image or into
+            // code found by another path
+            let fits = (0..ins.len as u16).all(|i| {
+                let a = addr.wrapping_add(i);
+                rom.contains(a) && kinds[rom.offset(a)] == Kind::Data
+            });
+            if !fits {
+                break;
+            }
+            kinds[rom.offset(addr)] = Kind::Code;
+            for i in 1..ins.len as u16 {
+                kinds[rom.offset(addr.wrapping_add(i))] = Kind::Operand;
+            }
+
+            if let Some(target) = ins.target {
+                if matches!(ins.flow, Flow::Jump | Flow::Branch | Flow::Call | Flow::Restart) {
+                    targets.insert(target);
+                    work.push(target);
+                }
+            }
+            // The handlers of an IM 2 table the image holds
+            match (rom.read(addr), rom.read(addr.wrapping_add(1))) {
+                (0xED, 0x47) => {
+                    if let Some(page) = loaded_a {
+                        for &vector in vectors {
+                            let entry = u16::from_le_bytes([vector, page]);
+                            if rom.contains(entry) && rom.contains(entry.wrapping_add(1)) {
+                                let handler = u16::from_le_bytes([rom.read(entry), rom.read(entry.wrapping_add(1))]);
+                                targets.insert(handler);
+                                work.push(handler);
+                            }
+                        }
+                    }
+                    loaded_a = None;
+                }
+                (0x3E, n) => loaded_a = Some(n),
+                _ => loaded_a = None,
+            }
+            match ins.flow {
+                Flow::Jump | Flow::Return => break,
+                _ => addr = addr.wrapping_add(ins.len as u16),
+            }
+        }
+    }
+    (kinds, targets)
+}
+
+//=============================================================================
+// Output
+//=============================================================================
+
+struct Listing<'a> {
+    rom: &'a Rom,
+    kinds: Vec<Kind>,
+    /// Names for targets inside the image
+    labels: BTreeMap<u16, String>,
+    out: Vec<String>,
+}
+
+impl Listing<'_> {
+    fn line(&mut self, body: String, addr: u16, bytes: &[u8]) {
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let pad = COMMENT_COLUMN.saturating_sub(body.len()).max(1);
+        self.out.push(format!("{}{:pad$}; {:04X}  {}", body, "", addr, hex.join(" "), pad = pad));
+    }
+
+    fn is_start(&self, addr: u16) -> bool {
+        self.kinds[self.rom.offset(addr)] != Kind::Operand
+    }
+
+    fn write(&mut self) {
+        // Targets that land inside an instruction cannot be placed as
+        // labels, so they are defined by value up front
+        let inside: Vec<(u16, String)> = self
+            .labels
+            .iter()
+            .filter(|(addr, _)| !self.is_start(**addr))
+            .map(|(addr, name)| (*addr, name.clone()))
+            .collect();
+        for (addr, name) in inside {
+            self.out.push(format!("{:<7} EQU     ${:04X}", name, addr));
+        }
+        self.out.push(String::new());
+        self.out.push(format!("        ORG     ${:04X}", self.rom.base));
+
+        let mut addr = self.rom.base as u32;
+        while addr < self.rom.end() {
+            let a = addr as u16;
+            if let Some(name) = self.labels.get(&a).filter(|_| self.is_start(a)).cloned() {
+                self.out.push(String::new());
+                self.out.push(format!("{}:", name));
+            }
+            let len = match self.kinds[self.rom.offset(a)] {
+                Kind::Code => self.instruction(a),
+                _ => self.data(a),
+            };
+            addr += len as u32;
+        }
+    }
+
+    fn instruction(&mut self, addr: u16) -> usize {
+        let ins = self.rom.decode(addr);
+        let text = match ins.target.and_then(|t| self.labels.get(&t)) {
+            Some(name) if ins.flow != Flow::Restart => ins.text_with_target(name),
+            _ => ins.text.clone(),
+        };
+        let body = match text.split_once(' ') {
+            Some((op, args)) => format!("        {:<8}{}", op, args),
+            None => format!("        {}", text),
+        };
+        let bytes: Vec<u8> = (0..ins.len as u16).map(|i| self.rom.read(addr.wrapping_add(i))).collect();
+        self.line(body, addr, &bytes);
+        ins.len as usize
+    }
+
+    /// One line of data, ending at the next code, label or full line.
+    /// Printable runs become strings, and a zero after one ends the line.
+    fn data(&mut self, addr: u16) -> usize {
+        let mut items: Vec<String> = Vec::new();
+        let mut width = 0;
+        let mut len = 0;
+
+        loop {
+            let a = addr.wrapping_add(len as u16);
+            let stop = len > 0
+                && (!self.rom.contains(a)
+                    || a == self.rom.base
+                    || self.kinds[self.rom.offset(a)] != Kind::Data
+                    || self.labels.contains_key(&a));
+            if stop || width >= DATA_WIDTH {
+                break;
+            }
+
+            let run = (0..)
+                .take_while(|&i| {
+                    let b = a.wrapping_add(i as u16);
+                    (i == 0 || (b != self.rom.base && !self.labels.contains_key(&b)))
+                        && self.rom.contains(b)
+                        && self.kinds[self.rom.offset(b)] == Kind::Data
+                        && printable(self.rom.read(b))
+                })
+                .count();
+            if run >= MIN_STRING {
+                let run = run.min(DATA_WIDTH);
+                let text: String = (0..run).map(|i| self.rom.read(a.wrapping_add(i as u16)) as char).collect();
+                width += text.len() + 3;
+                items.push(format!("\"{}\"", text));
+                len += run;
+            } else {
+                let b = self.rom.read(a);
+                let after_string = items.last().is_some_and(|item| item.starts_with('"'));
+                width += 4;
+                items.push(format!("${:02X}", b));
+                len += 1;
+                if b == 0 && after_string {
+                    break;
+                }
+            }
+        }
+
+        let bytes: Vec<u8> = (0..len).map(|i| self.rom.read(addr.wrapping_add(i as u16))).collect();
+        let body = format!("        {:<8}{}", "DB", items.join(","));
+        // Long data lines list only their first bytes
+        self.line(body, addr, &bytes[..bytes.len().min(4)]);
+        len
+    }
+}
+
+/// Characters that can go inside a quoted string
+fn printable(b: u8) -> bool {
+    (0x20..=0x7E).contains(&b) && b != b'"' && b != b'\\'
+}
+
+//=============================================================================
+// Main
+//=============================================================================
+
+fn parse_addr(s: &str) -> Option<u16> {
+    if let Some(hex) = s.strip_prefix('$').or_else(|| s.strip_prefix("0x")).or_else(|| s.strip_prefix("0X")) {
+        u16::from_str_radix(hex, 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+fn print_usage(program: &str) {
+    eprintln!("Usage: {} [-m machine.toml] [-l addr] [-e addr]... [-n] [-y file]... [-o out.asm] [rom.bin]", program);
+    eprintln!("  -m file     Machine profile (default: <rom>.toml next to the ROM, if present)");
+    eprintln!("  -l addr     Load address (default: the profile's rom.load)");
+    eprintln!("  -e addr     Extra entry point to trace code from (repeatable)");
+    eprintln!("  -n          Do not trace from the RST and NMI vectors, only the reset address");
+    eprintln!("  -y file     Symbol file naming the labels (repeatable; default: <rom>.sym, .map");
+    eprintln!("              or .lst next to the ROM, if present)");
+    eprintln!("  -o file     Write the listing to a file (default: stdout)");
+    eprintln!();
+    eprintln!("Addresses are decimal, $hex or 0xhex.");
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    let mut rom_file: Option<String> = None;
+    let mut machine_file: Option<String> = None;
+    let mut load: Option<u16> = None;
+    let mut entries: Vec<u16> = Vec::new();
+    let mut vectors = true;
+    let mut output: Option<String> = None;
+    let mut symbol_files: Vec<PathBuf> = Vec::new();
+
+    let address = |arg: Option<&String>| -> u16 {
+        match arg.and_then(|s| parse_addr(s)) {
+            Some(addr) => addr,
+            None => {
+                eprintln!("Error: invalid address {}", arg.map(String::as_str).unwrap_or("(missing)"));
+                process::exit(1);
+            }
+        }
+    };
+
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-m" | "--machine" => {
+                i += 1;
+                machine_file = args.get(i).cloned();
+            }
+            "-l" | "--load" => {
+                i += 1;
+                load = Some(address(args.get(i)));
+            }
+            "-e" | "--entry" => {
+                i += 1;
+                entries.push(address(args.get(i)));
+            }
+            "-n" | "--no-vectors" => vectors = false,
+            "-y" | "--symbols" => {
+                i += 1;
+                symbol_files.extend(args.get(i).map(PathBuf::from));
+            }
+            "-o" | "--output" => {
+                i += 1;
+                output = args.get(i).cloned();
+            }
+            "-h" | "--help" => {
+                print_usage(&args[0]);
+                return;
+            }
+            arg if !arg.starts_with('-') => rom_file = Some(arg.to_string()),
+            _ => {}
+        }
+        i += 1;
+    }
+
+    if rom_file.is_none() && machine_file.is_none() {
+        print_usage(&args[0]);
+        process::exit(1);
+    }
+
+    let (profile, rom_path) = match profile::resolve(machine_file.as_deref(), rom_file.as_deref()) {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+    let data = match fs::read(&rom_path) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Error: {}: {}", rom_path.display(), e);
+            process::exit(1);
+        }
+    };
+    let base = load.unwrap_or(profile.rom_load);
+    // Images that also fill RAM are cut at the end of the ROM region,
+    // unless something other than zeros follows it
+    let used = data.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    let size = data.len().min(used.max(profile.rom_size as usize)).min(0x10000 - base as usize);
+    let rom = Rom { data: data[..size].to_vec(), base };
+
+    let given = !symbol_files.is_empty();
+    if !given {
+        symbol_files.extend(symbols::sibling(&rom_path));
+    }
+    let mut symbols = Symbols::new();
+    for path in &symbol_files {
+        if let Err(e) = symbols.load(path) {
+            // A listing next to the ROM need not have a symbol table
+            if given {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    entries.insert(0, profile.reset_pc);
+    if vectors {
+        entries.extend(VECTORS.iter().filter(|&&v| rom.contains(v)));
+    }
+    let mut seen = BTreeSet::new();
+    entries.retain(|e| seen.insert(*e));
+    let mut im2_vectors = vec![profile.acia_vector, profile.usart_vector];
+    im2_vectors.dedup();
+    let (kinds, targets) = trace(&rom, &entries, &im2_vectors);
+
+    // Every label in the image, plus the targets without one
+    let mut labels: BTreeMap<u16, String> = symbols
+        .labels()
+        .filter(|&(addr, _)| rom.contains(addr))
+        .map(|(addr, name)| (addr, name.to_string()))
+        .collect();
+    for &t in targets.iter().chain(entries.iter()).filter(|&&t| rom.contains(t)) {
+        labels.entry(t).or_insert_with(|| format!("L{:04X}", t));
+    }
+    let code_bytes = kinds.iter().filter(|&&k| k != Kind::Data).count();
+
+    let mut listing = Listing { rom: &rom, kinds, labels, out: Vec::new() };
+    listing.out.push(format!(
+        "; {}: {} bytes at ${:04X}, {} traced as code",
+        Path::new(&rom_path).file_name().unwrap_or_default().to_string_lossy(),
+        rom.data.len(),
+        rom.base,
+        code_bytes
+    ));
+    listing.out.push(format!(
+        "; Entry points: {}",
+        entries.iter().map(|e| format!("${:04X}", e)).collect::<Vec<_>>().join(" ")
+    ));
+    listing.write();
+    listing.out.push(String::new());
+    listing.out.push("        END".to_string());
+
+    let text = listing.out.join("\n") + "\n";
+    let result = match &output {
+        Some(file) => fs::write(file, text),
+        None => io::stdout().write_all(text.as_bytes()),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}: {}", output.as_deref().unwrap_or("stdout"), e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,453 @@
+//! Debugger expressions
+//!
+//! Small C-like expressions over registers and memory, used for
+//! breakpoint conditions such as `HL==$2000 && A>10`:
+//!
+//! ```text
+//! 42  $2A  0x2A  #42         numbers (decimal, hex, or decimal forced)
+//! a f b c d e h l i r         8-bit registers, any case
+//! af bc de hl ix iy sp pc     16-bit registers
+//! ixh ixl iyh iyl             index register halves
+//! af' bc' de' hl'             shadow registers
+//! [addr]  w[addr]             byte or little-endian word in memory
+//! - ! ~                       unary minus, logical and bitwise not
+//! * / %  + -  << >>           arithmetic and shifts
+//! < <= > >=  == !=            comparisons (1 or 0)
+//! & ^ |  && ||                bitwise and logical operators
+//! ```
+//!
+//! Precedence follows C. Arithmetic is done on 64-bit signed values;
+//! division by zero gives 0. Plain numbers are decimal unless parsed with
+//! `parse_radix(text, 16)`, as the TUI console does; `$`, `0x` and `#`
+//! always mean hex, hex and decimal.
+//!
+//! Expressions parsed with `parse_with` may also name symbols (see
+//! `symbols`), which stand for their values. A name is a register first,
+//! then a symbol, then (in hex) a number: `ff00` is a number unless a
+//! symbol is called that, and `bc` is always the register (write `0bc` or
+//! `$bc` for the number).
+
+use std::fmt;
+
+use rz80::CPU;
+
+use crate::symbols::Symbols;
+
+/// Register names `register` and `set_register` know
+pub const REGISTERS: [&str; 26] = [
+    "a", "f", "b", "c", "d", "e", "h", "l", "i", "r", "af", "bc", "de", "hl", "ix", "iy", "sp", "pc", "ixh", "ixl",
+    "iyh", "iyl", "af'", "bc'", "de'", "hl'",
+];
+
+/// Read a register by name (any case), including `i`, `r`, the index
+/// register halves and the shadow registers (`af'` etc.)
+pub fn register(cpu: &CPU, name: &str) -> Option<u16> {
+    let reg = &cpu.reg;
+    let value = match name.to_ascii_lowercase().as_str() {
+        "a" => reg.a(),
+        "f" => reg.f(),
+        "b" => reg.b(),
+        "c" => reg.c(),
+        "d" => reg.d(),
+        "e" => reg.e(),
+        "h" => reg.h(),
+        "l" => reg.l(),
+        "i" => reg.i,
+        "r" => reg.r,
+        "af" => reg.af(),
+        "bc" => reg.bc(),
+        "de" => reg.de(),
+        "hl" => reg.hl(),
+        "ix" => reg.ix(),
+        "iy" => reg.iy(),
+        "sp" => reg.sp(),
+        "pc" => reg.pc(),
+        "ixh" => reg.ix() >> 8,
+        "ixl" => reg.ix() & 0xFF,
+        "iyh" => reg.iy() >> 8,
+        "iyl" => reg.iy() & 0xFF,
+        "af'" => reg.af_(),
+        "bc'" => reg.bc_(),
+        "de'" => reg.de_(),
+        "hl'" => reg.hl_(),
+        _ => return None,
+    };
+    Some(value as u16)
+}
+
+/// Write a register by name (any case), taking the same names as
+/// `register`. 8-bit registers reject values above $FF.
+pub fn set_register(cpu: &mut CPU, name: &str, value: u16) -> Result<(), String> {
+    let name = name.to_ascii_lowercase();
+    let wide = matches!(
+        name.as_str(),
+        "af" | "bc" | "de" | "hl" | "ix" | "iy" | "sp" | "pc" | "af'" | "bc'" | "de'" | "hl'"
+    );
+    if !REGISTERS.contains(&name.as_str()) {
+        return Err(format!("unknown register \"{}\"", name));
+    }
+    if !wide && value > 0xFF {
+        return Err(format!("${:X} does not fit in {}", value, name.to_ascii_uppercase()));
+    }
+
+    let reg = &mut cpu.reg;
+    let v = value as i32;
+    match name.as_str() {
+        "a" => reg.set_a(v),
+        "f" => reg.set_f(v),
+        "b" => reg.set_b(v),
+        "c" => reg.set_c(v),
+        "d" => reg.set_d(v),
+        "e" => reg.set_e(v),
+        "h" => reg.set_h(v),
+        "l" => reg.set_l(v),
+        "i" => reg.i = v,
+        "r" => reg.r = v,
+        "af" => reg.set_af(v),
+        "bc" => reg.set_bc(v),
+        "de" => reg.set_de(v),
+        "hl" => reg.set_hl(v),
+        "ix" => reg.set_ix(v),
+        "iy" => reg.set_iy(v),
+        "sp" => reg.set_sp(v),
+        "pc" => reg.set_pc(v),
+        "ixh" => reg.set_ix(reg.ix() & 0x00FF | v << 8),
+        "ixl" => reg.set_ix(reg.ix() & 0xFF00 | v),
+        "iyh" => reg.set_iy(reg.iy() & 0x00FF | v << 8),
+        "iyl" => reg.set_iy(reg.iy() & 0xFF00 | v),
+        "af'" => reg.set_af_(v),
+        "bc'" => reg.set_bc_(v),
+        "de'" => reg.set_de_(v),
+        "hl'" => reg.set_hl_(v),
+        _ => unreachable!("checked against REGISTERS"),
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Unary {
+    Neg,
+    Not,
+    Complement,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Binary {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl Binary {
+    /// The operator for a token, with its precedence (higher binds tighter)
+    fn from_token(token: &str) -> Option<(Binary, u8)> {
+        Some(match token {
+            "||" => (Binary::Or, 1),
+            "&&" => (Binary::And, 2),
+            "|" => (Binary::BitOr, 3),
+            "^" => (Binary::BitXor, 4),
+            "&" => (Binary::BitAnd, 5),
+            "==" => (Binary::Eq, 6),
+            "!=" => (Binary::Ne, 6),
+            "<" => (Binary::Lt, 7),
+            "<=" => (Binary::Le, 7),
+            ">" => (Binary::Gt, 7),
+            ">=" => (Binary::Ge, 7),
+            "<<" => (Binary::Shl, 8),
+            ">>" => (Binary::Shr, 8),
+            "+" => (Binary::Add, 9),
+            "-" => (Binary::Sub, 9),
+            "*" => (Binary::Mul, 10),
+            "/" => (Binary::Div, 10),
+            "%" => (Binary::Rem, 10),
+            _ => return None,
+        })
+    }
+
+    fn apply(self, a: i64, b: i64) -> i64 {
+        match self {
+            Binary::Or => (a != 0 || b != 0) as i64,
+            Binary::And => (a != 0 && b != 0) as i64,
+            Binary::BitOr => a | b,
+            Binary::BitXor => a ^ b,
+            Binary::BitAnd => a & b,
+            Binary::Eq => (a == b) as i64,
+            Binary::Ne => (a != b) as i64,
+            Binary::Lt => (a < b) as i64,
+            Binary::Le => (a <= b) as i64,
+            Binary::Gt => (a > b) as i64,
+            Binary::Ge => (a >= b) as i64,
+            Binary::Shl => a.wrapping_shl(b as u32),
+            Binary::Shr => a.wrapping_shr(b as u32),
+            Binary::Add => a.wrapping_add(b),
+            Binary::Sub => a.wrapping_sub(b),
+            Binary::Mul => a.wrapping_mul(b),
+            Binary::Div => a.checked_div(b).unwrap_or(0),
+            Binary::Rem => a.checked_rem(b).unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Number(i64),
+    Register(String),
+    Byte(Box<Node>),
+    Word(Box<Node>),
+    Unary(Unary, Box<Node>),
+    Binary(Binary, Box<Node>, Box<Node>),
+}
+
+impl Node {
+    fn eval(&self, cpu: &CPU) -> i64 {
+        let peek = |addr: i64| cpu.mem.r8(addr as u16 as i32) as u8 as i64;
+        match self {
+            Node::Number(n) => *n,
+            Node::Register(name) => register(cpu, name).unwrap_or(0) as i64,
+            Node::Byte(addr) => peek(addr.eval(cpu)),
+            Node::Word(addr) => {
+                let addr = addr.eval(cpu);
+                peek(addr) | peek(addr.wrapping_add(1)) << 8
+            }
+            Node::Unary(op, operand) => {
+                let v = operand.eval(cpu);
+                match op {
+                    Unary::Neg => v.wrapping_neg(),
+                    Unary::Not => (v == 0) as i64,
+                    Unary::Complement => !v,
+                }
+            }
+            Node::Binary(op, a, b) => op.apply(a.eval(cpu), b.eval(cpu)),
+        }
+    }
+}
+
+/// A parsed expression, kept with its source text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    text: String,
+    node: Node,
+}
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Expr, String> {
+        Self::parse_radix(text, 10)
+    }
+
+    /// Parse with plain numbers in another radix (16 for a monitor-style
+    /// `0100`)
+    pub fn parse_radix(text: &str, radix: u32) -> Result<Expr, String> {
+        Self::parse_with(text, radix, &Symbols::new())
+    }
+
+    /// Parse with plain numbers in `radix` and names looked up in `symbols`
+    /// as well as the registers
+    pub fn parse_with(text: &str, radix: u32, symbols: &Symbols) -> Result<Expr, String> {
+        let tokens = tokenize(text, radix)?;
+        let mut parser = Parser { tokens: &tokens, pos: 0, radix, symbols };
+        let node = parser.expression(0)?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected {}", token));
+        }
+        Ok(Expr { text: text.trim().to_string(), node })
+    }
+
+    pub fn eval(&self, cpu: &CPU) -> i64 {
+        self.node.eval(cpu)
+    }
+
+    /// Evaluate as a condition: true if nonzero
+    pub fn is_true(&self, cpu: &CPU) -> bool {
+        self.eval(cpu) != 0
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+//=============================================================================
+// Parsing
+//=============================================================================
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "number {}", n),
+            Token::Name(name) => write!(f, "\"{}\"", name),
+            Token::Op(op) => write!(f, "\"{}\"", op),
+        }
+    }
+}
+
+/// Operators, longest first so `<=` wins over `<`
+const OPERATORS: [&str; 24] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "<", ">", "+", "-", "*", "/", "%", "&", "|", "^", "!",
+    "~", "(", ")", "[", "]",
+];
+
+/// Parse a number: `$` or `0x` hex, `#` decimal, otherwise in `radix`.
+/// Digits may be grouped with `_`.
+pub fn parse_number(word: &str, radix: u32) -> Option<i64> {
+    let (radix, digits) = if let Some(hex) = word.strip_prefix('$') {
+        (16, hex)
+    } else if let Some(hex) = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        (16, hex)
+    } else if let Some(decimal) = word.strip_prefix('#') {
+        (10, decimal)
+    } else {
+        (radix, word)
+    };
+    if digits.starts_with(['+', '-']) {
+        return None;
+    }
+    i64::from_str_radix(&digits.replace('_', ""), radix).ok()
+}
+
+fn tokenize(text: &str, radix: u32) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        let (token, len) = if c.is_ascii_digit() || c == '$' || c == '#' {
+            // Length of the prefix
+            let start = match c {
+                '$' | '#' => 1,
+                _ if rest.starts_with("0x") || rest.starts_with("0X") => 2,
+                _ => 0,
+            };
+            let len = start + rest[start..].chars().take_while(|c| c.is_ascii_alphanumeric() || *c == '_').count();
+            let word = &rest[..len];
+            let n = parse_number(word, radix).ok_or_else(|| format!("invalid number \"{}\"", word))?;
+            (Token::Number(n), len)
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            // Symbols may contain dots (sjasmplus `main.loop`)
+            let mut len = rest.chars().take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.')).count();
+            if rest[len..].starts_with('\'') {
+                len += 1;
+            }
+            (Token::Name(rest[..len].to_string()), len)
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            (Token::Op(op), op.len())
+        } else {
+            return Err(format!("unexpected \"{}\"", c));
+        };
+        tokens.push(token);
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    radix: u32,
+    symbols: &'a Symbols,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self.peek().cloned().ok_or("unexpected end of expression")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        match self.peek() {
+            Some(Token::Op(o)) if *o == op => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(token) => Err(format!("expected \"{}\", found {}", op, token)),
+            None => Err(format!("expected \"{}\"", op)),
+        }
+    }
+
+    /// Binary operators binding tighter than `min`, by precedence climbing
+    fn expression(&mut self, min: u8) -> Result<Node, String> {
+        let mut left = self.unary()?;
+        while let Some(Token::Op(op)) = self.peek() {
+            let Some((binary, prec)) = Binary::from_token(op).filter(|(_, prec)| *prec > min) else {
+                break;
+            };
+            self.pos += 1;
+            let right = self.expression(prec)?;
+            left = Node::Binary(binary, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        let op = match self.peek() {
+            Some(Token::Op("-")) => Unary::Neg,
+            Some(Token::Op("!")) => Unary::Not,
+            Some(Token::Op("~")) => Unary::Complement,
+            _ => return self.primary(),
+        };
+        self.pos += 1;
+        Ok(Node::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        match self.next()? {
+            Token::Number(n) => Ok(Node::Number(n)),
+            Token::Op("(") => {
+                let node = self.expression(0)?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Token::Op("[") => Ok(Node::Byte(Box::new(self.bracketed()?))),
+            Token::Name(name) if name.eq_ignore_ascii_case("w") && self.peek() == Some(&Token::Op("[")) => {
+                self.pos += 1;
+                Ok(Node::Word(Box::new(self.bracketed()?)))
+            }
+            Token::Name(name) => {
+                let lower = name.to_ascii_lowercase();
+                if REGISTERS.contains(&lower.as_str()) {
+                    return Ok(Node::Register(lower));
+                }
+                match self.symbols.value(&name).map(i64::from).or_else(|| parse_number(&name, self.radix)) {
+                    Some(value) => Ok(Node::Number(value)),
+                    None if self.symbols.is_empty() => Err(format!("unknown register \"{}\"", lower)),
+                    None => Err(format!("unknown register or symbol \"{}\"", name)),
+                }
+            }
+            token => Err(format!("unexpected {}", token)),
+        }
+    }
+
+    /// The address inside `[...]`, after the opening bracket
+    fn bracketed(&mut self) -> Result<Node, String> {
+        let node = self.expression(0)?;
+        self.expect("]")?;
+        Ok(node)
+    }
+}
@@ -0,0 +1,130 @@
+//! Interrupt controller
+//!
+//! Devices get an `IrqLine` and drive it like the open-collector /INT pin:
+//! the CPU sees an interrupt request while any line is asserted. When the
+//! request is accepted, the highest-priority line (the first one created,
+//! as in a daisy chain) supplies the data bus contents for the acknowledge
+//! cycle: the instruction for IM 0 or the vector for IM 2.
+//!
+//! /NMI is edge triggered, so `nmi()` latches a request that the CPU takes
+//! after the current instruction.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Data bus contents when nothing drives it (RST 38H via the pull-ups)
+pub const FLOATING_BUS: u8 = 0xFF;
+
+struct Line {
+    name: &'static str,
+    asserted: bool,
+    bus_data: [u8; 3],
+}
+
+/// Shared /INT and /NMI state for all devices on the bus
+#[derive(Default)]
+pub struct InterruptController {
+    lines: RefCell<Vec<Line>>,
+    nmi_pending: RefCell<bool>,
+}
+
+/// One device's connection to /INT
+#[derive(Clone)]
+pub struct IrqLine {
+    controller: Rc<InterruptController>,
+    index: usize,
+}
+
+impl IrqLine {
+    /// Pull /INT low
+    pub fn assert(&self) {
+        self.set(true);
+    }
+
+    /// Let go of /INT
+    pub fn release(&self) {
+        self.set(false);
+    }
+
+    /// Drive /INT from a device condition
+    pub fn set(&self, asserted: bool) {
+        self.controller.lines.borrow_mut()[self.index].asserted = asserted;
+    }
+
+    pub fn is_asserted(&self) -> bool {
+        self.controller.lines.borrow()[self.index].asserted
+    }
+
+    /// Set the bytes this device puts on the data bus when its interrupt
+    /// is acknowledged: an IM 2 vector, or an IM 0 instruction (RST n, or
+    /// CALL nn as three bytes). Defaults to $FF.
+    pub fn set_bus_data(&self, data: &[u8]) {
+        let mut bus_data = [FLOATING_BUS; 3];
+        let len = data.len().min(3);
+        bus_data[..len].copy_from_slice(&data[..len]);
+        self.controller.lines.borrow_mut()[self.index].bus_data = bus_data;
+    }
+}
+
+impl InterruptController {
+    pub fn new() -> Rc<Self> {
+        Rc::new(Self::default())
+    }
+
+    /// Connect a new device to /INT. Lines created earlier have priority.
+    pub fn line(self: &Rc<Self>, name: &'static str) -> IrqLine {
+        let mut lines = self.lines.borrow_mut();
+        lines.push(Line {
+            name,
+            asserted: false,
+            bus_data: [FLOATING_BUS; 3],
+        });
+        IrqLine {
+            controller: self.clone(),
+            index: lines.len() - 1,
+        }
+    }
+
+    /// Check if any device is pulling /INT low
+    pub fn int_asserted(&self) -> bool {
+        self.lines.borrow().iter().any(|line| line.asserted)
+    }
+
+    /// Names of the devices currently asserting /INT
+    pub fn asserted_by(&self) -> Vec<&'static str> {
+        self.lines
+            .borrow()
+            .iter()
+            .filter(|line| line.asserted)
+            .map(|line| line.name)
+            .collect()
+    }
+
+    /// Run an acknowledge cycle: data bus contents from the
+    /// highest-priority asserted line
+    pub fn acknowledge(&self) -> [u8; 3] {
+        self.lines
+            .borrow()
+            .iter()
+            .find(|line| line.asserted)
+            .map_or([FLOATING_BUS; 3], |line| line.bus_data)
+    }
+
+    /// Pulse /NMI
+    pub fn nmi(&self) {
+        *self.nmi_pending.borrow_mut() = true;
+    }
+
+    /// Take a latched /NMI request
+    pub fn take_nmi(&self) -> bool {
+        std::mem::take(&mut *self.nmi_pending.borrow_mut())
+    }
+
+    /// Release every line and drop any pending /NMI
+    pub fn reset(&self) {
+        for line in self.lines.borrow_mut().iter_mut() {
+            line.asserted = false;
+        }
+        *self.nmi_pending.borrow_mut() = false;
+    }
+}
@@ -0,0 +1,24 @@
+//! RetroShield Z80 Emulator
+//!
+//! Shared machine model used by the passthrough, TUI and WASM front-ends.
+
+pub mod access;
+pub mod breakpoint;
+pub mod chardev;
+pub mod console;
+pub mod disasm;
+pub mod expr;
+pub mod interrupt;
+pub mod listing;
+pub mod memedit;
+pub mod ports;
+pub mod profile;
+pub mod retroshield;
+pub mod script;
+pub mod sd;
+pub mod serial;
+pub mod symbols;
+pub mod watchpoint;
+
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
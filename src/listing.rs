@@ -0,0 +1,286 @@
+//! Assembler listings, for source-level debugging
+//!
+//! A listing gives the address and bytes of every source line. Rows look
+//! much the same in sjasmplus, zmac and z88dk z80asm listings:
+//!
+//! ```text
+//! 12    0100 3E 2A        start:  ld a,42        sjasmplus
+//!   12:     0100  3E 2A           ld a,42        zmac
+//! 12    0100  3E 2A               ld a,42        z80asm
+//! ```
+//!
+//! A line number, perhaps marked with `+` or `:`, then the address, the
+//! bytes (two hex digits each, one space apart) and the source text after
+//! a wider gap. sjasmplus marks included files with `# file opened:` and
+//! `# file closed:` lines; other listings are taken to be one file, named
+//! after the listing.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use rz80::CPU;
+
+use crate::breakpoint::Goal;
+
+/// Width tabs in the source text expand to
+const TAB: usize = 8;
+
+/// One line of source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    pub number: u32,
+    pub text: String,
+    /// Address and length of the code or data the line assembled to
+    pub code: Option<(u16, u16)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    pub name: String,
+    /// Lines in listing order, each line number once
+    pub lines: Vec<SourceLine>,
+}
+
+/// A line of a source file: indexes into `Listing::files` and that file's
+/// `lines`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub file: usize,
+    pub line: usize,
+}
+
+/// The source files of one or more listings, and where their code is
+#[derive(Debug, Default, Clone)]
+pub struct Listing {
+    pub files: Vec<SourceFile>,
+    /// Length and line of each row of code, by address
+    code: BTreeMap<u16, (u16, Location)>,
+}
+
+impl Listing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the rows of a listing's text, with `name` for the source when
+    /// the listing does not say. Returns how many rows of code were added.
+    pub fn read(&mut self, text: &str, name: &str) -> usize {
+        let before = self.code.len();
+        let mut open: Vec<usize> = Vec::new();
+        for line in text.lines() {
+            if let Some(opened) = line.strip_prefix("# file opened:") {
+                open.push(self.file(opened.trim()));
+                continue;
+            }
+            if line.starts_with("# file closed:") {
+                open.pop();
+                continue;
+            }
+            let Some(row) = row(line) else {
+                continue;
+            };
+            let file = match open.last() {
+                Some(&file) => file,
+                None => self.file(name),
+            };
+
+            // Continuation rows and macro expansions repeat the line number
+            let lines = &mut self.files[file].lines;
+            if lines.last().is_none_or(|last| last.number != row.number) {
+                lines.push(SourceLine { number: row.number, text: row.text, code: None });
+            }
+            let index = lines.len() - 1;
+            if let Some((addr, len)) = row.code {
+                lines[index].code.get_or_insert((addr, len));
+                self.code.insert(addr, (len, Location { file, line: index }));
+            }
+        }
+        self.code.len() - before
+    }
+
+    /// Read a listing file, returning how many rows of code it added
+    pub fn load(&mut self, path: &Path) -> Result<usize, String> {
+        let text = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let name = path.with_extension("asm");
+        let name = name.file_name().unwrap_or_default().to_string_lossy();
+        match self.read(&String::from_utf8_lossy(&text), &name) {
+            0 => Err(format!("{}: no listing lines found", path.display())),
+            added => Ok(added),
+        }
+    }
+
+    /// Index of a file, added if it is new
+    fn file(&mut self, name: &str) -> usize {
+        match self.files.iter().position(|f| f.name == name) {
+            Some(index) => index,
+            None => {
+                self.files.push(SourceFile { name: name.to_string(), lines: Vec::new() });
+                self.files.len() - 1
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    pub fn line(&self, at: Location) -> &SourceLine {
+        &self.files[at.file].lines[at.line]
+    }
+
+    /// The line whose code covers an address
+    pub fn locate(&self, addr: u16) -> Option<Location> {
+        let (&start, &(len, at)) = self.code.range(..=addr).next_back()?;
+        ((addr - start) < len).then_some(at)
+    }
+
+    /// The line whose code starts at an address
+    pub fn line_start(&self, addr: u16) -> Option<Location> {
+        self.code.get(&addr).map(|&(_, at)| at)
+    }
+
+    /// Address of `FILE:LINE`: the first code at or after that line. FILE
+    /// may be given with or without its directory and extension.
+    pub fn find(&self, file: &str, number: u32) -> Result<u16, String> {
+        let wanted = Path::new(file);
+        let source = self
+            .files
+            .iter()
+            .find(|f| {
+                let path = Path::new(&f.name);
+                f.name == file || path.file_name() == wanted.file_name() || path.file_stem() == Some(wanted.as_os_str())
+            })
+            .ok_or_else(|| format!("no source file \"{}\"", file))?;
+        source
+            .lines
+            .iter()
+            .filter(|line| line.number >= number)
+            .find_map(|line| line.code.map(|(addr, _)| addr))
+            .ok_or_else(|| format!("no code at or after {}:{}", file, number))
+    }
+
+    /// Replace every `FILE:LINE` word of a command line by its address, so
+    /// that it can be used wherever an address can
+    pub fn resolve(&self, text: &str) -> Result<String, String> {
+        let words: Result<Vec<String>, String> = text
+            .split_whitespace()
+            .map(|word| match word.rsplit_once(':').map(|(file, number)| (file, number.parse::<u32>())) {
+                Some((file, Ok(number))) if !file.is_empty() => Ok(format!("${:04X}", self.find(file, number)?)),
+                _ => Ok(word.to_string()),
+            })
+            .collect();
+        Ok(words?.join(" "))
+    }
+}
+
+//=============================================================================
+// Stepping by line
+//=============================================================================
+
+/// A step by source line: runs until PC is at the start of another line,
+/// or back at the start of the same one (a loop). Code that is not in the
+/// listing, such as a ROM routine called from it, is run through.
+#[derive(Debug, Clone, Copy)]
+pub struct LineStep {
+    from: Option<Location>,
+    start: u16,
+    /// When stepping over, the call (or block instruction) to finish first
+    call: Option<Goal>,
+}
+
+impl LineStep {
+    /// Start a step from PC; `over` steps over calls instead of into them
+    pub fn new(listing: &Listing, cpu: &CPU, over: bool) -> LineStep {
+        let pc = cpu.reg.pc() as u16;
+        LineStep {
+            from: listing.locate(pc),
+            start: pc,
+            call: if over { Goal::step_over(cpu) } else { None },
+        }
+    }
+
+    /// Whether `reached` wants to know if the last instruction returned
+    pub fn awaits_return(&self) -> bool {
+        self.call.is_some_and(|goal| goal.awaits_return())
+    }
+
+    /// Whether the step is done once an instruction has executed.
+    /// `returned` tells whether that instruction was a return.
+    pub fn reached(&mut self, listing: &Listing, cpu: &CPU, returned: bool) -> bool {
+        if let Some(goal) = self.call {
+            if !goal.reached(cpu, returned) {
+                return false;
+            }
+            self.call = None;
+        }
+        let pc = cpu.reg.pc() as u16;
+        match listing.line_start(pc) {
+            Some(at) => Some(at) != self.from || pc == self.start,
+            None => false,
+        }
+    }
+}
+
+//=============================================================================
+// Parsing
+//=============================================================================
+
+struct Row {
+    number: u32,
+    text: String,
+    code: Option<(u16, u16)>,
+}
+
+/// A listing row: line number, address, bytes and source text. Rows
+/// without a line number (headers, symbol tables) are not rows.
+fn row(line: &str) -> Option<Row> {
+    let rest = line.trim_start();
+    let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    let number = rest[..digits].parse().ok()?;
+    let rest = rest[digits..].trim_start_matches(['+', ':']);
+    if !(rest.is_empty() || rest.starts_with([' ', '\t'])) {
+        return None;
+    }
+
+    // The address, if the line has one
+    let field = rest.trim_start_matches(' ');
+    let addr = field
+        .get(..4)
+        .filter(|a| a.chars().all(|c| c.is_ascii_hexdigit()))
+        .filter(|_| field[4..].is_empty() || field[4..].starts_with([' ', '\t']))
+        .and_then(|a| u16::from_str_radix(a, 16).ok());
+    let Some(addr) = addr else {
+        return Some(Row { number, text: source(rest), code: None });
+    };
+
+    // Bytes, one space apart; the source follows a wider gap
+    let mut rest = field[4..].trim_start_matches(' ');
+    let mut len = 0;
+    while let Some(byte) = rest.get(..2).filter(|b| b.chars().all(|c| c.is_ascii_hexdigit())) {
+        let after = &rest[byte.len()..];
+        if !(after.is_empty() || after.starts_with([' ', '\t'])) {
+            break;
+        }
+        len += 1;
+        rest = after;
+        match after.strip_prefix(' ') {
+            Some(next) if !next.is_empty() && !next.starts_with([' ', '\t']) => rest = next,
+            _ => break,
+        }
+    }
+    Some(Row { number, text: source(rest), code: (len > 0).then_some((addr, len)) })
+}
+
+/// The source text at the end of a row: the padding before it dropped and
+/// its tabs expanded
+fn source(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.trim_start_matches(' ').trim_end().chars() {
+        match c {
+            '\t' => out.push_str(&" ".repeat(TAB - out.chars().count() % TAB)),
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -2,248 +2,350 @@
 //!
 //! A Z80 emulator for testing RetroShield firmware.
 //! Supports MC6850 ACIA, Intel 8251 USART serial chips, and SD card emulation.
+//!
+//! When the console is an interactive terminal it is put in raw mode, so
+//! every key (Ctrl-C included) goes to the firmware, and the emulator runs
+//! at the profile's clock, or without one sleeps while the firmware waits
+//! for a key. Ctrl-] opens a menu to reset, dump memory, break in or quit.
+//!
+//! With `-x script` it runs a test script headlessly instead (see the
+//! `script` module) and exits 0 if every step passes, 1 if one fails.
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::env;
-use std::fs::File;
-use std::io::{self, Read, Write};
+use std::fs;
+use std::io::{self, IsTerminal, Write};
 use std::path::PathBuf;
 use std::process;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use retro_z80_emulator::chardev::{self, CharDevice, StdioDevice};
+use retro_z80_emulator::profile;
+use retro_z80_emulator::retroshield::Machine;
+use retro_z80_emulator::script::Script;
+use retro_z80_emulator::watchpoint;
+
+/// Key that opens the console menu (Ctrl-], as in telnet)
+const ESCAPE_KEY: u8 = 0x1D;
+
+/// How often the interactive console checks its speed against the clock
+const PACE_CHECKS_PER_SECOND: u32 = 100;
+
+/// Cycles an interactive console without a clock runs between checks for
+/// idling, and how long it then sleeps unless a key comes
+const IDLE_CHECK_CYCLES: u64 = 200_000;
+const IDLE_WAIT: Duration = Duration::from_millis(10);
+
+/// Host terminal in raw mode; the original settings come back on drop
+#[cfg(unix)]
+struct RawTerminal {
+    saved: libc::termios,
+}
 
-use rz80::{Bus, CPU};
-
-mod sd;
-mod serial;
+#[cfg(unix)]
+impl RawTerminal {
+    /// Switch stdin to raw mode: no line buffering, echo or signal keys
+    fn enable() -> Option<Self> {
+        unsafe {
+            let mut saved: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0 {
+                return None;
+            }
+            let mut raw = saved;
+            libc::cfmakeraw(&mut raw);
+            // Keep LF -> CR LF on output so our own messages stay readable
+            raw.c_oflag |= libc::OPOST;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return None;
+            }
+            Some(Self { saved })
+        }
+    }
+}
 
-use sd::SdCard;
-use serial::{Mc6850, Intel8251};
+#[cfg(unix)]
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved);
+        }
+    }
+}
 
-/// MC6850 ACIA I/O ports
-const ACIA_CTRL: u8 = 0x80;
-const ACIA_DATA: u8 = 0x81;
+/// Stdin/stdout for the interactive console: the escape key is kept from
+/// the firmware and flagged for the main loop instead
+struct ConsoleDevice {
+    stdio: StdioDevice,
+    escape: Rc<RefCell<bool>>,
+    /// Set whenever the firmware sends a byte
+    sent: Rc<Cell<bool>>,
+}
 
-/// Intel 8251 USART I/O ports
-const USART_DATA: u8 = 0x00;
-const USART_CTRL: u8 = 0x01;
+impl CharDevice for ConsoleDevice {
+    fn read(&mut self) -> Option<u8> {
+        match self.stdio.read()? {
+            ESCAPE_KEY => {
+                *self.escape.borrow_mut() = true;
+                None
+            }
+            c => Some(c),
+        }
+    }
 
-/// Memory dump I/O ports
-const DUMP_ADDR_LO: u8 = 0x82;   // Low byte of start address
-const DUMP_ADDR_HI: u8 = 0x83;   // High byte of start address
-const DUMP_LEN_LO: u8 = 0x84;    // Low byte of length
-const DUMP_LEN_HI: u8 = 0x85;    // High byte of length
-const DUMP_TRIGGER: u8 = 0x86;  // Write any value to trigger dump
+    fn write(&mut self, c: u8) {
+        self.sent.set(true);
+        self.stdio.write(c);
+    }
 
-/// Memory dump state (for interior mutability)
-#[derive(Default)]
-struct DumpState {
-    start_addr: u16,
-    length: u16,
-    output_file: Option<String>,
+    fn flush(&mut self) {
+        self.stdio.flush();
+    }
 }
 
-/// RetroShield system with memory and I/O
-#[allow(dead_code)]
-struct RetroShield {
-    rom_size: u16,
-    acia: Mc6850,
-    usart: Intel8251,
-    sd: SdCard,
-    uses_8251: bool,
-    debug: bool,
-    dump_state: RefCell<DumpState>,
-    cpu_mem: RefCell<Option<*const rz80::Memory>>,  // Reference to CPU memory for dumps
+/// Keeps an interactive session at the profile's clock speed, sleeping
+/// in poll() on stdin rather than spinning
+struct Pacer {
+    clock_hz: u32,
+    start: Instant,
+    start_cycles: u64,
+    next_check: u64,
 }
 
-impl RetroShield {
-    fn new(storage_dir: PathBuf) -> Self {
+impl Pacer {
+    fn new(clock_hz: u32, cycles: u64) -> Self {
         Self {
-            rom_size: 0x2000, // Default 8KB ROM
-            acia: Mc6850::new(),
-            usart: Intel8251::new(),
-            sd: SdCard::new(storage_dir),
-            uses_8251: false,
-            debug: false,
-            dump_state: RefCell::new(DumpState::default()),
-            cpu_mem: RefCell::new(None),
+            clock_hz,
+            start: Instant::now(),
+            start_cycles: cycles,
+            next_check: cycles,
         }
     }
 
-    fn set_dump_output(&self, filename: &str) {
-        self.dump_state.borrow_mut().output_file = Some(filename.to_string());
+    /// Measure from now (after a pause, or a reset that zeroed the count)
+    fn restart(&mut self, cycles: u64) {
+        *self = Self::new(self.clock_hz, cycles);
     }
 
-    fn set_cpu_mem(&self, mem: &rz80::Memory) {
-        *self.cpu_mem.borrow_mut() = Some(mem as *const _);
-    }
-
-    fn do_memory_dump(&self) {
-        let state = self.dump_state.borrow();
-        let filename = state.output_file.as_ref().map(|s| s.as_str()).unwrap_or("dump.bin");
-        let start = state.start_addr as usize;
-        let len = state.length as usize;
-
-        if len == 0 {
-            eprintln!("Memory dump: length is 0, nothing to dump");
-            return;
+    /// Wait if the emulation is ahead of the wall clock. A key press ends
+    /// the wait early so it is seen at once.
+    fn pace(&mut self, cycles: u64) {
+        if cycles < self.start_cycles {
+            self.restart(cycles);
         }
-
-        // Get CPU memory reference
-        let mem_ptr = *self.cpu_mem.borrow();
-        if mem_ptr.is_none() {
-            eprintln!("Memory dump: CPU memory not available");
+        if cycles < self.next_check {
             return;
         }
+        self.next_check = cycles + (self.clock_hz / PACE_CHECKS_PER_SECOND).max(1) as u64;
 
-        // Safety: We know the CPU memory is valid for the lifetime of the emulation
-        let mem = unsafe { &*mem_ptr.unwrap() };
-
-        // Read memory range
-        let mut buffer = Vec::with_capacity(len);
-        for addr in start..(start + len).min(0x10000) {
-            buffer.push(mem.r8(addr as i32) as u8);
+        let due = Duration::from_secs_f64((cycles - self.start_cycles) as f64 / self.clock_hz as f64);
+        if let Some(ahead) = due.checked_sub(self.start.elapsed()) {
+            wait_for_stdin(ahead);
         }
+    }
+}
 
-        // Write to file
-        match File::create(filename) {
-            Ok(mut file) => {
-                match file.write_all(&buffer) {
-                    Ok(_) => eprintln!("Memory dump: {} bytes written to {} (0x{:04X}-0x{:04X})",
-                                      buffer.len(), filename, start, start + buffer.len() - 1),
-                    Err(e) => eprintln!("Memory dump: write error: {}", e),
-                }
-            }
-            Err(e) => eprintln!("Memory dump: failed to create {}: {}", filename, e),
+/// Keeps an interactive session without a clock from spinning while the
+/// firmware waits for a key: when it has sent nothing for a while and no
+/// input is waiting, sleep in poll() on stdin. Firmware that computes
+/// without output is slowed to `IDLE_CHECK_CYCLES` per `IDLE_WAIT`, still
+/// several times a real Z80.
+struct Idler {
+    sent: Rc<Cell<bool>>,
+    next_check: u64,
+}
+
+impl Idler {
+    fn new(sent: Rc<Cell<bool>>, cycles: u64) -> Self {
+        Self {
+            sent,
+            next_check: cycles + IDLE_CHECK_CYCLES,
         }
     }
 
-    /// Configure ROM size based on ROM filename
-    fn configure_rom(&mut self, filename: &str) {
-        let basename = filename.rsplit('/').next().unwrap_or(filename);
-
-        if basename.contains("mint") {
-            self.rom_size = 0x0800; // 2KB ROM for MINT
-            if self.debug {
-                eprintln!("MINT ROM: {} bytes protected", self.rom_size);
-            }
-        } else {
-            self.rom_size = 0x2000; // Default 8KB
-            if self.debug {
-                eprintln!("Default ROM: {} bytes protected", self.rom_size);
-            }
+    fn idle(&mut self, cycles: u64, input_waiting: bool) {
+        // A reset zeroes the cycle count
+        if cycles < self.next_check && cycles + IDLE_CHECK_CYCLES >= self.next_check {
+            return;
+        }
+        self.next_check = cycles + IDLE_CHECK_CYCLES;
+        if !self.sent.replace(false) && !input_waiting {
+            wait_for_stdin(IDLE_WAIT);
         }
     }
 }
 
-impl Bus for RetroShield {
-    fn cpu_inp(&self, port: i32) -> i32 {
-        let port = port as u8;
-        let val = match port {
-            // MC6850 ACIA
-            ACIA_CTRL => self.acia.read_status(),
-            ACIA_DATA => self.acia.read_data(),
-
-            // Intel 8251 USART
-            USART_CTRL => self.usart.read_status(),
-            USART_DATA => self.usart.read_data(),
-
-            // SD Card
-            p if SdCard::handles_port(p) => self.sd.read_port(p),
-
-            _ => 0xFF,
+/// Sleep until stdin has input or the timeout passes
+fn wait_for_stdin(timeout: Duration) {
+    #[cfg(unix)]
+    {
+        let mut pfd = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
         };
-        val as i32
-    }
-
-    fn cpu_outp(&self, port: i32, val: i32) {
-        let port = port as u8;
-        let val = val as u8;
-
-        // SD Card ports
-        if SdCard::handles_port(port) {
-            self.sd.write_port(port, val);
-            return;
+        unsafe {
+            libc::poll(&mut pfd, 1, timeout.as_millis() as i32);
         }
+    }
 
-        // Note: We need interior mutability here since Bus trait takes &self
-        // Using RefCell for dump state
-        match port {
-            // MC6850 ACIA
-            ACIA_CTRL => { /* Control register write - ignored for now */ }
-            ACIA_DATA => {
-                print!("{}", val as char);
-                let _ = io::stdout().flush();
-            }
+    #[cfg(not(unix))]
+    {
+        std::thread::sleep(timeout);
+    }
+}
 
-            // Intel 8251 USART
-            USART_CTRL => { /* Control/mode register - ignored for now */ }
-            USART_DATA => {
-                print!("{}", val as char);
-                let _ = io::stdout().flush();
-            }
+/// What the main loop does after the console menu closes
+enum MenuAction {
+    Resume,
+    Quit,
+}
 
-            // Memory dump ports
-            DUMP_ADDR_LO => {
-                let mut state = self.dump_state.borrow_mut();
-                state.start_addr = (state.start_addr & 0xFF00) | (val as u16);
-            }
-            DUMP_ADDR_HI => {
-                let mut state = self.dump_state.borrow_mut();
-                state.start_addr = (state.start_addr & 0x00FF) | ((val as u16) << 8);
+/// Show the escape menu and carry out the chosen command
+fn console_menu(machine: &mut Machine, dump_file: &str) -> MenuAction {
+    let mut stdio = StdioDevice::new();
+    loop {
+        eprint!("\r\n[retroshield] c)ontinue r)eset n)mi d)ump b)reak q)uit ^])send ^]: ");
+        let Some(key) = stdio.read_blocking() else {
+            return MenuAction::Quit;
+        };
+        eprintln!("{}", if key.is_ascii_graphic() { key as char } else { ' ' });
+
+        match key.to_ascii_lowercase() {
+            b'c' | b'\r' | b'\n' => return MenuAction::Resume,
+            b'r' => {
+                machine.reset();
+                eprintln!("Reset");
+                return MenuAction::Resume;
             }
-            DUMP_LEN_LO => {
-                let mut state = self.dump_state.borrow_mut();
-                state.length = (state.length & 0xFF00) | (val as u16);
+            b'n' => {
+                machine.nmi();
+                return MenuAction::Resume;
             }
-            DUMP_LEN_HI => {
-                let mut state = self.dump_state.borrow_mut();
-                state.length = (state.length & 0x00FF) | ((val as u16) << 8);
+            b'd' => {
+                let image: Vec<u8> = (0..0x10000).map(|addr| machine.cpu.mem.r8(addr) as u8).collect();
+                match fs::write(dump_file, image) {
+                    Ok(()) => eprintln!("Memory $0000-$FFFF written to {}", dump_file),
+                    Err(e) => eprintln!("Memory dump: {}: {}", dump_file, e),
+                }
             }
-            DUMP_TRIGGER => {
-                self.do_memory_dump();
+            b'b' => return break_in(machine, &mut stdio),
+            b'q' => return MenuAction::Quit,
+            ESCAPE_KEY => {
+                machine.system.send_key(ESCAPE_KEY);
+                return MenuAction::Resume;
             }
-
             _ => {}
         }
     }
 }
 
-fn load_rom(cpu: &mut CPU, filename: &str) -> io::Result<usize> {
-    let mut file = File::open(filename)?;
-    let mut buffer = Vec::new();
-    let bytes_read = file.read_to_end(&mut buffer)?;
+/// Stop the CPU and single-step it from the console
+fn break_in(machine: &mut Machine, stdio: &mut StdioDevice) -> MenuAction {
+    loop {
+        print_registers(machine);
+        eprint!("[break] s)tep c)ontinue q)uit: ");
+        let Some(key) = stdio.read_blocking() else {
+            return MenuAction::Quit;
+        };
+        eprintln!();
 
-    // Load into CPU memory
-    for (addr, &byte) in buffer.iter().enumerate() {
-        if addr < 0x10000 {
-            cpu.mem.w8(addr as i32, byte as i32);
+        match key.to_ascii_lowercase() {
+            b's' | b' ' => {
+                if let Err(trap) = machine.step() {
+                    eprintln!("{}", trap);
+                }
+                machine.system.console.flush();
+            }
+            b'c' | b'\r' | b'\n' => return MenuAction::Resume,
+            b'q' => return MenuAction::Quit,
+            _ => {}
         }
     }
+}
 
-    Ok(bytes_read)
+fn print_registers(machine: &Machine) {
+    let reg = &machine.cpu.reg;
+    let mem = &machine.cpu.mem;
+    let pc = reg.pc();
+    eprintln!(
+        "PC={:04X} AF={:04X} BC={:04X} DE={:04X} HL={:04X} IX={:04X} IY={:04X} SP={:04X} IFF1={} IM={}  [{:02X} {:02X} {:02X} {:02X}]",
+        pc, reg.af(), reg.bc(), reg.de(), reg.hl(), reg.ix(), reg.iy(), reg.sp(),
+        machine.cpu.iff1 as u8, reg.im,
+        mem.r8(pc), mem.r8(pc + 1), mem.r8(pc + 2), mem.r8(pc + 3),
+    );
 }
 
 fn print_usage(program: &str) {
-    eprintln!("Usage: {} [-d] [-c cycles] [-o dump.bin] [-s storage_dir] <rom.bin>", program);
+    eprintln!("Usage: {} [-d] [-t] [-w watch]... [-m machine.toml] [-i filter] [-S device] [-x script] [-c cycles] [-o dump.bin] [-s storage_dir] [rom.bin]", program);
     eprintln!("  -d          Debug mode");
-    eprintln!("  -c cycles   Max cycles to run (0 = unlimited)");
+    eprintln!("  -m file     Machine profile (default: <rom>.toml next to the ROM, if present)");
+    eprintln!("  -i filter   Input filter on top of the profile's, e.g. upcase,eol=cr,bs=del,strip7");
+    eprintln!("  -S device   Serial console: stdio (default), file:IN[,OUT], pipe:IN,OUT, unix:PATH,");
+    eprintln!("              tcp-listen:[HOST:]PORT[,wait][,telnet], unix-listen:PATH[,wait][,telnet]");
+    eprintln!("              or pty[:LINK] (prints the /dev/pts path)");
+    eprintln!("  -x script   Run a test script (send/expect/assert_mem/...) and exit 0 on pass, 1 on fail");
+    eprintln!("  -t          Stop on writes to ROM (default: ignore them)");
+    eprintln!("  -w watch    Stop on a memory or port access: [io,]ADDR[,LEN][,r|w|rw], e.g. 0x2043,w");
+    eprintln!("              or io,0x80 (repeatable; memory defaults to w, ports to rw)");
+    eprintln!("  -c cycles   Max cycles to run (0 = unlimited; a script fails on reaching it)");
     eprintln!("  -o file     Output file for memory dumps (default: dump.bin)");
     eprintln!("  -s dir      Storage directory for SD card emulation (default: ./storage)");
+    eprintln!();
+    eprintln!("On a terminal, Ctrl-] opens a menu: continue, reset, NMI, dump, break, quit.");
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
     let mut debug = false;
+    let mut trap_rom = false;
     let mut max_cycles: u64 = 0;
     let mut rom_file: Option<String> = None;
+    let mut machine_file: Option<String> = None;
+    let mut input_filter: Option<String> = None;
+    let mut serial_device = "stdio".to_string();
+    let mut script_file: Option<String> = None;
     let mut dump_output: Option<String> = None;
     let mut storage_dir: Option<String> = None;
+    let mut watches: Vec<String> = Vec::new();
 
     // Parse arguments
     let mut i = 1;
     while i < args.len() {
         match args[i].as_str() {
             "-d" | "--debug" => debug = true,
+            "-t" | "--trap-rom" => trap_rom = true,
+            "-w" | "--watch" => {
+                i += 1;
+                if i < args.len() {
+                    watches.push(args[i].clone());
+                }
+            }
+            "-m" | "--machine" => {
+                i += 1;
+                if i < args.len() {
+                    machine_file = Some(args[i].clone());
+                }
+            }
+            "-i" | "--input" => {
+                i += 1;
+                if i < args.len() {
+                    input_filter = Some(args[i].clone());
+                }
+            }
+            "-S" | "--serial" => {
+                i += 1;
+                if i < args.len() {
+                    serial_device = args[i].clone();
+                }
+            }
+            "-x" | "--script" => {
+                i += 1;
+                if i < args.len() {
+                    script_file = Some(args[i].clone());
+                }
+            }
             "-c" => {
                 i += 1;
                 if i < args.len() {
@@ -270,42 +372,115 @@ fn main() {
         i += 1;
     }
 
-    let rom_file = match rom_file {
-        Some(f) => f,
-        None => {
-            print_usage(&args[0]);
+    if rom_file.is_none() && machine_file.is_none() {
+        print_usage(&args[0]);
+        process::exit(1);
+    }
+
+    let (mut profile, rom_file) = match profile::resolve(machine_file.as_deref(), rom_file.as_deref()) {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            eprintln!("Error: {}", e);
             process::exit(1);
         }
     };
+    if let Some(spec) = input_filter {
+        if let Err(e) = profile.input.apply_spec(&spec) {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+
+    if debug {
+        eprintln!("Machine: {} (ROM ${:04X}, {} bytes protected)",
+                 profile.name, profile.rom_load, profile.rom_size);
+    }
 
     // Initialize system
     let storage_path = PathBuf::from(storage_dir.unwrap_or_else(|| "storage".to_string()));
-    let mut system = RetroShield::new(storage_path);
-    system.debug = debug;
-    system.configure_rom(&rom_file);
+    let mut machine = match Machine::with_profile(profile, storage_path) {
+        Ok(machine) => machine,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+    machine.set_debug(debug);
+
+    // A script talks to the built-in queue. An interactive terminal gets
+    // the escape menu and runs at clock speed.
+    let script = script_file.map(|file| {
+        match fs::read_to_string(&file).map_err(|e| e.to_string()).and_then(|text| Script::parse(&text)) {
+            Ok(script) => (file, script),
+            Err(e) => {
+                eprintln!("Error: script {}: {}", file, e);
+                process::exit(1);
+            }
+        }
+    });
+    let interactive = script.is_none() && serial_device == "stdio" && io::stdin().is_terminal();
+    let escape = Rc::new(RefCell::new(false));
+    let sent = Rc::new(Cell::new(false));
+    if script.is_some() {
+        if serial_device != "stdio" {
+            eprintln!("Error: -x and -S cannot be combined");
+            process::exit(1);
+        }
+    } else if interactive {
+        machine.system.console.set_device(Box::new(ConsoleDevice {
+            stdio: StdioDevice::new(),
+            escape: escape.clone(),
+            sent: sent.clone(),
+        }));
+    } else {
+        match chardev::open(&serial_device) {
+            Ok(mut device) => {
+                device.set_debug(debug);
+                if let Some(place) = device.describe() {
+                    eprintln!("Serial console on {}", place);
+                }
+                machine.system.console.set_device(device);
+            }
+            Err(e) => {
+                eprintln!("Error: serial device {}: {}", serial_device, e);
+                process::exit(1);
+            }
+        }
+    }
+    let clock_hz = machine.profile().clock_hz.filter(|_| interactive);
+    let idle = (interactive && clock_hz.is_none()).then_some(sent);
+
+    if debug {
+        for (port, device) in machine.system.ports().iter() {
+            eprintln!("  Port ${:02X}: {}", port, device);
+        }
+    }
+    machine.trap_rom_writes = trap_rom;
+    for spec in &watches {
+        match watchpoint::parse_spec(spec, &machine.cpu) {
+            Ok(watch) => {
+                let id = machine.watchpoints.add(watch);
+                if debug {
+                    eprintln!("Watchpoint #{} on {}", id, watch);
+                }
+            }
+            Err(e) => {
+                eprintln!("Error: watchpoint {}: {}", spec, e);
+                process::exit(1);
+            }
+        }
+    }
 
     // Set dump output file if specified
     if let Some(ref output) = dump_output {
-        system.set_dump_output(output);
+        machine.system.set_dump_output(output);
     }
 
-    // Initialize CPU with 64KB RAM
-    let mut cpu = CPU::new_64k();
-
-    // Set CPU memory reference for dumps
-    system.set_cpu_mem(&cpu.mem);
-
-    // Set CPU memory reference for SD DMA block transfers
-    system.sd.set_cpu_mem(&mut cpu.mem);
-
-    // Enable SD debug if main debug is enabled
-    system.sd.set_debug(debug);
-
     // Load ROM
-    match load_rom(&mut cpu, &rom_file) {
+    match machine.load_rom_file(&rom_file) {
         Ok(bytes) => {
             if debug {
-                eprintln!("Loaded {} bytes from {}", bytes, rom_file);
+                eprintln!("Loaded {} bytes from {}", bytes, rom_file.display());
             }
         }
         Err(e) => {
@@ -318,29 +493,89 @@ fn main() {
         eprintln!("Starting Z80 emulation...");
     }
 
-    // Main emulation loop
-    let mut total_cycles: u64 = 0;
+    if let Some((file, script)) = script {
+        let result = script.run(&mut machine, max_cycles, &mut io::stdout());
+        let _ = io::stdout().flush();
+        match result {
+            Ok(()) => {
+                eprintln!("\nPASS {} ({} cycles)", file, machine.total_cycles);
+                process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("\nFAIL {}: {}", file, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    let dump_file = dump_output.unwrap_or_else(|| "dump.bin".to_string());
+    let code = {
+        #[cfg(unix)]
+        let _raw_terminal = if interactive { RawTerminal::enable() } else { None };
+        run(&mut machine, &escape, clock_hz, idle, max_cycles, &dump_file, debug)
+    };
+    let _ = io::stdout().flush();
+    process::exit(code);
+}
+
+/// Main emulation loop (the console polls the serial device itself).
+/// Returns the process exit code.
+fn run(
+    machine: &mut Machine,
+    escape: &RefCell<bool>,
+    clock_hz: Option<u32>,
+    idle: Option<Rc<Cell<bool>>>,
+    max_cycles: u64,
+    dump_file: &str,
+    debug: bool,
+) -> i32 {
+    let mut pacer = clock_hz.map(|hz| Pacer::new(hz, machine.total_cycles));
+    let mut idler = idle.map(|sent| Idler::new(sent, machine.total_cycles));
 
     loop {
-        let cycles = cpu.step(&system);
-        total_cycles += cycles as u64;
+        let result = machine.step();
+
+        if let Err(trap) = result {
+            machine.system.console.flush();
+            eprintln!("\n{} after {} cycles", trap, machine.total_cycles);
+            return 1;
+        }
+
+        if escape.replace(false) {
+            machine.system.console.flush();
+            if let MenuAction::Quit = console_menu(machine, dump_file) {
+                return 0;
+            }
+            if let Some(pacer) = &mut pacer {
+                pacer.restart(machine.total_cycles);
+            }
+        }
+
+        if let Some(pacer) = &mut pacer {
+            pacer.pace(machine.total_cycles);
+        }
+        if let Some(idler) = &mut idler {
+            idler.idle(machine.total_cycles, machine.system.console.input_available());
+        }
 
-        // Check for halt
-        if cpu.halt {
+        // Check for HALT with interrupts disabled (nothing can resume it)
+        if machine.is_stopped() {
+            machine.system.console.flush();
             if debug {
                 eprintln!("\nCPU halted at PC={:04X} after {} cycles",
-                         cpu.reg.pc(), total_cycles);
+                         machine.cpu.reg.pc(), machine.total_cycles);
             }
-            break;
+            return 0;
         }
 
         // Check cycle limit
-        if max_cycles > 0 && total_cycles >= max_cycles {
+        if max_cycles > 0 && machine.total_cycles >= max_cycles {
+            machine.system.console.flush();
             if debug {
                 eprintln!("Stopped at PC={:04X} after {} cycles",
-                         cpu.reg.pc(), total_cycles);
+                         machine.cpu.reg.pc(), machine.total_cycles);
             }
-            break;
+            return 0;
         }
     }
 }
@@ -0,0 +1,104 @@
+//! Memory panel editor
+//!
+//! The TUI's F1 editor: a cursor over the bytes of the memory view that
+//! overwrites memory as hex nibbles or ASCII characters and follows
+//! little-endian pointers. The view scrolls by whole 16-byte rows to keep
+//! the cursor on screen. Edits go straight to memory, ROM included.
+
+use rz80::CPU;
+
+/// Bytes per row of the memory view
+pub const ROW: u16 = 16;
+
+/// Editor state: the cursor and how typed characters are taken
+#[derive(Debug, Clone, Default)]
+pub struct MemoryEditor {
+    pub cursor: u16,
+    /// Typing into the ASCII column rather than the hex one
+    pub ascii: bool,
+    /// The high nibble of the byte under the cursor has been typed
+    pub low_nibble: bool,
+    /// Addresses pointers were followed from, for `back`
+    trail: Vec<u16>,
+}
+
+impl MemoryEditor {
+    pub fn new(cursor: u16) -> Self {
+        MemoryEditor {
+            cursor,
+            ..Default::default()
+        }
+    }
+
+    /// Put the cursor on an address, at the start of its byte
+    pub fn move_to(&mut self, addr: u16) {
+        self.cursor = addr;
+        self.low_nibble = false;
+    }
+
+    /// Switch between typing hex and ASCII
+    pub fn toggle_ascii(&mut self) {
+        self.ascii = !self.ascii;
+        self.low_nibble = false;
+    }
+
+    /// Type a character at the cursor: a printable character in the ASCII
+    /// column, a hex digit (high nibble first) in the hex one. The cursor
+    /// moves on once the byte is complete. Returns false if the character
+    /// was not taken.
+    pub fn type_char(&mut self, cpu: &mut CPU, c: char) -> bool {
+        let addr = self.cursor as i32;
+        if self.ascii {
+            if !c.is_ascii() || c.is_ascii_control() {
+                return false;
+            }
+            cpu.mem.write(addr, &[c as u8]);
+            self.move_to(self.cursor.saturating_add(1));
+            return true;
+        }
+        let Some(digit) = c.to_digit(16) else {
+            return false;
+        };
+        let old = cpu.mem.r8(addr) as u8;
+        if self.low_nibble {
+            cpu.mem.write(addr, &[old & 0xF0 | digit as u8]);
+            self.move_to(self.cursor.saturating_add(1));
+        } else {
+            cpu.mem.write(addr, &[old & 0x0F | (digit as u8) << 4]);
+            self.low_nibble = true;
+        }
+        true
+    }
+
+    /// Follow the little-endian pointer under the cursor, remembering where
+    /// it was for `back`
+    pub fn follow(&mut self, cpu: &CPU) -> u16 {
+        let target = cpu.mem.r16(self.cursor as i32) as u16;
+        self.trail.push(self.cursor);
+        self.move_to(target);
+        target
+    }
+
+    /// Return to where the last pointer was followed from
+    pub fn back(&mut self) -> Option<u16> {
+        let addr = self.trail.pop()?;
+        self.move_to(addr);
+        Some(addr)
+    }
+}
+
+/// The start of a memory view of `rows` rows scrolled by whole rows, as
+/// little as possible, to show `cursor`
+pub fn scroll(view: u16, cursor: u16, rows: u16) -> u16 {
+    let rows = rows.max(1) as i32;
+    let row = ROW as i32;
+    let offset = cursor as i32 - view as i32;
+    let view = if offset < 0 {
+        view as i32 - (-offset + row - 1) / row * row
+    } else if offset >= rows * row {
+        view as i32 + (offset - rows * row) / row * row + row
+    } else {
+        return view;
+    };
+    view.clamp(0, 0xFFFF) as u16
+}
@@ -0,0 +1,116 @@
+//! I/O port decoding
+//!
+//! Maps each of the 256 Z80 I/O ports to the device register strapped
+//! there. The map is built from a machine profile, so any device can be
+//! moved or left out, and two devices claiming the same port is an error.
+
+use std::fmt;
+
+use crate::profile::Profile;
+use crate::retroshield::DUMP_PORT_COUNT;
+use crate::sd::SD_PORT_COUNT;
+
+/// Device register selected by an I/O port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    AciaControl,
+    AciaData,
+    UsartControl,
+    UsartData,
+    /// SD card register (offset from the SD base port)
+    Sd(u8),
+    /// Memory dump register (offset from the dump base port)
+    Dump(u8),
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Device::AciaControl => write!(f, "ACIA control"),
+            Device::AciaData => write!(f, "ACIA data"),
+            Device::UsartControl => write!(f, "USART control"),
+            Device::UsartData => write!(f, "USART data"),
+            Device::Sd(reg) => write!(f, "SD register {}", reg),
+            Device::Dump(reg) => write!(f, "dump register {}", reg),
+        }
+    }
+}
+
+/// Port-decoding table
+#[derive(Clone)]
+pub struct PortMap {
+    devices: [Option<Device>; 256],
+}
+
+impl Default for PortMap {
+    fn default() -> Self {
+        Self { devices: [None; 256] }
+    }
+}
+
+impl PortMap {
+    /// Empty map: every port is unconnected
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build the map for the devices fitted by a profile
+    pub fn from_profile(profile: &Profile) -> Result<Self, String> {
+        let mut map = Self::new();
+
+        if let Some(p) = profile.acia_ports() {
+            map.assign(p.control, Device::AciaControl)?;
+            map.assign(p.data, Device::AciaData)?;
+        }
+        if let Some(p) = profile.usart_ports() {
+            map.assign(p.control, Device::UsartControl)?;
+            map.assign(p.data, Device::UsartData)?;
+        }
+        if let Some(base) = profile.sd_port {
+            map.assign_block(base, SD_PORT_COUNT, Device::Sd)?;
+        }
+        if let Some(base) = profile.dump_port {
+            map.assign_block(base, DUMP_PORT_COUNT, Device::Dump)?;
+        }
+
+        Ok(map)
+    }
+
+    /// Connect a device register to a port, refusing ports already taken
+    pub fn assign(&mut self, port: u8, device: Device) -> Result<(), String> {
+        match self.devices[port as usize] {
+            Some(other) => Err(format!(
+                "I/O port ${:02X} assigned to both {} and {}",
+                port, other, device
+            )),
+            None => {
+                self.devices[port as usize] = Some(device);
+                Ok(())
+            }
+        }
+    }
+
+    /// Connect `count` consecutive registers starting at `base`
+    fn assign_block(&mut self, base: u8, count: u8, device: fn(u8) -> Device) -> Result<(), String> {
+        if base as u16 + count as u16 > 0x100 {
+            return Err(format!(
+                "{} ports from ${:02X} run past port $FF",
+                count, base
+            ));
+        }
+        (0..count).try_for_each(|reg| self.assign(base + reg, device(reg)))
+    }
+
+    /// Device register at a port, if any
+    pub fn decode(&self, port: u8) -> Option<Device> {
+        self.devices[port as usize]
+    }
+
+    /// All connected ports in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = (u8, Device)> + '_ {
+        self.devices
+            .iter()
+            .enumerate()
+            .filter_map(|(port, dev)| dev.map(|dev| (port as u8, dev)))
+    }
+}
@@ -0,0 +1,638 @@
+//! Machine profiles
+//!
+//! A profile describes one RetroShield configuration: where the ROM sits,
+//! which pages are RAM, which devices are fitted at which I/O ports, how
+//! serial input raises interrupts, the reset PC and the default clock.
+//! Profiles are small TOML files, one per ROM in `roms/`:
+//!
+//! ```toml
+//! name = "MINT"
+//!
+//! [rom]
+//! file = "mint.z80.bin"   # relative to the profile
+//! load = 0x0000
+//! size = 0x0800           # write-protected bytes
+//!
+//! [ram]
+//! ranges = ["0x0800-0xFFFF"]
+//!
+//! [serial]
+//! chip = "6850"           # "6850", "8251", "auto" (both) or "none"
+//! control_port = 0x80
+//! data_port = 0x81
+//! baud = 115200           # optional: time characters (needs cpu.clock_hz)
+//!
+//! [sd]
+//! enabled = true
+//! base_port = 0x10        # ten ports, $10-$19
+//!
+//! [dump]
+//! enabled = true
+//! base_port = 0x82        # five ports, $82-$86
+//!
+//! [cpu]
+//! reset_pc = 0x0000
+//! clock_hz = 4_000_000
+//!
+//! [interrupts]
+//! mode = "none"           # "none", "serial" or "auto"
+//! acia_vector = 0xFF      # data bus byte on acknowledge: IM 2 vector or
+//! usart_vector = 0xFF     # IM 0 RST opcode
+//!
+//! [input]                 # host keyboard transforms, all off by default
+//! upcase = false
+//! line_ending = "keep"    # "keep", "cr", "lf" or "crlf"
+//! strip_bit7 = false
+//! backspace = "keep"      # "keep", "bs" or "del"
+//! ```
+//!
+//! Only the subset of TOML used above is understood: `[section]` headers,
+//! `key = value` pairs with integer, string, boolean or single-line array
+//! values, and `#` comments.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::interrupt::FLOATING_BUS;
+use crate::serial::{Backspace, InputFilter, LineEnding, SerialTiming};
+
+/// Memory is mapped in 1KB pages, so every region must be page aligned
+pub const PAGE_SIZE: u32 = 0x400;
+
+/// Serial chip fitted to the board
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialChip {
+    /// Both chips at their default ports (firmware picks one)
+    Auto,
+    Mc6850,
+    Intel8251,
+    None,
+}
+
+/// Whether the serial chips' interrupt outputs are wired to /INT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptMode {
+    /// Not wired: firmware polls the status register
+    None,
+    /// ACIA /IRQ as programmed in its control register, 8251 RxRDY
+    Serial,
+    /// Whichever chip the firmware uses: the ACIA until it touches the 8251
+    Auto,
+}
+
+/// Control and data port pair of a serial chip
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialPorts {
+    pub control: u8,
+    pub data: u8,
+}
+
+/// Default MC6850 ACIA ports
+pub const ACIA_PORTS: SerialPorts = SerialPorts { control: 0x80, data: 0x81 };
+
+/// Default Intel 8251 USART ports
+pub const USART_PORTS: SerialPorts = SerialPorts { control: 0x01, data: 0x00 };
+
+/// Default first port of the SD card
+pub const SD_BASE_PORT: u8 = 0x10;
+
+/// Default first port of the memory dump device
+pub const DUMP_BASE_PORT: u8 = 0x82;
+
+/// Inclusive address range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl MemRange {
+    pub fn size(&self) -> u32 {
+        self.end as u32 - self.start as u32 + 1
+    }
+
+    pub fn contains(&self, addr: u16) -> bool {
+        (self.start..=self.end).contains(&addr)
+    }
+
+    fn overlaps(&self, other: &MemRange) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+}
+
+/// Declarative description of a RetroShield machine
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    /// ROM image to load when none is given on the command line
+    pub rom_file: Option<PathBuf>,
+    pub rom_load: u16,
+    /// Size of the write-protected ROM region in bytes
+    pub rom_size: u32,
+    pub ram: Vec<MemRange>,
+    pub serial: SerialChip,
+    /// Ports of the selected chip (ignored for `Auto` and `None`)
+    pub serial_ports: SerialPorts,
+    /// Line speed for baud-rate timing; `None` moves characters instantly
+    pub serial_baud: Option<u32>,
+    /// First SD card port, or `None` if no card is fitted
+    pub sd_port: Option<u8>,
+    /// First memory dump port, or `None` to disable dumps
+    pub dump_port: Option<u8>,
+    pub interrupts: InterruptMode,
+    /// Bytes the ACIA and the 8251 put on the data bus when their interrupt
+    /// is acknowledged: the IM 2 vector, or the IM 0 instruction
+    pub acia_vector: u8,
+    pub usart_vector: u8,
+    /// Transforms applied to keyboard input
+    pub input: InputFilter,
+    pub reset_pc: u16,
+    /// Nominal Z80 clock; `None` runs as fast as the front-end likes
+    pub clock_hz: Option<u32>,
+}
+
+impl Default for Profile {
+    /// 8KB ROM at $0000, RAM above it, both serial chips, SD card and dumps
+    fn default() -> Self {
+        Self {
+            name: "RetroShield Z80".to_string(),
+            rom_file: None,
+            rom_load: 0x0000,
+            rom_size: 0x2000,
+            ram: vec![MemRange { start: 0x2000, end: 0xFFFF }],
+            serial: SerialChip::Auto,
+            serial_ports: ACIA_PORTS,
+            serial_baud: None,
+            sd_port: Some(SD_BASE_PORT),
+            dump_port: Some(DUMP_BASE_PORT),
+            interrupts: InterruptMode::Auto,
+            acia_vector: FLOATING_BUS,
+            usart_vector: FLOATING_BUS,
+            input: InputFilter::default(),
+            reset_pc: 0x0000,
+            clock_hz: None,
+        }
+    }
+}
+
+impl Profile {
+    /// Load a profile file. A relative `rom.file` is resolved against the
+    /// profile's directory.
+    pub fn load(path: &Path) -> Result<Profile, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut profile = Profile::parse(&text)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        if let Some(rom) = &profile.rom_file {
+            if rom.is_relative() {
+                let dir = path.parent().unwrap_or(Path::new(""));
+                profile.rom_file = Some(dir.join(rom));
+            }
+        }
+        Ok(profile)
+    }
+
+    /// Parse profile text. Keys that are not given keep their defaults,
+    /// except that RAM defaults to everything outside the ROM.
+    pub fn parse(text: &str) -> Result<Profile, String> {
+        let mut table = parse_toml(text)?;
+        let mut p = Profile::default();
+
+        if let Some(v) = table.take("name") {
+            p.name = v.string()?;
+        }
+
+        if let Some(v) = table.take("rom.file") {
+            p.rom_file = Some(PathBuf::from(v.string()?));
+        }
+        if let Some(v) = table.take("rom.load") {
+            p.rom_load = v.int(0, 0xFFFF)? as u16;
+        }
+        if let Some(v) = table.take("rom.size") {
+            p.rom_size = v.int(0, 0x10000)? as u32;
+        }
+
+        p.ram = match table.take("ram.ranges") {
+            Some(v) => v.array()?
+                .iter()
+                .map(|r| parse_range(&r.string()?).map_err(|e| r.error(&e)))
+                .collect::<Result<_, _>>()?,
+            None => p.ram_outside_rom(),
+        };
+
+        if let Some(v) = table.take("serial.chip") {
+            p.serial = match v.string()?.as_str() {
+                "auto" => SerialChip::Auto,
+                "6850" => SerialChip::Mc6850,
+                "8251" => SerialChip::Intel8251,
+                "none" => SerialChip::None,
+                other => return Err(v.error(&format!("unknown serial chip \"{}\"", other))),
+            };
+        }
+        if p.serial == SerialChip::Intel8251 {
+            p.serial_ports = USART_PORTS;
+        }
+        if let Some(v) = table.take("serial.control_port") {
+            p.serial_ports.control = v.int(0, 0xFF)? as u8;
+        }
+        if let Some(v) = table.take("serial.data_port") {
+            p.serial_ports.data = v.int(0, 0xFF)? as u8;
+        }
+        if let Some(v) = table.take("serial.baud") {
+            p.serial_baud = Some(v.int(1, u32::MAX as i64)? as u32);
+        }
+
+        p.sd_port = optional_port(&mut table, "sd", SD_BASE_PORT)?;
+        p.dump_port = optional_port(&mut table, "dump", DUMP_BASE_PORT)?;
+
+        if let Some(v) = table.take("cpu.reset_pc") {
+            p.reset_pc = v.int(0, 0xFFFF)? as u16;
+        }
+        if let Some(v) = table.take("cpu.clock_hz") {
+            p.clock_hz = Some(v.int(1, u32::MAX as i64)? as u32);
+        }
+
+        if let Some(v) = table.take("interrupts.mode") {
+            p.interrupts = match v.string()?.as_str() {
+                "none" => InterruptMode::None,
+                "serial" => InterruptMode::Serial,
+                "auto" => InterruptMode::Auto,
+                other => return Err(v.error(&format!("unknown interrupt mode \"{}\"", other))),
+            };
+        }
+        if let Some(v) = table.take("interrupts.acia_vector") {
+            p.acia_vector = v.int(0, 0xFF)? as u8;
+        }
+        if let Some(v) = table.take("interrupts.usart_vector") {
+            p.usart_vector = v.int(0, 0xFF)? as u8;
+        }
+
+        if let Some(v) = table.take("input.upcase") {
+            p.input.upcase = v.bool()?;
+        }
+        if let Some(v) = table.take("input.line_ending") {
+            p.input.line_ending = LineEnding::parse(&v.string()?).map_err(|e| v.error(&e))?;
+        }
+        if let Some(v) = table.take("input.strip_bit7") {
+            p.input.strip_bit7 = v.bool()?;
+        }
+        if let Some(v) = table.take("input.backspace") {
+            p.input.backspace = Backspace::parse(&v.string()?).map_err(|e| v.error(&e))?;
+        }
+
+        // Anything left over is a typo or an unsupported setting
+        if let Some((key, v)) = table.0.iter().min_by_key(|(_, v)| v.line) {
+            return Err(v.error(&format!("unknown key \"{}\"", key)));
+        }
+
+        p.validate()?;
+        Ok(p)
+    }
+
+    /// Check that memory regions are page aligned and do not overlap, and
+    /// that serial timing has a clock to count in. Port clashes are caught
+    /// when the port map is built.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.serial_baud.is_some() && self.clock_hz.is_none() {
+            return Err("serial.baud needs cpu.clock_hz to time characters".to_string());
+        }
+
+        let rom_end = self.rom_load as u32 + self.rom_size;
+        if !(self.rom_load as u32).is_multiple_of(PAGE_SIZE) || !self.rom_size.is_multiple_of(PAGE_SIZE) {
+            return Err(format!(
+                "ROM at ${:04X} size ${:X} is not aligned to {} byte pages",
+                self.rom_load, self.rom_size, PAGE_SIZE
+            ));
+        }
+        if rom_end > 0x10000 {
+            return Err(format!(
+                "ROM at ${:04X} size ${:X} extends past $FFFF",
+                self.rom_load, self.rom_size
+            ));
+        }
+
+        for (i, r) in self.ram.iter().enumerate() {
+            if r.start > r.end {
+                return Err(format!("RAM range ${:04X}-${:04X} is empty", r.start, r.end));
+            }
+            if !(r.start as u32).is_multiple_of(PAGE_SIZE) || !r.size().is_multiple_of(PAGE_SIZE) {
+                return Err(format!(
+                    "RAM range ${:04X}-${:04X} is not aligned to {} byte pages",
+                    r.start, r.end, PAGE_SIZE
+                ));
+            }
+            if let Some(rom) = self.rom_range() {
+                if r.overlaps(&rom) {
+                    return Err(format!(
+                        "RAM range ${:04X}-${:04X} overlaps ROM ${:04X}-${:04X}",
+                        r.start, r.end, rom.start, rom.end
+                    ));
+                }
+            }
+            if let Some(o) = self.ram[..i].iter().find(|o| r.overlaps(o)) {
+                return Err(format!(
+                    "RAM ranges ${:04X}-${:04X} and ${:04X}-${:04X} overlap",
+                    o.start, o.end, r.start, r.end
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Address range of the ROM, if there is one
+    pub fn rom_range(&self) -> Option<MemRange> {
+        (self.rom_size > 0).then(|| MemRange {
+            start: self.rom_load,
+            end: (self.rom_load as u32 + self.rom_size - 1) as u16,
+        })
+    }
+
+    /// Check if an address lies in the write-protected ROM region
+    pub fn is_rom(&self, addr: u16) -> bool {
+        self.rom_range().is_some_and(|r| r.contains(addr))
+    }
+
+    /// Baud-rate timing for the serial chips, if a line speed is set
+    pub fn serial_timing(&self) -> Option<SerialTiming> {
+        let (baud, clock_hz) = self.serial_baud.zip(self.clock_hz)?;
+        Some(SerialTiming { baud, clock_hz })
+    }
+
+    /// Ports the MC6850 ACIA answers on, if fitted
+    pub fn acia_ports(&self) -> Option<SerialPorts> {
+        match self.serial {
+            SerialChip::Auto => Some(ACIA_PORTS),
+            SerialChip::Mc6850 => Some(self.serial_ports),
+            _ => None,
+        }
+    }
+
+    /// Ports the Intel 8251 USART answers on, if fitted
+    pub fn usart_ports(&self) -> Option<SerialPorts> {
+        match self.serial {
+            SerialChip::Auto => Some(USART_PORTS),
+            SerialChip::Intel8251 => Some(self.serial_ports),
+            _ => None,
+        }
+    }
+
+    /// Everything below and above the ROM
+    fn ram_outside_rom(&self) -> Vec<MemRange> {
+        let start = self.rom_load as u32;
+        let end = start + self.rom_size;
+        let mut ram = Vec::new();
+        if start > 0 {
+            ram.push(MemRange { start: 0, end: (start - 1) as u16 });
+        }
+        if end < 0x10000 {
+            ram.push(MemRange { start: end as u16, end: 0xFFFF });
+        }
+        ram
+    }
+}
+
+/// Profile file that sits next to a ROM image (`mint.z80.bin` -> `mint.z80.toml`)
+pub fn sibling_profile(rom: &Path) -> Option<PathBuf> {
+    let path = rom.with_extension("toml");
+    path.is_file().then_some(path)
+}
+
+/// Work out the profile and ROM image from the `--machine` and ROM
+/// arguments shared by all binaries. Without `--machine`, a profile next
+/// to the ROM is used if there is one, otherwise the default machine.
+pub fn resolve(machine: Option<&str>, rom: Option<&str>) -> Result<(Profile, PathBuf), String> {
+    let profile = match (machine, rom) {
+        (Some(path), _) => Profile::load(Path::new(path))?,
+        (None, Some(rom)) => match sibling_profile(Path::new(rom)) {
+            Some(path) => Profile::load(&path)?,
+            None => Profile::default(),
+        },
+        (None, None) => return Err("ROM file or --machine profile required".to_string()),
+    };
+
+    let rom = match (rom, &profile.rom_file) {
+        (Some(rom), _) => PathBuf::from(rom),
+        (None, Some(file)) => file.clone(),
+        (None, None) => return Err(format!("profile \"{}\" does not name a ROM file", profile.name)),
+    };
+
+    Ok((profile, rom))
+}
+
+/// Read `<section>.enabled` and `<section>.base_port` for a device that
+/// occupies a block of ports
+fn optional_port(table: &mut Table, section: &str, default: u8) -> Result<Option<u8>, String> {
+    let enabled = match table.take(&format!("{}.enabled", section)) {
+        Some(v) => v.bool()?,
+        None => true,
+    };
+    let port = match table.take(&format!("{}.base_port", section)) {
+        Some(v) => v.int(0, 0xFF)? as u8,
+        None => default,
+    };
+    Ok(enabled.then_some(port))
+}
+
+/// Parse "start-end" with each end in any integer syntax
+fn parse_range(s: &str) -> Result<MemRange, String> {
+    let (start, end) = s
+        .split_once('-')
+        .ok_or_else(|| format!("expected \"start-end\", got \"{}\"", s))?;
+    let start = parse_int(start.trim()).filter(|v| (0..=0xFFFF).contains(v));
+    let end = parse_int(end.trim()).filter(|v| (0..=0xFFFF).contains(v));
+    match (start, end) {
+        (Some(start), Some(end)) => Ok(MemRange { start: start as u16, end: end as u16 }),
+        _ => Err(format!("invalid address range \"{}\"", s)),
+    }
+}
+
+//=============================================================================
+// TOML subset
+//=============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+enum Kind {
+    Int(i64),
+    Str(String),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+/// A parsed value and the line it came from
+#[derive(Debug, Clone, PartialEq)]
+struct Value {
+    kind: Kind,
+    line: usize,
+}
+
+impl Value {
+    fn error(&self, msg: &str) -> String {
+        format!("line {}: {}", self.line, msg)
+    }
+
+    fn int(&self, min: i64, max: i64) -> Result<i64, String> {
+        match self.kind {
+            Kind::Int(v) if (min..=max).contains(&v) => Ok(v),
+            Kind::Int(v) => Err(self.error(&format!("{} is out of range", v))),
+            _ => Err(self.error("expected an integer")),
+        }
+    }
+
+    fn string(&self) -> Result<String, String> {
+        match &self.kind {
+            Kind::Str(s) => Ok(s.clone()),
+            _ => Err(self.error("expected a string")),
+        }
+    }
+
+    fn bool(&self) -> Result<bool, String> {
+        match self.kind {
+            Kind::Bool(b) => Ok(b),
+            _ => Err(self.error("expected true or false")),
+        }
+    }
+
+    fn array(&self) -> Result<&[Value], String> {
+        match &self.kind {
+            Kind::Array(items) => Ok(items),
+            _ => Err(self.error("expected an array")),
+        }
+    }
+}
+
+/// Values keyed by "section.key" (top-level keys have no section)
+struct Table(HashMap<String, Value>);
+
+impl Table {
+    fn take(&mut self, key: &str) -> Option<Value> {
+        self.0.remove(key)
+    }
+}
+
+fn parse_toml(text: &str) -> Result<Table, String> {
+    let mut table = HashMap::new();
+    let mut section = String::new();
+
+    for (n, raw) in text.lines().enumerate() {
+        let line = n + 1;
+        let err = |msg: &str| format!("line {}: {}", line, msg);
+        let content = strip_comment(raw).trim();
+        if content.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = content.strip_prefix('[') {
+            let name = name
+                .strip_suffix(']')
+                .ok_or_else(|| err("unterminated section header"))?
+                .trim();
+            if !is_bare_key(name) {
+                return Err(err(&format!("invalid section name \"{}\"", name)));
+            }
+            section = name.to_string();
+            continue;
+        }
+
+        let (key, value) = content
+            .split_once('=')
+            .ok_or_else(|| err("expected key = value"))?;
+        let key = key.trim();
+        if !is_bare_key(key) {
+            return Err(err(&format!("invalid key \"{}\"", key)));
+        }
+        let full_key = if section.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", section, key)
+        };
+
+        let value = parse_value(value.trim(), line)?;
+        if table.insert(full_key.clone(), value).is_some() {
+            return Err(err(&format!("duplicate key \"{}\"", full_key)));
+        }
+    }
+
+    Ok(Table(table))
+}
+
+fn is_bare_key(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Drop a trailing `#` comment, leaving `#` inside strings alone
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(s: &str, line: usize) -> Result<Value, String> {
+    let err = |msg: String| format!("line {}: {}", line, msg);
+
+    let kind = if let Some(inner) = s.strip_prefix('[') {
+        let inner = inner
+            .strip_suffix(']')
+            .ok_or_else(|| err("unterminated array".to_string()))?;
+        let items = split_array(inner)
+            .into_iter()
+            .map(|item| parse_value(item, line))
+            .collect::<Result<_, _>>()?;
+        Kind::Array(items)
+    } else if let Some(inner) = s.strip_prefix('"') {
+        let inner = inner
+            .strip_suffix('"')
+            .filter(|i| !i.contains('"'))
+            .ok_or_else(|| err(format!("invalid string {}", s)))?;
+        Kind::Str(inner.to_string())
+    } else if s == "true" || s == "false" {
+        Kind::Bool(s == "true")
+    } else {
+        Kind::Int(parse_int(s).ok_or_else(|| err(format!("invalid value \"{}\"", s)))?)
+    };
+
+    Ok(Value { kind, line })
+}
+
+/// Split array contents on commas outside strings, allowing a trailing comma
+fn split_array(s: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut in_string = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                items.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(s[start..].trim());
+    items.retain(|item| !item.is_empty());
+    items
+}
+
+/// Integer in decimal or 0x/0o/0b form, with optional `_` separators
+pub(crate) fn parse_int(s: &str) -> Option<i64> {
+    let s = s.replace('_', "");
+    let (digits, radix) = match s.get(..2) {
+        Some("0x") | Some("0X") => (&s[2..], 16),
+        Some("0o") => (&s[2..], 8),
+        Some("0b") => (&s[2..], 2),
+        _ => (s.as_str(), 10),
+    };
+    if digits.is_empty() || (radix != 10 && digits.starts_with(['+', '-'])) {
+        return None;
+    }
+    i64::from_str_radix(digits, radix).ok()
+}
@@ -0,0 +1,635 @@
+//! RetroShield machine model
+//!
+//! Owns the Z80 CPU, the memory map, the I/O device bus, interrupt delivery
+//! and cycle accounting. The passthrough, TUI and WASM front-ends all drive
+//! the same `Machine`, so a ROM behaves identically in each of them.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use rz80::{Bus, CPU};
+
+use crate::access;
+use crate::interrupt::{InterruptController, IrqLine};
+use crate::ports::{Device, PortMap};
+use crate::profile::{InterruptMode, Profile};
+use crate::sd::SdCard;
+use crate::serial::{Console, Intel8251, Mc6850};
+use crate::watchpoint::{Access, IoAccess, Space, WatchHit, Watchpoints};
+
+/// Memory dump registers (offsets from the base port, $82 by default)
+pub const DUMP_ADDR_LO: u8 = 0;   // Low byte of start address
+pub const DUMP_ADDR_HI: u8 = 1;   // High byte of start address
+pub const DUMP_LEN_LO: u8 = 2;    // Low byte of length
+pub const DUMP_LEN_HI: u8 = 3;    // High byte of length
+pub const DUMP_TRIGGER: u8 = 4;   // Write any value to trigger dump
+
+/// Number of consecutive ports decoded by the memory dump device
+pub const DUMP_PORT_COUNT: u8 = 5;
+
+/// IM 1 interrupt vector (RST 38H)
+const IM1_VECTOR: i32 = 0x0038;
+
+/// NMI restart address
+const NMI_VECTOR: i32 = 0x0066;
+
+/// Cycles taken to accept an interrupt, including the pushed return address
+const NMI_CYCLES: u32 = 11;
+const IM0_RST_CYCLES: u32 = 13;
+const IM0_CALL_CYCLES: u32 = 19;
+const IM1_CYCLES: u32 = 13;
+const IM2_CYCLES: u32 = 19;
+
+/// Cycles between polls of the console device for new input
+const INPUT_POLL_CYCLES: u32 = 1000;
+
+/// Condition that stopped the machine after an instruction executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trap {
+    /// Instruction at `pc` attempted to write ROM at `addr` (the write is discarded)
+    RomWrite { pc: u16, addr: u16 },
+    /// A watchpoint saw the instruction access memory or a port
+    Watch(WatchHit),
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trap::RomWrite { pc, addr } => {
+                write!(f, "ROM write at PC={:04X} to address {:04X}", pc, addr)
+            }
+            Trap::Watch(hit) => write!(f, "{}", hit),
+        }
+    }
+}
+
+/// Memory dump state (for interior mutability)
+#[derive(Default)]
+struct DumpState {
+    start_addr: u16,
+    length: u16,
+    output_file: Option<String>,
+}
+
+//=============================================================================
+// Device Bus
+//=============================================================================
+
+/// RetroShield system with memory and I/O
+pub struct RetroShield {
+    pub console: Rc<Console>,
+    pub acia: Mc6850,
+    pub usart: Intel8251,
+    pub sd: SdCard,
+    pub debug: bool,
+    ports: PortMap,
+    acia_fitted: bool,
+    usart_fitted: bool,
+    /// /INT and /NMI lines shared by all devices
+    pub interrupts: Rc<InterruptController>,
+    acia_irq: IrqLine,
+    usart_irq: IrqLine,
+    int_mode: InterruptMode,
+    uses_8251: RefCell<bool>,
+    /// Cycles left until the console device is polled again
+    poll_countdown: RefCell<u32>,
+    dump_state: RefCell<DumpState>,
+    cpu_mem: RefCell<Option<*const rz80::Memory>>,  // Reference to CPU memory for dumps
+    /// Last port access, for I/O watchpoints
+    last_io: RefCell<Option<IoAccess>>,
+}
+
+impl RetroShield {
+    pub fn new(storage_dir: PathBuf) -> Self {
+        let console = Rc::new(Console::new());
+        let profile = Profile::default();
+        let interrupts = InterruptController::new();
+        Self {
+            acia: Mc6850::new(console.clone()),
+            usart: Intel8251::new(console.clone()),
+            console,
+            sd: SdCard::new(storage_dir),
+            debug: false,
+            ports: PortMap::from_profile(&profile).expect("default port map has no clashes"),
+            acia_fitted: profile.acia_ports().is_some(),
+            usart_fitted: profile.usart_ports().is_some(),
+            acia_irq: interrupts.line("ACIA"),
+            usart_irq: interrupts.line("USART"),
+            interrupts,
+            int_mode: profile.interrupts,
+            uses_8251: RefCell::new(false),
+            poll_countdown: RefCell::new(0),
+            dump_state: RefCell::new(DumpState::default()),
+            cpu_mem: RefCell::new(None),
+            last_io: RefCell::new(None),
+        }
+    }
+
+    pub fn set_dump_output(&self, filename: &str) {
+        self.dump_state.borrow_mut().output_file = Some(filename.to_string());
+    }
+
+    fn set_cpu_mem(&self, mem: &rz80::Memory) {
+        *self.cpu_mem.borrow_mut() = Some(mem as *const _);
+    }
+
+    fn do_memory_dump(&self) {
+        let state = self.dump_state.borrow();
+        let filename = state.output_file.as_deref().unwrap_or("dump.bin");
+        let start = state.start_addr as usize;
+        let len = state.length as usize;
+
+        if len == 0 {
+            eprintln!("Memory dump: length is 0, nothing to dump");
+            return;
+        }
+
+        // Get CPU memory reference
+        let Some(mem_ptr) = *self.cpu_mem.borrow() else {
+            eprintln!("Memory dump: CPU memory not available");
+            return;
+        };
+
+        // Safety: the CPU is boxed inside `Machine` and outlives the bus
+        let mem = unsafe { &*mem_ptr };
+
+        // Read memory range
+        let buffer: Vec<u8> = (start..(start + len).min(0x10000))
+            .map(|addr| mem.r8(addr as i32) as u8)
+            .collect();
+
+        // Write to file
+        match File::create(filename) {
+            Ok(mut file) => {
+                match file.write_all(&buffer) {
+                    Ok(_) => eprintln!("Memory dump: {} bytes written to {} (0x{:04X}-0x{:04X})",
+                                      buffer.len(), filename, start, start + buffer.len() - 1),
+                    Err(e) => eprintln!("Memory dump: write error: {}", e),
+                }
+            }
+            Err(e) => eprintln!("Memory dump: failed to create {}: {}", filename, e),
+        }
+    }
+
+    /// Fit the devices described by a profile. Fails, leaving the bus
+    /// unchanged, if two devices claim the same port.
+    fn configure(&mut self, profile: &Profile) -> Result<(), String> {
+        self.ports = PortMap::from_profile(profile)?;
+        self.acia_fitted = profile.acia_ports().is_some();
+        self.usart_fitted = profile.usart_ports().is_some();
+        self.int_mode = profile.interrupts;
+        self.acia_irq.set_bus_data(&[profile.acia_vector]);
+        self.usart_irq.set_bus_data(&[profile.usart_vector]);
+        self.set_8251_mode(false);
+        self.console.set_filter(profile.input);
+        let timing = profile.serial_timing();
+        self.acia.set_timing(timing);
+        self.usart.set_timing(timing);
+        Ok(())
+    }
+
+    /// Port-decoding table in use
+    pub fn ports(&self) -> &PortMap {
+        &self.ports
+    }
+
+    /// Check if the firmware drives the 8251 (auto-detected on first access)
+    pub fn uses_8251(&self) -> bool {
+        *self.uses_8251.borrow()
+    }
+
+    /// Force 8251 interrupt mode on or off (used by `InterruptMode::Auto`)
+    pub fn set_8251_mode(&self, enabled: bool) {
+        *self.uses_8251.borrow_mut() = enabled;
+    }
+
+    /// Fitted serial chips the firmware is using, as (ACIA, 8251). With both
+    /// fitted the ACIA is assumed until the firmware touches the 8251.
+    fn serial_in_use(&self) -> (bool, bool) {
+        let usart = self.usart_fitted && (self.uses_8251() || !self.acia_fitted);
+        (self.acia_fitted && !usart, usart)
+    }
+
+    /// Advance device timing by `cycles` CPU cycles and poll the console
+    /// device now and then. Only the serial chip in use is clocked, so the
+    /// other one never takes console input.
+    pub fn tick(&self, cycles: u32) {
+        {
+            let mut countdown = self.poll_countdown.borrow_mut();
+            if *countdown <= cycles {
+                self.console.poll();
+                *countdown = INPUT_POLL_CYCLES;
+            } else {
+                *countdown -= cycles;
+            }
+        }
+
+        let (acia, usart) = self.serial_in_use();
+        if acia {
+            self.acia.tick(cycles);
+        }
+        if usart {
+            self.usart.tick(cycles);
+        }
+    }
+
+    /// Drive each device's /INT output from its current state
+    pub fn update_interrupts(&self) {
+        let (acia_wired, usart_wired) = match self.int_mode {
+            InterruptMode::None => (false, false),
+            InterruptMode::Serial => (true, true),
+            InterruptMode::Auto => (!self.uses_8251(), self.uses_8251()),
+        };
+        // Checking a chip pulls console input into its receive buffer, so
+        // leave it alone unless it is the chip in use
+        self.acia_irq.set(acia_wired && self.acia_fitted && self.acia.irq_pending());
+        // The 8251's RxRDY output is wired to /INT
+        self.usart_irq.set(usart_wired && self.usart_fitted && self.usart.rx_ready());
+    }
+
+    /// Return the devices to their power-on state
+    pub fn reset(&self) {
+        self.acia.reset();
+        self.usart.reset();
+        self.interrupts.reset();
+    }
+
+    fn write_dump_reg(&self, reg: u8, val: u8) {
+        match reg {
+            DUMP_ADDR_LO => {
+                let mut state = self.dump_state.borrow_mut();
+                state.start_addr = (state.start_addr & 0xFF00) | (val as u16);
+            }
+            DUMP_ADDR_HI => {
+                let mut state = self.dump_state.borrow_mut();
+                state.start_addr = (state.start_addr & 0x00FF) | ((val as u16) << 8);
+            }
+            DUMP_LEN_LO => {
+                let mut state = self.dump_state.borrow_mut();
+                state.length = (state.length & 0xFF00) | (val as u16);
+            }
+            DUMP_LEN_HI => {
+                let mut state = self.dump_state.borrow_mut();
+                state.length = (state.length & 0x00FF) | ((val as u16) << 8);
+            }
+            DUMP_TRIGGER => {
+                self.do_memory_dump();
+            }
+            _ => {}
+        }
+    }
+
+    /// Queue a character from the host keyboard
+    pub fn send_key(&self, c: u8) {
+        self.console.push_input(c);
+    }
+}
+
+impl Bus for RetroShield {
+    fn cpu_inp(&self, port: i32) -> i32 {
+        let val = match self.ports.decode(port as u8) {
+            // MC6850 ACIA
+            Some(Device::AciaControl) => self.acia.read_status(),
+            Some(Device::AciaData) => self.acia.read_data(),
+
+            // Intel 8251 USART
+            Some(Device::UsartControl) => {
+                self.set_8251_mode(true); // Mark ROM as using 8251
+                self.usart.read_status()
+            }
+            Some(Device::UsartData) => {
+                self.set_8251_mode(true);
+                self.usart.read_data()
+            }
+
+            // SD Card
+            Some(Device::Sd(reg)) => self.sd.read_reg(reg),
+
+            // Memory dump registers are write-only
+            Some(Device::Dump(_)) | None => 0xFF,
+        };
+        *self.last_io.borrow_mut() = Some((Access::Read, port as u8, val));
+        val as i32
+    }
+
+    fn cpu_outp(&self, port: i32, val: i32) {
+        let val = val as u8;
+        *self.last_io.borrow_mut() = Some((Access::Write, port as u8, val));
+
+        match self.ports.decode(port as u8) {
+            // MC6850 ACIA
+            Some(Device::AciaControl) => self.acia.write_control(val),
+            Some(Device::AciaData) => self.acia.write_data(val),
+
+            // Intel 8251 USART
+            Some(Device::UsartControl) => {
+                self.set_8251_mode(true);
+                self.usart.write_control(val);
+            }
+            Some(Device::UsartData) => {
+                self.set_8251_mode(true);
+                self.usart.write_data(val);
+            }
+
+            // SD Card
+            Some(Device::Sd(reg)) => self.sd.write_reg(reg, val),
+
+            // Memory dump
+            Some(Device::Dump(reg)) => self.write_dump_reg(reg, val),
+
+            None => {}
+        }
+    }
+}
+
+//=============================================================================
+// Machine
+//=============================================================================
+
+/// A complete RetroShield Z80: CPU, 64KB memory and the device bus
+pub struct Machine {
+    /// Boxed so the memory address handed to the SD and dump devices stays put
+    pub cpu: Box<CPU>,
+    pub system: RetroShield,
+    pub total_cycles: u64,
+    /// Stop with `Trap::RomWrite` instead of silently ignoring ROM writes
+    pub trap_rom_writes: bool,
+    /// Memory and port accesses that stop with `Trap::Watch`
+    pub watchpoints: Watchpoints,
+    profile: Profile,
+}
+
+impl Machine {
+    pub fn new(storage_dir: PathBuf) -> Self {
+        let mut cpu = Box::new(CPU::new_64k());
+        let system = RetroShield::new(storage_dir);
+
+        // Set CPU memory reference for dumps and SD DMA block transfers
+        system.set_cpu_mem(&cpu.mem);
+        system.sd.set_cpu_mem(&mut cpu.mem);
+
+        let mut machine = Self {
+            cpu,
+            system,
+            total_cycles: 0,
+            trap_rom_writes: false,
+            watchpoints: Watchpoints::new(),
+            profile: Profile::default(),
+        };
+        machine.apply_memory_map();
+        machine
+    }
+
+    /// Create a machine configured by a profile
+    pub fn with_profile(profile: Profile, storage_dir: PathBuf) -> Result<Self, String> {
+        let mut machine = Self::new(storage_dir);
+        machine.configure(profile)?;
+        Ok(machine)
+    }
+
+    /// Profile the machine is currently configured with
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    /// Reconfigure memory and devices from a profile and reset the CPU.
+    /// Fails if the profile is invalid or two devices share a port.
+    /// Memory contents are kept, so reload the ROM afterwards if its
+    /// location changed.
+    pub fn configure(&mut self, profile: Profile) -> Result<(), String> {
+        profile.validate()?;
+        self.system.configure(&profile)?;
+        self.profile = profile;
+        self.apply_memory_map();
+        self.reset();
+        Ok(())
+    }
+
+    /// Map the ROM region read-only and the RAM ranges writable. Anything
+    /// else is unmapped: reads return $FF and writes are ignored.
+    fn apply_memory_map(&mut self) {
+        // Heap offsets equal CPU addresses, so contents survive remapping
+        self.cpu.mem.unmap_all();
+        if let Some(rom) = self.profile.rom_range() {
+            self.cpu.mem.map(0, rom.start as usize, rom.start as usize, false, rom.size() as usize);
+        }
+        for ram in &self.profile.ram {
+            self.cpu.mem.map(0, ram.start as usize, ram.start as usize, true, ram.size() as usize);
+        }
+    }
+
+    /// Enable debug output on the machine and its devices
+    pub fn set_debug(&mut self, debug: bool) {
+        self.system.debug = debug;
+        self.system.sd.set_debug(debug);
+    }
+
+    /// Copy a ROM image into memory at the profile's load address. Images
+    /// larger than the ROM region also initialise the memory after it.
+    pub fn load_rom(&mut self, data: &[u8]) {
+        let load = self.profile.rom_load as usize;
+        // Bypasses write protection so the ROM region can be filled
+        self.cpu.mem.write(load as i32, &data[..data.len().min(0x10000 - load)]);
+    }
+
+    /// Load a ROM image file at the profile's load address
+    pub fn load_rom_file(&mut self, path: &Path) -> io::Result<usize> {
+        let mut file = File::open(path)?;
+        let mut buffer = Vec::new();
+        let bytes_read = file.read_to_end(&mut buffer)?;
+
+        self.load_rom(&buffer);
+
+        Ok(bytes_read)
+    }
+
+    /// Reset the CPU and interrupt state
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        self.cpu.reg.set_pc(self.profile.reset_pc as i32);
+        self.system.reset();
+        self.total_cycles = 0;
+    }
+
+    /// Check if the CPU has executed HALT
+    pub fn is_halted(&self) -> bool {
+        self.cpu.halt
+    }
+
+    /// Check if the CPU has halted with interrupts disabled, so that only
+    /// /NMI or a reset can resume it
+    pub fn is_stopped(&self) -> bool {
+        self.cpu.halt && !self.cpu.iff1
+    }
+
+    /// Pulse /NMI
+    pub fn nmi(&self) {
+        self.system.interrupts.nmi();
+    }
+
+    /// Check the next instruction for a write into ROM
+    fn rom_write_trap(&self) -> Option<Trap> {
+        let region = access::memory_write(&self.cpu)?;
+        let addr = region.addrs().find(|&a| self.profile.is_rom(a))?;
+        let pc = self.cpu.reg.pc() as u16;
+        Some(Trap::RomWrite { pc, addr })
+    }
+
+    /// Execute ED-prefixed instructions that rz80 does not implement:
+    /// RETN and its mirrors, and the undefined opcodes, which are 8-cycle
+    /// NOPs on the Z80. Returns the cycles taken if the instruction at PC
+    /// was one of them.
+    fn execute_missing_ed(&mut self) -> Option<u32> {
+        let pc = self.cpu.reg.pc();
+        if self.cpu.mem.r8(pc) != 0xED {
+            return None;
+        }
+        let op = self.cpu.mem.r8(pc + 1) as u8;
+        let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+
+        let cycles = match (x, y, z) {
+            // RETN (ED 4D is RETI, which rz80 handles)
+            (1, _, 5) if y != 1 => {
+                self.cpu.iff1 = self.cpu.iff2;
+                let sp = self.cpu.reg.sp();
+                self.cpu.reg.set_pc(self.cpu.mem.r16(sp));
+                self.cpu.reg.set_sp((sp + 2) & 0xFFFF);
+                14
+            }
+            (1, _, _) => return None,
+            // Block instructions
+            (2, 4..=7, 0..=3) => return None,
+            _ => {
+                self.cpu.reg.set_pc((pc + 2) & 0xFFFF);
+                8
+            }
+        };
+
+        // Two opcode fetches
+        let r = self.cpu.reg.r;
+        self.cpu.reg.r = (r & 0x80) | ((r + 2) & 0x7F);
+        Some(cycles)
+    }
+
+    /// Resume after HALT, which leaves PC on the HALT instruction
+    fn leave_halt(&mut self) {
+        if self.cpu.halt {
+            self.cpu.halt = false;
+            self.cpu.reg.inc_pc(1);
+        }
+    }
+
+    /// Push the return address and jump to an interrupt handler
+    fn enter_interrupt(&mut self, handler: i32) {
+        self.leave_halt();
+        let pc = self.cpu.reg.pc();
+        self.cpu.push(pc);
+        self.cpu.reg.set_pc(handler);
+    }
+
+    /// Accept a pending /NMI or /INT. Called between instructions, which
+    /// also gives EI its one-instruction delay: rz80 only sets IFF1 when
+    /// the instruction after EI starts. Returns the cycles taken.
+    fn service_interrupts(&mut self) -> u32 {
+        let interrupts = self.system.interrupts.clone();
+        self.system.update_interrupts();
+
+        if interrupts.take_nmi() {
+            self.cpu.iff1 = false;
+            self.enter_interrupt(NMI_VECTOR);
+            return NMI_CYCLES;
+        }
+
+        if !self.cpu.iff1 || !interrupts.int_asserted() {
+            return 0;
+        }
+
+        let bus = interrupts.acknowledge();
+        let target = match self.cpu.reg.im {
+            // Execute the instruction on the data bus
+            0 => match bus[0] {
+                // RST p
+                op if op & 0xC7 == 0xC7 => Some(((op & 0x38) as i32, IM0_RST_CYCLES)),
+                // CALL nn
+                0xCD => Some(((bus[2] as i32) << 8 | bus[1] as i32, IM0_CALL_CYCLES)),
+                _ => None,
+            },
+            // RST 38H
+            1 => Some((IM1_VECTOR, IM1_CYCLES)),
+            // Handler address from the table at I * 256 + vector
+            _ => {
+                let table = (self.cpu.reg.i << 8) | bus[0] as i32;
+                Some((self.cpu.mem.r16(table), IM2_CYCLES))
+            }
+        };
+
+        self.cpu.iff1 = false;
+        self.cpu.iff2 = false;
+        match target {
+            Some((handler, cycles)) => {
+                self.enter_interrupt(handler);
+                cycles
+            }
+            None => {
+                // Other IM 0 instructions would have to be executed from
+                // outside memory; treat them as NOP
+                if self.system.debug {
+                    eprintln!("IM 0: unsupported instruction {:02X} on the data bus", bus[0]);
+                }
+                self.leave_halt();
+                4
+            }
+        }
+    }
+
+    /// Execute one instruction and deliver any pending interrupt.
+    /// Returns the number of cycles taken, or the trap raised by the
+    /// instruction (which has still executed, and its cycles are counted).
+    pub fn step(&mut self) -> Result<u32, Trap> {
+        let trap = if self.trap_rom_writes {
+            self.rom_write_trap()
+        } else {
+            None
+        };
+        let watch = (!self.watchpoints.is_empty()).then(|| {
+            self.system.last_io.borrow_mut().take();
+            self.watchpoints.before(&self.cpu)
+        });
+
+        let mut cycles = match self.execute_missing_ed() {
+            Some(cycles) => cycles,
+            None => self.cpu.step(&self.system) as u32,
+        };
+        self.system.tick(cycles);
+        // Before the interrupt, whose pushes are not watched
+        let trap = trap.or_else(|| {
+            let pending = watch?;
+            let io = self.system.last_io.borrow_mut().take().filter(|_| self.watchpoints.watches(Space::Io));
+            self.watchpoints.after(pending, &self.cpu, io).map(Trap::Watch)
+        });
+        let int_cycles = self.service_interrupts();
+        self.system.tick(int_cycles);
+        cycles += int_cycles;
+        self.total_cycles += cycles as u64;
+
+        match trap {
+            Some(trap) => Err(trap),
+            None => Ok(cycles),
+        }
+    }
+
+    /// Run until at least `max_cycles` have elapsed or the CPU stops
+    /// (halted with interrupts disabled). Returns the number of cycles
+    /// executed.
+    pub fn run(&mut self, max_cycles: u32) -> Result<u32, Trap> {
+        let mut cycles_run: u32 = 0;
+        while cycles_run < max_cycles && !self.is_stopped() {
+            cycles_run += self.step()?;
+        }
+        Ok(cycles_run)
+    }
+}
@@ -0,0 +1,445 @@
+//! Scripted test runner
+//!
+//! Drives a machine headlessly from a small line-based script, for
+//! firmware regression tests in CI. Each line is one command; `#` starts
+//! a comment outside strings:
+//!
+//! ```text
+//! timeout 20000000            # default limit for expect (cycles)
+//! expect "Ok"                 # wait for text in the serial output
+//! sendline "PRINT 2+2"        # type a line (CR appended)
+//! expect " 4" 2s              # per-line limit: cycles, or wall time in s/ms
+//! send "\x03"                 # type raw bytes
+//! run 100000                  # just run for a number of cycles
+//! assert_mem $9000 $2A 0 "AB" # bytes (or text) at an address
+//! assert_reg hl $1234         # a register: a f b c d e h l af bc de hl
+//!                             # ix iy sp pc
+//! ```
+//!
+//! Strings take the escapes `\r`, `\n`, `\t`, `\e`, `\0`, `\\`, `\"` and
+//! `\xNN`. Numbers are decimal, `$` or `0x` hex. `expect` searches the
+//! output that follows the previous match, so repeated text must be
+//! expected once per appearance.
+
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use crate::expr;
+use crate::profile::parse_int;
+use crate::retroshield::Machine;
+
+/// Limit for `expect` when the script sets none
+pub const DEFAULT_TIMEOUT_CYCLES: u64 = 10_000_000;
+
+/// Registers `assert_reg` knows
+const REGISTERS: [&str; 16] = [
+    "a", "f", "b", "c", "d", "e", "h", "l", "af", "bc", "de", "hl", "ix", "iy", "sp", "pc",
+];
+
+/// How many cycles run between wall-clock checks
+const TIME_CHECK_CYCLES: u64 = 10_000;
+
+/// How an `expect` is bounded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Cycles(u64),
+    Time(Duration),
+}
+
+impl Limit {
+    /// Parse `N` (cycles), `Ns` or `Nms`
+    pub fn parse(s: &str) -> Result<Limit, String> {
+        let (digits, scale) = if let Some(ms) = s.strip_suffix("ms") {
+            (ms, Some(1))
+        } else if let Some(secs) = s.strip_suffix('s') {
+            (secs, Some(1000))
+        } else {
+            (s, None)
+        };
+        let n = parse_number(digits).ok_or_else(|| format!("invalid limit \"{}\"", s))?;
+        Ok(match scale {
+            Some(ms) => Limit::Time(Duration::from_millis(n * ms)),
+            None => Limit::Cycles(n),
+        })
+    }
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Limit::Cycles(n) => write!(f, "{} cycles", n),
+            Limit::Time(d) => write!(f, "{} ms", d.as_millis()),
+        }
+    }
+}
+
+/// One script command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Timeout(Limit),
+    Send(Vec<u8>),
+    Expect(Vec<u8>, Option<Limit>),
+    Run(u64),
+    AssertMem(u16, Vec<u8>),
+    AssertReg(String, u16),
+}
+
+/// A parsed script: commands with their line numbers
+#[derive(Debug, Clone)]
+pub struct Script {
+    commands: Vec<(usize, Command)>,
+}
+
+impl Script {
+    pub fn parse(text: &str) -> Result<Script, String> {
+        let mut commands = Vec::new();
+        for (n, raw) in text.lines().enumerate() {
+            let line = n + 1;
+            let err = |msg: String| format!("line {}: {}", line, msg);
+            let tokens = tokenize(raw).map_err(err)?;
+            let Some((Token::Word(name), args)) = tokens.split_first() else {
+                if tokens.is_empty() {
+                    continue;
+                }
+                return Err(err("expected a command".to_string()));
+            };
+            let command = parse_command(&name.to_ascii_lowercase(), args).map_err(err)?;
+            commands.push((line, command));
+        }
+        Ok(Script { commands })
+    }
+
+    /// Run the script against a machine with the in-memory console.
+    /// Serial output is copied to `out` as it arrives. `max_cycles`
+    /// (0 = none) caps the whole run. The error names the failing line.
+    pub fn run(&self, machine: &mut Machine, max_cycles: u64, out: &mut dyn Write) -> Result<(), String> {
+        let mut runner = Runner {
+            machine,
+            out,
+            max_cycles,
+            timeout: Limit::Cycles(DEFAULT_TIMEOUT_CYCLES),
+            output: Vec::new(),
+            matched: 0,
+        };
+        for (line, command) in &self.commands {
+            runner
+                .execute(command)
+                .map_err(|e| format!("line {}: {}", line, e))?;
+        }
+        Ok(())
+    }
+}
+
+//=============================================================================
+// Runner
+//=============================================================================
+
+struct Runner<'a> {
+    machine: &'a mut Machine,
+    out: &'a mut dyn Write,
+    max_cycles: u64,
+    timeout: Limit,
+    /// Everything the firmware has printed
+    output: Vec<u8>,
+    /// End of the last `expect` match in `output`
+    matched: usize,
+}
+
+impl Runner<'_> {
+    fn execute(&mut self, command: &Command) -> Result<(), String> {
+        match command {
+            Command::Timeout(limit) => self.timeout = *limit,
+            Command::Send(bytes) => {
+                for &c in bytes {
+                    self.machine.system.send_key(c);
+                }
+            }
+            Command::Expect(text, limit) => self.expect(text, limit.unwrap_or(self.timeout))?,
+            Command::Run(cycles) => {
+                let end = self.machine.total_cycles + cycles;
+                while self.machine.total_cycles < end && !self.machine.is_stopped() {
+                    self.step()?;
+                }
+            }
+            Command::AssertMem(addr, expected) => {
+                let actual: Vec<u8> = (0..expected.len())
+                    .map(|i| self.machine.cpu.mem.r8(addr.wrapping_add(i as u16) as i32) as u8)
+                    .collect();
+                if &actual != expected {
+                    return Err(format!(
+                        "memory at ${:04X} is {}, expected {}",
+                        addr,
+                        hex_bytes(&actual),
+                        hex_bytes(expected)
+                    ));
+                }
+            }
+            Command::AssertReg(name, expected) => {
+                let actual = expr::register(&self.machine.cpu, name).unwrap_or(0);
+                if actual != *expected {
+                    return Err(format!(
+                        "{} is ${:04X}, expected ${:04X}",
+                        name.to_ascii_uppercase(),
+                        actual,
+                        expected
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn expect(&mut self, text: &[u8], limit: Limit) -> Result<(), String> {
+        let start_cycles = self.machine.total_cycles;
+        let start_time = Instant::now();
+        let mut next_time_check = start_cycles + TIME_CHECK_CYCLES;
+        let mut searched = None;
+
+        loop {
+            // Only search again when there is new output
+            if searched != Some(self.output.len()) {
+                searched = Some(self.output.len());
+                if let Some(pos) = find(&self.output[self.matched..], text) {
+                    self.matched += pos + text.len();
+                    return Ok(());
+                }
+            }
+
+            let elapsed = self.machine.total_cycles - start_cycles;
+            let expired = match limit {
+                Limit::Cycles(n) => elapsed >= n,
+                Limit::Time(d) if self.machine.total_cycles >= next_time_check => {
+                    next_time_check = self.machine.total_cycles + TIME_CHECK_CYCLES;
+                    start_time.elapsed() >= d
+                }
+                Limit::Time(_) => false,
+            };
+            if expired {
+                return Err(format!(
+                    "expected \"{}\" within {}, got \"{}\"",
+                    escape(text),
+                    limit,
+                    escape(self.unmatched_tail())
+                ));
+            }
+            if self.machine.is_stopped() {
+                return Err(format!(
+                    "CPU halted at PC={:04X} while expecting \"{}\"",
+                    self.machine.cpu.reg.pc(),
+                    escape(text)
+                ));
+            }
+            self.step()?;
+        }
+    }
+
+    /// Execute one instruction and collect its output
+    fn step(&mut self) -> Result<(), String> {
+        let result = self.machine.step();
+        let bytes = self.machine.system.console.take_output();
+        if !bytes.is_empty() {
+            let _ = self.out.write_all(&bytes);
+            let _ = self.out.flush();
+            self.output.extend_from_slice(&bytes);
+        }
+
+        if let Err(trap) = result {
+            return Err(format!("{} after {} cycles", trap, self.machine.total_cycles));
+        }
+        if self.max_cycles > 0 && self.machine.total_cycles >= self.max_cycles {
+            return Err(format!("cycle limit of {} reached", self.max_cycles));
+        }
+        Ok(())
+    }
+
+    /// The last part of the output not yet matched, for error messages
+    fn unmatched_tail(&self) -> &[u8] {
+        let rest = &self.output[self.matched..];
+        &rest[rest.len().saturating_sub(60)..]
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ")
+}
+
+/// Show bytes the way a script would write them
+fn escape(bytes: &[u8]) -> String {
+    bytes.iter().map(|&c| match c {
+        b'\r' => "\\r".to_string(),
+        b'\n' => "\\n".to_string(),
+        b'\t' => "\\t".to_string(),
+        b'\\' => "\\\\".to_string(),
+        b'"' => "\\\"".to_string(),
+        0x20..=0x7E => (c as char).to_string(),
+        _ => format!("\\x{:02X}", c),
+    }).collect()
+}
+
+//=============================================================================
+// Parsing
+//=============================================================================
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Text(Vec<u8>),
+}
+
+fn parse_command(name: &str, args: &[Token]) -> Result<Command, String> {
+    let arity = |min: usize, max: usize| {
+        if (min..=max).contains(&args.len()) {
+            Ok(())
+        } else {
+            Err(format!("wrong number of arguments to {}", name))
+        }
+    };
+
+    Ok(match name {
+        "timeout" => {
+            arity(1, 1)?;
+            Command::Timeout(Limit::parse(word(&args[0])?)?)
+        }
+        "send" | "sendline" => {
+            arity(1, 1)?;
+            let mut bytes = text(&args[0])?;
+            if name == "sendline" {
+                bytes.push(b'\r');
+            }
+            Command::Send(bytes)
+        }
+        "expect" => {
+            arity(1, 2)?;
+            let limit = args.get(1).map(|a| word(a).and_then(Limit::parse)).transpose()?;
+            Command::Expect(text(&args[0])?, limit)
+        }
+        "run" => {
+            arity(1, 1)?;
+            Command::Run(number(&args[0], u64::MAX)?)
+        }
+        "assert_mem" => {
+            if args.len() < 2 {
+                return Err("assert_mem needs an address and at least one byte".to_string());
+            }
+            let addr = number(&args[0], 0xFFFF)? as u16;
+            let mut bytes = Vec::new();
+            for arg in &args[1..] {
+                match arg {
+                    Token::Text(t) => bytes.extend_from_slice(t),
+                    Token::Word(_) => bytes.push(number(arg, 0xFF)? as u8),
+                }
+            }
+            Command::AssertMem(addr, bytes)
+        }
+        "assert_reg" => {
+            arity(2, 2)?;
+            let reg = word(&args[0])?.to_ascii_lowercase();
+            let max = match reg.len() {
+                1 => 0xFF,
+                _ => 0xFFFF,
+            };
+            if !REGISTERS.contains(&reg.as_str()) {
+                return Err(format!("unknown register \"{}\"", reg));
+            }
+            Command::AssertReg(reg, number(&args[1], max)? as u16)
+        }
+        _ => return Err(format!("unknown command \"{}\"", name)),
+    })
+}
+
+fn word(token: &Token) -> Result<&str, String> {
+    match token {
+        Token::Word(w) => Ok(w),
+        Token::Text(_) => Err("expected a word, not a string".to_string()),
+    }
+}
+
+fn text(token: &Token) -> Result<Vec<u8>, String> {
+    match token {
+        Token::Text(t) => Ok(t.clone()),
+        Token::Word(w) => Err(format!("expected a quoted string, not {}", w)),
+    }
+}
+
+fn number(token: &Token, max: u64) -> Result<u64, String> {
+    let w = word(token)?;
+    match parse_number(w) {
+        Some(n) if n <= max => Ok(n),
+        Some(_) => Err(format!("{} is out of range", w)),
+        None => Err(format!("invalid number \"{}\"", w)),
+    }
+}
+
+/// Decimal, `$`/`0x` hex (with optional `_` separators)
+fn parse_number(s: &str) -> Option<u64> {
+    let n = match s.strip_prefix('$') {
+        Some(hex) => parse_int(&format!("0x{}", hex))?,
+        None => parse_int(s)?,
+    };
+    u64::try_from(n).ok()
+}
+
+/// Split a line into words and quoted strings, dropping any comment
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            break;
+        } else if c == '"' {
+            chars.next();
+            let mut bytes = Vec::new();
+            loop {
+                match chars.next() {
+                    None => return Err("unterminated string".to_string()),
+                    Some('"') => break,
+                    Some('\\') => bytes.push(unescape(&mut chars)?),
+                    Some(c) => {
+                        let mut buf = [0; 4];
+                        bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                    }
+                }
+            }
+            tokens.push(Token::Text(bytes));
+        } else {
+            let mut w = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '"' || c == '#' {
+                    break;
+                }
+                w.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Word(w));
+        }
+    }
+    Ok(tokens)
+}
+
+fn unescape(chars: &mut impl Iterator<Item = char>) -> Result<u8, String> {
+    Ok(match chars.next() {
+        Some('r') => b'\r',
+        Some('n') => b'\n',
+        Some('t') => b'\t',
+        Some('e') => 0x1B,
+        Some('0') => 0,
+        Some('\\') => b'\\',
+        Some('"') => b'"',
+        Some('x') => {
+            let hex: String = chars.take(2).collect();
+            u8::from_str_radix(&hex, 16).map_err(|_| format!("invalid escape \\x{}", hex))?
+        }
+        Some(c) => return Err(format!("invalid escape \\{}", c)),
+        None => return Err("unterminated string".to_string()),
+    })
+}
@@ -1,6 +1,6 @@
 //! SD Card Emulation
 //!
-//! Emulates SD card storage via I/O ports 0x10-0x18.
+//! Emulates SD card storage via ten consecutive I/O ports ($10-$19 by default).
 //! Includes DMA block transfer support for CP/M disk operations.
 
 use std::cell::RefCell;
@@ -8,19 +8,22 @@ use std::fs::{self, File, OpenOptions, ReadDir};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::PathBuf;
 
-/// SD Card I/O ports
-pub const SD_CMD_PORT: u8 = 0x10;
-pub const SD_STATUS_PORT: u8 = 0x11;
-pub const SD_DATA_PORT: u8 = 0x12;
-pub const SD_FNAME_PORT: u8 = 0x13;
-pub const SD_SEEK_LO: u8 = 0x14;
-pub const SD_SEEK_HI: u8 = 0x15;
+/// SD Card registers (offsets from the base port)
+pub const SD_CMD: u8 = 0;
+pub const SD_STATUS: u8 = 1;
+pub const SD_DATA: u8 = 2;
+pub const SD_FNAME: u8 = 3;
+pub const SD_SEEK_LO: u8 = 4;
+pub const SD_SEEK_HI: u8 = 5;
 
-/// DMA block transfer ports (for CP/M)
-pub const SD_DMA_LO: u8 = 0x16;      // DMA address low byte
-pub const SD_DMA_HI: u8 = 0x17;      // DMA address high byte
-pub const SD_BLOCK_CMD: u8 = 0x18;   // Block command: 0=read 128 bytes, 1=write 128 bytes
-pub const SD_SEEK_EX: u8 = 0x19;     // Seek position extended byte (bits 16-23)
+/// DMA block transfer registers (for CP/M)
+pub const SD_DMA_LO: u8 = 6;      // DMA address low byte
+pub const SD_DMA_HI: u8 = 7;      // DMA address high byte
+pub const SD_BLOCK_CMD: u8 = 8;   // Block command: 0=read 128 bytes, 1=write 128 bytes
+pub const SD_SEEK_EX: u8 = 9;     // Seek position extended byte (bits 16-23)
+
+/// Number of consecutive ports decoded by the SD card
+pub const SD_PORT_COUNT: u8 = 10;
 
 /// Block size for DMA transfers
 pub const BLOCK_SIZE: usize = 128;
@@ -118,9 +121,7 @@ impl SdCard {
             match file.read(&mut buffer) {
                 Ok(bytes_read) => {
                     // Fill remaining with zeros if less than BLOCK_SIZE
-                    for i in bytes_read..BLOCK_SIZE {
-                        buffer[i] = 0;
-                    }
+                    buffer[bytes_read..].fill(0);
 
                     // Copy to CPU memory at DMA address
                     let dma = state.dma_addr as usize;
@@ -128,9 +129,9 @@ impl SdCard {
                     // Safety: We trust the caller set up valid memory
                     unsafe {
                         let mem = &mut *mem_ptr.unwrap();
-                        for i in 0..BLOCK_SIZE {
+                        for (i, &byte) in buffer.iter().enumerate() {
                             if dma + i < 0x10000 {
-                                mem.w8((dma + i) as i32, buffer[i] as i32);
+                                mem.w8((dma + i) as i32, byte as i32);
                             }
                         }
                     }
@@ -174,9 +175,9 @@ impl SdCard {
             // Safety: We trust the caller set up valid memory
             unsafe {
                 let mem = &*mem_ptr.unwrap();
-                for i in 0..BLOCK_SIZE {
+                for (i, byte) in buffer.iter_mut().enumerate() {
                     if dma + i < 0x10000 {
-                        buffer[i] = mem.r8((dma + i) as i32) as u8;
+                        *byte = mem.r8((dma + i) as i32) as u8;
                     }
                 }
             }
@@ -207,19 +208,19 @@ impl SdCard {
         self.storage_dir.join(filename)
     }
 
-    /// Handle port read
-    pub fn read_port(&self, port: u8) -> u8 {
+    /// Handle register read
+    pub fn read_reg(&self, reg: u8) -> u8 {
         let mut state = self.state.borrow_mut();
 
-        match port {
-            SD_STATUS_PORT => {
+        match reg {
+            SD_STATUS => {
                 let mut status = state.status;
                 if state.file.is_some() || state.dir.is_some() {
                     status |= STATUS_DATA;
                 }
                 status
             }
-            SD_DATA_PORT => {
+            SD_DATA => {
                 // Read from file
                 if let Some(ref mut file) = state.file {
                     let mut buf = [0u8; 1];
@@ -276,20 +277,20 @@ impl SdCard {
         }
     }
 
-    /// Handle port write
-    pub fn write_port(&self, port: u8, val: u8) {
+    /// Handle register write
+    pub fn write_reg(&self, reg: u8, val: u8) {
         let mut state = self.state.borrow_mut();
 
-        match port {
-            SD_CMD_PORT => {
+        match reg {
+            SD_CMD => {
                 self.handle_command(&mut state, val);
             }
-            SD_DATA_PORT => {
+            SD_DATA => {
                 if let Some(ref mut file) = state.file {
                     let _ = file.write_all(&[val]);
                 }
             }
-            SD_FNAME_PORT => {
+            SD_FNAME => {
                 if val == 0 {
                     // Null terminator - filename complete
                     state.filename_pos = 0;
@@ -319,7 +320,7 @@ impl SdCard {
                     eprintln!("[SD] Seek position ext: {:02X} (pos={})", val, state.seek_pos);
                 }
             }
-            // DMA address ports
+            // DMA address registers
             SD_DMA_LO => {
                 state.dma_addr = (state.dma_addr & 0xFF00) | (val as u16);
                 if self.debug {
@@ -487,10 +488,4 @@ impl SdCard {
             _ => {}
         }
     }
-
-    /// Check if this port is handled by SD emulation
-    pub fn handles_port(port: u8) -> bool {
-        matches!(port, SD_CMD_PORT | SD_STATUS_PORT | SD_DATA_PORT | SD_FNAME_PORT |
-                       SD_SEEK_LO | SD_SEEK_HI | SD_SEEK_EX | SD_DMA_LO | SD_DMA_HI | SD_BLOCK_CMD)
-    }
 }
@@ -1,106 +1,539 @@
 //! Serial chip emulation for RetroShield
 //!
-//! Implements MC6850 ACIA and Intel 8251 USART
+//! Implements MC6850 ACIA and Intel 8251 USART. Both chips are wired to a
+//! shared `Console`, the host end of the line. The console exchanges bytes
+//! with a `CharDevice` (an in-memory queue unless a front-end attaches
+//! stdin, a file, a pipe or a socket), and front-ends can also type into
+//! it directly. Input passes through the console's `InputFilter` first.
+//!
+//! By default characters move instantly: a byte written to the transmit
+//! register appears on the console at once, and console input is latched
+//! as soon as the receive register is empty. With a `SerialTiming` set, the
+//! shift registers instead take a full frame time per character at the
+//! configured baud rate, measured in CPU cycles passed to `tick`. The host
+//! then sends back to back, and firmware that polls too slowly sees
+//! receive overruns.
 
 use std::cell::RefCell;
 use std::collections::VecDeque;
-use std::io::{self, Read};
-
-/// Check if input is available on stdin (non-blocking)
-fn stdin_has_data() -> bool {
-    // On Unix, we can use select() or poll()
-    // For simplicity, we'll check if stdin is a tty and has data
-    #[cfg(unix)]
-    {
-        use std::os::unix::io::AsRawFd;
-
-        let fd = io::stdin().as_raw_fd();
-        unsafe {
-            let mut fds: libc::fd_set = std::mem::zeroed();
-            libc::FD_ZERO(&mut fds);
-            libc::FD_SET(fd, &mut fds);
-
-            let mut tv = libc::timeval {
-                tv_sec: 0,
-                tv_usec: 0,
-            };
+use std::rc::Rc;
 
-            libc::select(fd + 1, &mut fds, std::ptr::null_mut(), std::ptr::null_mut(), &mut tv) > 0
+use crate::chardev::{CharDevice, QueueDevice};
+
+//=============================================================================
+// Input filter
+//=============================================================================
+
+/// What the Enter key sends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEnding {
+    /// Pass CR and LF through unchanged
+    #[default]
+    Keep,
+    Cr,
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    /// Parse "keep", "cr", "lf" or "crlf"
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "keep" => Ok(LineEnding::Keep),
+            "cr" => Ok(LineEnding::Cr),
+            "lf" => Ok(LineEnding::Lf),
+            "crlf" => Ok(LineEnding::CrLf),
+            other => Err(format!("unknown line ending \"{}\"", other)),
         }
     }
 
-    #[cfg(not(unix))]
-    {
-        false // TODO: Windows implementation
+    fn bytes(self) -> &'static [u8] {
+        match self {
+            LineEnding::Keep => &[],
+            LineEnding::Cr => b"\r",
+            LineEnding::Lf => b"\n",
+            LineEnding::CrLf => b"\r\n",
+        }
     }
 }
 
-/// Read a character from stdin (non-blocking)
-fn read_char() -> Option<u8> {
-    if stdin_has_data() {
-        let mut buf = [0u8; 1];
-        if io::stdin().read(&mut buf).is_ok() {
-            if buf[0] != 0 {
-                return Some(buf[0]);
+/// What the backspace and delete keys send
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backspace {
+    /// Pass BS and DEL through unchanged
+    #[default]
+    Keep,
+    /// Both send BS ($08)
+    Bs,
+    /// Both send DEL ($7F)
+    Del,
+}
+
+impl Backspace {
+    /// Parse "keep", "bs" or "del"
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "keep" => Ok(Backspace::Keep),
+            "bs" => Ok(Backspace::Bs),
+            "del" => Ok(Backspace::Del),
+            other => Err(format!("unknown backspace mapping \"{}\"", other)),
+        }
+    }
+}
+
+/// Transforms applied to host input before any serial chip sees it. The
+/// default passes every byte through unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InputFilter {
+    /// Fold a-z to A-Z (what the RetroShield Arduino 8251 code did)
+    pub upcase: bool,
+    pub line_ending: LineEnding,
+    /// Clear bit 7 of every byte
+    pub strip_bit7: bool,
+    pub backspace: Backspace,
+}
+
+impl InputFilter {
+    /// Change settings from a comma-separated list: `upcase`, `no-upcase`,
+    /// `strip7`, `no-strip7`, `eol=keep|cr|lf|crlf`, `bs=keep|bs|del`, or
+    /// `raw` to turn everything off. Settings not named are left alone.
+    pub fn apply_spec(&mut self, spec: &str) -> Result<(), String> {
+        for item in spec.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            match item.split_once('=') {
+                Some(("eol", v)) => self.line_ending = LineEnding::parse(v)?,
+                Some(("bs", v)) => self.backspace = Backspace::parse(v)?,
+                _ => match item {
+                    "upcase" => self.upcase = true,
+                    "no-upcase" => self.upcase = false,
+                    "strip7" => self.strip_bit7 = true,
+                    "no-strip7" => self.strip_bit7 = false,
+                    "raw" => *self = InputFilter::default(),
+                    other => return Err(format!("unknown input filter option \"{}\"", other)),
+                },
             }
         }
+        Ok(())
     }
-    None
 }
 
+//=============================================================================
+// Console (host side of the serial line)
+//=============================================================================
+
+/// Host side of the serial line: filtered input waiting for the firmware,
+/// and the device that supplies input and takes output.
+pub struct Console {
+    rx: RefCell<VecDeque<u8>>,
+    device: RefCell<Box<dyn CharDevice>>,
+    /// The built-in queue device, used until another device is attached
+    queue: QueueDevice,
+    filter: RefCell<InputFilter>,
+    /// Last input byte was CR, so a following LF completes the same line
+    after_cr: RefCell<bool>,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        let queue = QueueDevice::new();
+        Self {
+            rx: RefCell::new(VecDeque::new()),
+            device: RefCell::new(Box::new(queue.clone())),
+            queue,
+            filter: RefCell::new(InputFilter::default()),
+            after_cr: RefCell::new(false),
+        }
+    }
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Exchange bytes with another device instead of the built-in queue
+    pub fn set_device(&self, device: Box<dyn CharDevice>) {
+        *self.device.borrow_mut() = device;
+    }
+
+    /// The built-in queue device (idle once another device is attached)
+    pub fn queue(&self) -> &QueueDevice {
+        &self.queue
+    }
+
+    /// Flush the device's output, and take its next input byte unless
+    /// earlier input is still waiting for the firmware
+    pub fn poll(&self) {
+        let mut device = self.device.borrow_mut();
+        device.flush();
+        if self.rx.borrow().is_empty() {
+            if let Some(c) = device.read() {
+                self.push_input(c);
+            }
+        }
+    }
+
+    /// Flush the device's output
+    pub fn flush(&self) {
+        self.device.borrow_mut().flush();
+    }
+
+    /// Input transforms in use
+    pub fn filter(&self) -> InputFilter {
+        *self.filter.borrow()
+    }
+
+    /// Replace the input transforms
+    pub fn set_filter(&self, filter: InputFilter) {
+        *self.filter.borrow_mut() = filter;
+        *self.after_cr.borrow_mut() = false;
+    }
+
+    /// Queue a byte for the emulated system to receive, after filtering
+    pub fn push_input(&self, c: u8) {
+        let filter = *self.filter.borrow();
+        let mut rx = self.rx.borrow_mut();
+
+        let c = if filter.strip_bit7 { c & 0x7F } else { c };
+        let after_cr = self.after_cr.replace(c == b'\r');
+
+        match c {
+            b'\r' | b'\n' if filter.line_ending != LineEnding::Keep => {
+                // CR, LF and CR LF are all one Enter
+                if !(c == b'\n' && after_cr) {
+                    rx.extend(filter.line_ending.bytes());
+                }
+            }
+            0x08 | 0x7F if filter.backspace == Backspace::Bs => rx.push_back(0x08),
+            0x08 | 0x7F if filter.backspace == Backspace::Del => rx.push_back(0x7F),
+            c if filter.upcase => rx.push_back(c.to_ascii_uppercase()),
+            c => rx.push_back(c),
+        }
+    }
+
+    /// Check if received data is waiting to be read by the firmware
+    pub fn input_available(&self) -> bool {
+        !self.rx.borrow().is_empty()
+    }
+
+    /// Take the next received byte
+    pub fn read_input(&self) -> Option<u8> {
+        self.rx.borrow_mut().pop_front()
+    }
+
+    /// Send a byte transmitted by the firmware to the device
+    pub fn write_output(&self, c: u8) {
+        self.device.borrow_mut().write(c);
+    }
+
+    /// Take the next transmitted byte from the built-in queue
+    pub fn pop_output(&self) -> Option<u8> {
+        self.queue.pop_output()
+    }
+
+    /// Take all transmitted bytes from the built-in queue
+    pub fn take_output(&self) -> Vec<u8> {
+        self.queue.take_output()
+    }
+
+    /// Get number of output bytes pending in the built-in queue
+    pub fn pending_output(&self) -> usize {
+        self.queue.pending_output()
+    }
+
+    /// Discard any queued input and output
+    pub fn clear(&self) {
+        self.rx.borrow_mut().clear();
+        self.queue.clear();
+        *self.after_cr.borrow_mut() = false;
+    }
+}
+
+//=============================================================================
+// Baud-rate timing
+//=============================================================================
+
+/// Line speed and CPU clock for the baud-rate timing mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialTiming {
+    pub baud: u32,
+    pub clock_hz: u32,
+}
+
+impl SerialTiming {
+    /// CPU cycles to shift a frame lasting `half_bits` half bit times
+    /// (half bits so that 1.5 stop bits can be expressed)
+    fn frame_cycles(&self, half_bits: u32) -> u64 {
+        (self.clock_hz as u64 * half_bits as u64).div_ceil(2 * self.baud as u64)
+    }
+}
+
+/// Character in a shift register and the cycle at which its frame ends
+type Shifting = Option<(u8, u64)>;
+
 //=============================================================================
 // MC6850 ACIA (Asynchronous Communications Interface Adapter)
 //=============================================================================
 
-/// MC6850 status register bits
+/// MC6850 status register bits. The host end of the line never garbles a
+/// frame, so framing (FE) and parity (PE) errors are never reported, and
+/// DCD and CTS are tied low.
 const ACIA_RDRF: u8 = 0x01;  // Receive Data Register Full
 const ACIA_TDRE: u8 = 0x02;  // Transmit Data Register Empty
+const ACIA_OVRN: u8 = 0x20;  // Receiver Overrun
+const ACIA_IRQ: u8  = 0x80;  // Interrupt Request (state of /IRQ)
+
+/// MC6850 control register fields
+const ACIA_CR_DIVIDE: u8 = 0x03;        // CR1:0 counter divide select
+const ACIA_MASTER_RESET: u8 = 0x03;     // CR1:0 = 11
+const ACIA_CR_WORD: u8 = 0x1C;          // CR4:2 word select
+const ACIA_CR_TX: u8 = 0x60;            // CR6:5 transmitter control
+const ACIA_TX_RTS_LOW_TIE: u8 = 0x20;   // RTS low, transmit interrupt enabled
+const ACIA_TX_RTS_HIGH: u8 = 0x40;      // RTS high, transmit interrupt disabled
+const ACIA_CR_RIE: u8 = 0x80;           // CR7 receive interrupt enable
+
+/// MC6850 register state
+struct AciaState {
+    control: u8,
+    /// Held in master reset: nothing is received or transmitted
+    in_reset: bool,
+    rdr: u8,
+    rdrf: bool,
+    overrun: bool,
+    /// Transmit data register (timing mode only)
+    tdr: Option<u8>,
+    tx_shift: Shifting,
+    rx_shift: Shifting,
+}
+
+impl Default for AciaState {
+    /// Power-on state. A bare MC6850 stays in reset until the firmware
+    /// writes a master reset, but the RetroShield's ACIA comes up ready, and
+    /// some bundled ROMs (Firth, Grant Searle's BASIC) rely on that.
+    fn default() -> Self {
+        Self {
+            control: 0,
+            in_reset: false,
+            rdr: 0,
+            rdrf: false,
+            overrun: false,
+            tdr: None,
+            tx_shift: None,
+            rx_shift: None,
+        }
+    }
+}
+
+impl AciaState {
+    /// Frame length from the word select bits: start bit, 7 or 8 data
+    /// bits, optional parity and 1 or 2 stop bits
+    fn frame_half_bits(&self) -> u32 {
+        let bits = match (self.control & ACIA_CR_WORD) >> 2 {
+            0 | 1 => 1 + 7 + 1 + 2,  // 7 bits, parity, 2 stop
+            2 | 3 => 1 + 7 + 1 + 1,  // 7 bits, parity, 1 stop
+            4 => 1 + 8 + 2,          // 8 bits, 2 stop
+            5 => 1 + 8 + 1,          // 8 bits, 1 stop
+            _ => 1 + 8 + 1 + 1,      // 8 bits, parity, 1 stop
+        };
+        bits * 2
+    }
+}
 
 /// MC6850 ACIA emulation
-#[allow(dead_code)]
 pub struct Mc6850 {
-    control: RefCell<u8>,
-    rx_buffer: RefCell<VecDeque<u8>>,
+    state: RefCell<AciaState>,
+    console: Rc<Console>,
+    timing: RefCell<Option<SerialTiming>>,
+    /// CPU cycles elapsed, for timing mode
+    now: RefCell<u64>,
 }
 
 impl Mc6850 {
-    pub fn new() -> Self {
+    pub fn new(console: Rc<Console>) -> Self {
         Self {
-            control: RefCell::new(0),
-            rx_buffer: RefCell::new(VecDeque::new()),
+            state: RefCell::new(AciaState::default()),
+            console,
+            timing: RefCell::new(None),
+            now: RefCell::new(0),
         }
     }
 
-    /// Read status register (port $80)
-    pub fn read_status(&self) -> u8 {
-        let mut status = ACIA_TDRE; // Always ready to transmit
+    /// Return to the power-on state
+    pub fn reset(&self) {
+        *self.state.borrow_mut() = AciaState::default();
+    }
+
+    /// Time characters at a baud rate, or move them instantly (`None`)
+    pub fn set_timing(&self, timing: Option<SerialTiming>) {
+        *self.timing.borrow_mut() = timing;
+    }
+
+    /// Move the next console byte into an empty receive data register.
+    /// The host honours flow control and holds input while RTS is high.
+    /// In timing mode bytes only arrive through the receive shift register.
+    fn fill_rdr(&self, state: &mut AciaState) {
+        if state.rdrf || !Self::rts_asserted(state) || self.timing.borrow().is_some() {
+            return;
+        }
+        if let Some(c) = self.console.read_input() {
+            state.rdr = c;
+            state.rdrf = true;
+        }
+    }
+
+    /// Latch a character from the receive shift register. If the previous
+    /// one has not been read yet, the new one is lost and the overrun flag
+    /// is set.
+    fn latch(state: &mut AciaState, c: u8) {
+        if state.rdrf {
+            state.overrun = true;
+        } else {
+            state.rdr = c;
+            state.rdrf = true;
+        }
+    }
+
+    /// Move the transmit data register into an idle shift register
+    fn start_tx(state: &mut AciaState, timing: &SerialTiming, now: u64) {
+        if state.tx_shift.is_none() {
+            if let Some(c) = state.tdr.take() {
+                state.tx_shift = Some((c, now + timing.frame_cycles(state.frame_half_bits())));
+            }
+        }
+    }
 
-        // Check for input
-        if let Some(c) = read_char() {
-            self.rx_buffer.borrow_mut().push_back(c);
+    /// Advance the shift registers by `cycles` CPU cycles (timing mode)
+    pub fn tick(&self, cycles: u32) {
+        let Some(timing) = *self.timing.borrow() else {
+            return;
+        };
+        let now = {
+            let mut now = self.now.borrow_mut();
+            *now += cycles as u64;
+            *now
+        };
+        let mut state = self.state.borrow_mut();
+        if state.in_reset {
+            return;
         }
 
-        if !self.rx_buffer.borrow().is_empty() {
+        while let Some((c, done)) = state.tx_shift {
+            if now < done {
+                break;
+            }
+            self.console.write_output(c);
+            state.tx_shift = None;
+            Self::start_tx(&mut state, &timing, done);
+        }
+
+        // The host sends back to back, starting as soon as the line is idle
+        let mut idle_since = now - cycles as u64;
+        loop {
+            match state.rx_shift {
+                Some((c, done)) if done <= now => {
+                    Self::latch(&mut state, c);
+                    state.rx_shift = None;
+                    idle_since = done;
+                }
+                Some(_) => break,
+                None => {
+                    if !Self::rts_asserted(&state) {
+                        break;
+                    }
+                    let Some(c) = self.console.read_input() else {
+                        break;
+                    };
+                    let done = idle_since + timing.frame_cycles(state.frame_half_bits());
+                    state.rx_shift = Some((c, done));
+                }
+            }
+        }
+    }
+
+    fn status(&self, state: &AciaState) -> u8 {
+        if state.in_reset {
+            return 0;
+        }
+
+        let mut status = 0;
+        if state.tdr.is_none() {
+            status |= ACIA_TDRE;
+        }
+        if state.rdrf {
             status |= ACIA_RDRF;
         }
+        if state.overrun {
+            status |= ACIA_OVRN;
+        }
+
+        let rx_irq = state.control & ACIA_CR_RIE != 0 && (state.rdrf || state.overrun);
+        let tx_irq = state.control & ACIA_CR_TX == ACIA_TX_RTS_LOW_TIE && status & ACIA_TDRE != 0;
+        if rx_irq || tx_irq {
+            status |= ACIA_IRQ;
+        }
 
         status
     }
 
-    /// Read data register (port $81)
+    /// Read status register (control port, $80 by default)
+    pub fn read_status(&self) -> u8 {
+        let mut state = self.state.borrow_mut();
+        self.fill_rdr(&mut state);
+        self.status(&state)
+    }
+
+    /// Read data register (data port, $81 by default). Clears RDRF and
+    /// the overrun flag.
     pub fn read_data(&self) -> u8 {
-        // Check for new input first
-        if let Some(c) = read_char() {
-            self.rx_buffer.borrow_mut().push_back(c);
+        let mut state = self.state.borrow_mut();
+        self.fill_rdr(&mut state);
+        let c = state.rdr;
+        state.rdrf = false;
+        state.overrun = false;
+        c
+    }
+
+    /// Write control register (control port, $80 by default)
+    pub fn write_control(&self, val: u8) {
+        let mut state = self.state.borrow_mut();
+        if val & ACIA_CR_DIVIDE == ACIA_MASTER_RESET {
+            // Master reset clears the status and holds the chip in reset
+            *state = AciaState {
+                control: val,
+                in_reset: true,
+                ..AciaState::default()
+            };
+        } else {
+            state.control = val;
+            state.in_reset = false;
+        }
+    }
+
+    /// Write data register (data port, $81 by default)
+    pub fn write_data(&self, val: u8) {
+        let mut state = self.state.borrow_mut();
+        if state.in_reset {
+            return;
+        }
+        match *self.timing.borrow() {
+            Some(timing) => {
+                // Overwrites a character still waiting for the shift register
+                state.tdr = Some(val);
+                Self::start_tx(&mut state, &timing, *self.now.borrow());
+            }
+            None => self.console.write_output(val),
         }
+    }
 
-        self.rx_buffer.borrow_mut().pop_front().unwrap_or(0)
+    /// Check if /IRQ is asserted (receive or transmit interrupt condition)
+    pub fn irq_pending(&self) -> bool {
+        let mut state = self.state.borrow_mut();
+        self.fill_rdr(&mut state);
+        self.status(&state) & ACIA_IRQ != 0
     }
 
-    /// Write control register (port $80)
-    #[allow(dead_code)]
-    pub fn write_control(&self, val: u8) {
-        *self.control.borrow_mut() = val;
+    /// Check if /RTS is asserted (low), i.e. the firmware is ready to receive
+    fn rts_asserted(state: &AciaState) -> bool {
+        !state.in_reset && state.control & ACIA_CR_TX != ACIA_TX_RTS_HIGH
     }
 }
 
@@ -108,68 +541,343 @@ impl Mc6850 {
 // Intel 8251 USART (Universal Synchronous/Asynchronous Receiver/Transmitter)
 //=============================================================================
 
-/// Intel 8251 status register bits
+/// Intel 8251 status register bits. As with the ACIA, parity (PE) and
+/// framing (FE) errors are never reported.
 const STAT_8251_TXRDY: u8 = 0x01;  // Transmitter Ready
 const STAT_8251_RXRDY: u8 = 0x02;  // Receiver Ready
 const STAT_8251_TXE: u8   = 0x04;  // Transmitter Empty
+const STAT_8251_OE: u8    = 0x10;  // Overrun Error
+const STAT_SYNDET: u8     = 0x40;  // Sync Detect
 const STAT_DSR: u8        = 0x80;  // Data Set Ready
 
-/// Initial status: TxRDY + TxE + DSR
-const USART_STATUS_INIT: u8 = STAT_8251_TXRDY | STAT_8251_TXE | STAT_DSR;
+/// Intel 8251 mode instruction fields
+const MODE_BAUD_FACTOR: u8 = 0x03;  // 00 = synchronous mode
+const MODE_CHAR_LEN: u8    = 0x0C;  // 5 + n data bits
+const MODE_PEN: u8         = 0x10;  // Parity enable
+const MODE_STOP: u8        = 0xC0;  // Async mode: 1, 1.5 or 2 stop bits
+const MODE_ESD: u8         = 0x40;  // Sync mode: external sync detect
+const MODE_SCS: u8         = 0x80;  // Sync mode: single sync character
+
+/// Intel 8251 command instruction bits
+const CMD_TXEN: u8 = 0x01;  // Transmit Enable
+const CMD_RXE: u8  = 0x04;  // Receive Enable
+const CMD_ER: u8   = 0x10;  // Error Reset
+const CMD_IR: u8   = 0x40;  // Internal Reset
+const CMD_EH: u8   = 0x80;  // Enter Hunt (sync mode)
+
+/// What the next control port write programs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UsartPhase {
+    Mode,
+    FirstSync,
+    SecondSync,
+    Command,
+}
+
+/// Intel 8251 register state
+struct UsartState {
+    phase: UsartPhase,
+    mode: u8,
+    sync_chars: [u8; 2],
+    command: u8,
+    /// Sync mode receiver searching for the sync character(s)
+    hunting: bool,
+    /// Sync characters matched so far while hunting
+    sync_matched: usize,
+    sync_detected: bool,
+    rx_data: u8,
+    rx_ready: bool,
+    tx_data: Option<u8>,
+    tx_shift: Shifting,
+    rx_shift: Shifting,
+    overrun: bool,
+}
+
+impl Default for UsartState {
+    /// State after a hardware or internal reset: expecting a mode instruction
+    fn default() -> Self {
+        Self {
+            phase: UsartPhase::Mode,
+            mode: 0,
+            sync_chars: [0; 2],
+            command: 0,
+            hunting: false,
+            sync_matched: 0,
+            sync_detected: false,
+            rx_data: 0,
+            rx_ready: false,
+            tx_data: None,
+            tx_shift: None,
+            rx_shift: None,
+            overrun: false,
+        }
+    }
+}
+
+impl UsartState {
+    fn sync_mode(&self) -> bool {
+        self.mode & MODE_BAUD_FACTOR == 0
+    }
+
+    fn sync_char_count(&self) -> usize {
+        if self.mode & MODE_SCS != 0 { 1 } else { 2 }
+    }
+
+    fn rx_enabled(&self) -> bool {
+        self.phase == UsartPhase::Command && self.command & CMD_RXE != 0
+    }
+
+    fn tx_enabled(&self) -> bool {
+        self.phase == UsartPhase::Command && self.command & CMD_TXEN != 0
+    }
+
+    /// Frame length from the mode instruction. Sync mode frames have no
+    /// start or stop bits.
+    fn frame_half_bits(&self) -> u32 {
+        let mut half_bits = 2 * (5 + ((self.mode & MODE_CHAR_LEN) >> 2) as u32);
+        if self.mode & MODE_PEN != 0 {
+            half_bits += 2;
+        }
+        if !self.sync_mode() {
+            // Start bit plus 1, 1.5 or 2 stop bits (00 is invalid, taken as 1)
+            half_bits += 2 + match (self.mode & MODE_STOP) >> 6 {
+                2 => 3,
+                3 => 4,
+                _ => 2,
+            };
+        }
+        half_bits
+    }
+}
 
 /// Intel 8251 USART emulation
-#[allow(dead_code)]
 pub struct Intel8251 {
-    mode: RefCell<u8>,
-    command: RefCell<u8>,
-    rx_buffer: RefCell<VecDeque<u8>>,
+    state: RefCell<UsartState>,
+    console: Rc<Console>,
+    timing: RefCell<Option<SerialTiming>>,
+    /// CPU cycles elapsed, for timing mode
+    now: RefCell<u64>,
 }
 
 impl Intel8251 {
-    pub fn new() -> Self {
+    pub fn new(console: Rc<Console>) -> Self {
         Self {
-            mode: RefCell::new(0),
-            command: RefCell::new(0),
-            rx_buffer: RefCell::new(VecDeque::new()),
+            state: RefCell::new(UsartState::default()),
+            console,
+            timing: RefCell::new(None),
+            now: RefCell::new(0),
         }
     }
 
-    /// Read status register (port $01)
-    pub fn read_status(&self) -> u8 {
-        let mut status = USART_STATUS_INIT;
+    /// Hardware reset
+    pub fn reset(&self) {
+        *self.state.borrow_mut() = UsartState::default();
+    }
+
+    /// Time characters at a baud rate, or move them instantly (`None`)
+    pub fn set_timing(&self, timing: Option<SerialTiming>) {
+        *self.timing.borrow_mut() = timing;
+    }
 
-        // Check for input
-        if let Some(c) = read_char() {
-            self.rx_buffer.borrow_mut().push_back(c);
+    /// Advance the shift registers by `cycles` CPU cycles (timing mode)
+    pub fn tick(&self, cycles: u32) {
+        let Some(timing) = *self.timing.borrow() else {
+            return;
+        };
+        let now = {
+            let mut now = self.now.borrow_mut();
+            *now += cycles as u64;
+            *now
+        };
+        let mut state = self.state.borrow_mut();
+
+        while let Some((c, done)) = state.tx_shift {
+            if now < done {
+                break;
+            }
+            self.console.write_output(c);
+            state.tx_shift = None;
+            self.transmit(&mut state, done);
         }
 
-        if !self.rx_buffer.borrow().is_empty() {
+        // The host sends back to back, starting as soon as the line is idle
+        let mut idle_since = now - cycles as u64;
+        loop {
+            match state.rx_shift {
+                Some((c, done)) if done <= now => {
+                    state.rx_shift = None;
+                    // A character still arriving when RxE is cleared is lost
+                    if state.rx_enabled() {
+                        Self::shift_in(&mut state, c);
+                    }
+                    idle_since = done;
+                }
+                Some(_) => break,
+                None => {
+                    if !state.rx_enabled() {
+                        break;
+                    }
+                    let Some(c) = self.console.read_input() else {
+                        break;
+                    };
+                    let done = idle_since + timing.frame_cycles(state.frame_half_bits());
+                    state.rx_shift = Some((c, done));
+                }
+            }
+        }
+    }
+
+    /// Assemble a received character. While hunting in sync mode it is
+    /// only compared against the sync characters; otherwise it goes to the
+    /// receive buffer, overwriting (and flagging an overrun on) any
+    /// character the firmware has not read yet.
+    fn shift_in(state: &mut UsartState, c: u8) {
+        if state.hunting {
+            // External sync detect is driven by a pin nothing is wired to
+            if state.mode & MODE_ESD != 0 {
+                return;
+            }
+            if c == state.sync_chars[state.sync_matched] {
+                state.sync_matched += 1;
+                if state.sync_matched == state.sync_char_count() {
+                    state.hunting = false;
+                    state.sync_detected = true;
+                }
+            } else {
+                state.sync_matched = 0;
+            }
+            return;
+        }
+
+        if state.rx_ready {
+            state.overrun = true;
+        }
+        state.rx_data = c;
+        state.rx_ready = true;
+    }
+
+    /// Move the next console byte into an empty receive buffer. In timing
+    /// mode bytes only arrive through the receive shift register.
+    fn fill_rx(&self, state: &mut UsartState) {
+        if self.timing.borrow().is_some() {
+            return;
+        }
+        while state.rx_enabled() && !state.rx_ready {
+            match self.console.read_input() {
+                Some(c) => Self::shift_in(state, c),
+                None => break,
+            }
+        }
+    }
+
+    /// Read status register (control port, $01 by default)
+    pub fn read_status(&self) -> u8 {
+        let mut state = self.state.borrow_mut();
+        self.fill_rx(&mut state);
+
+        let mut status = STAT_DSR;
+        if state.tx_data.is_none() {
+            status |= STAT_8251_TXRDY;
+            if state.tx_shift.is_none() {
+                status |= STAT_8251_TXE;
+            }
+        }
+        if state.rx_ready {
             status |= STAT_8251_RXRDY;
         }
+        if state.overrun {
+            status |= STAT_8251_OE;
+        }
+        if state.sync_detected {
+            status |= STAT_SYNDET;
+            // SYNDET is cleared by reading the status
+            state.sync_detected = false;
+        }
 
         status
     }
 
-    /// Read data register (port $00)
+    /// Read data register (data port, $00 by default)
     pub fn read_data(&self) -> u8 {
-        // Check for new input first
-        if let Some(c) = read_char() {
-            self.rx_buffer.borrow_mut().push_back(c);
-        }
+        let mut state = self.state.borrow_mut();
+        self.fill_rx(&mut state);
+        state.rx_ready = false;
+        state.rx_data
+    }
 
-        let c = self.rx_buffer.borrow_mut().pop_front().unwrap_or(0);
+    /// Write control/mode register (control port, $01 by default). After
+    /// reset the first write is the mode instruction, followed in sync
+    /// mode by one or two sync characters; later writes are commands.
+    pub fn write_control(&self, val: u8) {
+        let mut state = self.state.borrow_mut();
+        match state.phase {
+            UsartPhase::Mode => {
+                state.mode = val;
+                state.phase = if state.sync_mode() {
+                    UsartPhase::FirstSync
+                } else {
+                    UsartPhase::Command
+                };
+            }
+            UsartPhase::FirstSync => {
+                state.sync_chars[0] = val;
+                state.phase = if state.sync_char_count() == 2 {
+                    UsartPhase::SecondSync
+                } else {
+                    UsartPhase::Command
+                };
+            }
+            UsartPhase::SecondSync => {
+                state.sync_chars[1] = val;
+                state.phase = UsartPhase::Command;
+            }
+            UsartPhase::Command => {
+                if val & CMD_IR != 0 {
+                    *state = UsartState::default();
+                    return;
+                }
+                state.command = val;
+                if val & CMD_ER != 0 {
+                    state.overrun = false;
+                }
+                if val & CMD_EH != 0 && state.sync_mode() {
+                    state.hunting = true;
+                    state.sync_matched = 0;
+                }
+                let now = *self.now.borrow();
+                self.transmit(&mut state, now);
+            }
+        }
+    }
 
-        // Convert lowercase to uppercase like Arduino does
-        if c >= b'a' && c <= b'z' {
-            c - b'a' + b'A'
-        } else {
-            c
+    /// Send the buffered character once the transmitter is enabled (and,
+    /// in timing mode, the shift register is idle)
+    fn transmit(&self, state: &mut UsartState, now: u64) {
+        if !state.tx_enabled() || state.tx_shift.is_some() {
+            return;
+        }
+        if let Some(c) = state.tx_data.take() {
+            match *self.timing.borrow() {
+                Some(timing) => {
+                    state.tx_shift = Some((c, now + timing.frame_cycles(state.frame_half_bits())));
+                }
+                None => self.console.write_output(c),
+            }
         }
     }
 
-    /// Write control/mode register (port $01)
-    #[allow(dead_code)]
-    pub fn write_control(&self, val: u8) {
-        *self.command.borrow_mut() = val;
+    /// Write data register (data port, $00 by default)
+    pub fn write_data(&self, val: u8) {
+        let mut state = self.state.borrow_mut();
+        state.tx_data = Some(val);
+        let now = *self.now.borrow();
+        self.transmit(&mut state, now);
+    }
+
+    /// State of the RxRDY output pin (received character waiting, RxE set)
+    pub fn rx_ready(&self) -> bool {
+        let mut state = self.state.borrow_mut();
+        self.fill_rx(&mut state);
+        state.rx_ready && state.rx_enabled()
     }
 }
@@ -0,0 +1,211 @@
+//! Symbol tables from assembler output
+//!
+//! Symbols name addresses in the disassembly, the stack view and
+//! expressions (`b main_loop`). The assembler's symbol file is read line by
+//! line; each line may use any of these forms:
+//!
+//! ```text
+//! _main     = $0123 ; addr, public, , main, , main.c:3   z88dk .map, z80asm .sym
+//! main:     EQU 0x00000123                              sjasmplus --sym, --exp
+//! main      EQU 0123H                                   pasmo --equ, generic
+//! 0x0123 X  main                                        sjasmplus --lstlab table
+//! main        123    cr        =  0d                    zmac "Symbol Table:"
+//! ```
+//!
+//! Labels name addresses; equates (z88dk `const` entries and zmac `=`
+//! entries) can be used in expressions but never label an address. Other
+//! formats cannot tell the two apart, so their symbols are all labels.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Symbol file extensions tried next to a ROM, in order
+pub const EXTENSIONS: [&str; 3] = ["sym", "map", "lst"];
+
+/// Furthest an address can be past a label and still be shown as
+/// `label+offset`
+pub const MAX_OFFSET: u16 = 0x100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// An address in code or data
+    Label,
+    /// A constant
+    Equate,
+}
+
+/// The symbols of one program
+#[derive(Debug, Default, Clone)]
+pub struct Symbols {
+    /// Name as written and value, by lowercased name
+    values: HashMap<String, (String, u16)>,
+    /// The first label defined at each address
+    labels: BTreeMap<u16, String>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a symbol. A name already defined keeps its first value.
+    pub fn add(&mut self, name: &str, value: u16, kind: Kind) {
+        let key = name.to_ascii_lowercase();
+        if self.values.contains_key(&key) {
+            return;
+        }
+        self.values.insert(key, (name.to_string(), value));
+        if kind == Kind::Label {
+            self.labels.entry(value).or_insert_with(|| name.to_string());
+        }
+    }
+
+    /// Read every symbol definition in a symbol file's text, returning how
+    /// many were added
+    pub fn read(&mut self, text: &str) -> usize {
+        let before = self.len();
+        let mut table = false;
+        for line in text.lines() {
+            if line.to_ascii_lowercase().contains("symbol table") {
+                table = true;
+                continue;
+            }
+            let found = match definition(line) {
+                Some(symbol) => vec![symbol],
+                None if table => table_entries(line),
+                None => Vec::new(),
+            };
+            for (name, value, kind) in found {
+                self.add(name, value, kind);
+            }
+        }
+        self.len() - before
+    }
+
+    /// Read a symbol file, returning how many symbols it added
+    pub fn load(&mut self, path: &Path) -> Result<usize, String> {
+        let text = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        match self.read(&String::from_utf8_lossy(&text)) {
+            0 => Err(format!("{}: no symbols found", path.display())),
+            added => Ok(added),
+        }
+    }
+
+    /// Value of a symbol, by name in any case
+    pub fn value(&self, name: &str) -> Option<u16> {
+        self.values.get(&name.to_ascii_lowercase()).map(|(_, value)| *value)
+    }
+
+    /// The label at an address
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+
+    /// Name an address by the nearest label at or below it: `main` or
+    /// `main+$1C`, or `None` if there is no label within `MAX_OFFSET`
+    pub fn describe(&self, addr: u16) -> Option<String> {
+        let (&at, name) = self.labels.range(..=addr).next_back()?;
+        match addr - at {
+            0 => Some(name.clone()),
+            offset if offset < MAX_OFFSET => Some(format!("{}+${:X}", name, offset)),
+            _ => None,
+        }
+    }
+
+    /// Every label with its address, in address order
+    pub fn labels(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.labels.iter().map(|(addr, name)| (*addr, name.as_str()))
+    }
+
+    /// Every symbol name, as written
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.values.values().map(|(name, _)| name.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+/// The symbol file next to a ROM (`rom.sym`, `rom.map` or `rom.lst`), if
+/// there is one
+pub fn sibling(rom: &Path) -> Option<PathBuf> {
+    EXTENSIONS.iter().map(|ext| rom.with_extension(ext)).find(|path| path.is_file())
+}
+
+//=============================================================================
+// Parsing
+//=============================================================================
+
+/// A symbol defined on one line: `NAME = VALUE`, `NAME[:] EQU VALUE` or
+/// `0xVALUE [FLAG] NAME`
+fn definition(line: &str) -> Option<(&str, u16, Kind)> {
+    let (body, comment) = line.split_once(';').unwrap_or((line, ""));
+    let words: Vec<&str> = body.split_whitespace().collect();
+    match words[..] {
+        [name, op, value] if op == "=" || op.eq_ignore_ascii_case("equ") || op.eq_ignore_ascii_case("defl") => {
+            let name = name.strip_suffix(':').unwrap_or(name);
+            // z88dk says what each symbol is after the semicolon
+            let kind = match comment.split(',').next().map(str::trim) {
+                Some("const") => Kind::Equate,
+                _ => Kind::Label,
+            };
+            Some((identifier(name)?, number(value, 10)?, kind))
+        }
+        [value, name] | [value, _, name] if value.starts_with("0x") || value.starts_with('$') => {
+            Some((identifier(name)?, number(value, 16)?, Kind::Label))
+        }
+        _ => None,
+    }
+}
+
+/// The `NAME [=] VALUE` pairs of a line of zmac's symbol table, where
+/// values are hex and `=` marks an equate
+fn table_entries(line: &str) -> Vec<(&str, u16, Kind)> {
+    let mut entries = Vec::new();
+    let mut words = line.split_whitespace().peekable();
+    while let Some(name) = words.next() {
+        let kind = match words.next_if_eq(&"=") {
+            Some(_) => Kind::Equate,
+            None => Kind::Label,
+        };
+        let value = words.next().and_then(|v| number(v, 16));
+        match (identifier(name), value) {
+            (Some(name), Some(value)) => entries.push((name, value, kind)),
+            _ => return Vec::new(),
+        }
+    }
+    entries
+}
+
+/// A word that can be a symbol name
+fn identifier(word: &str) -> Option<&str> {
+    let mut chars = word.chars();
+    let first = chars.next()?;
+    let valid = (first.is_ascii_alphabetic() || matches!(first, '_' | '.' | '@'))
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '@' | '?'));
+    valid.then_some(word)
+}
+
+/// A symbol value: `$`, `0x` or `#` hex, `H`-suffixed hex, otherwise in
+/// `radix`. Values that do not fit in 16 bits are not addresses.
+fn number(word: &str, radix: u32) -> Option<u16> {
+    let (radix, digits) = if let Some(hex) = word.strip_prefix(['$', '#']) {
+        (16, hex)
+    } else if let Some(hex) = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        (16, hex)
+    } else if let Some(hex) = word.strip_suffix(['h', 'H']) {
+        (16, hex)
+    } else {
+        (radix, word)
+    };
+    if digits.starts_with(['+', '-']) {
+        return None;
+    }
+    u32::from_str_radix(digits, radix).ok().and_then(|v| u16::try_from(v).ok())
+}
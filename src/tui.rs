@@ -2,17 +2,17 @@
 //!
 //! Full-screen debugger with registers, disassembly, memory view, and terminal.
 
-use std::cell::RefCell;
+use std::cell::Cell;
 use std::collections::VecDeque;
 use std::env;
-use std::fs::File;
-use std::io::{self, Read};
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::process;
 use std::time::{Duration, Instant};
 
 use crossterm::{
-    event::{self, Event, KeyCode, KeyModifiers},
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
@@ -24,31 +24,40 @@ use ratatui::{
     widgets::{Block, Borders, Paragraph},
     Frame, Terminal,
 };
-use rz80::{Bus, CPU};
+use retro_z80_emulator::breakpoint::{self, Breakpoints, Goal};
+use retro_z80_emulator::console::{self, Command, History};
+use retro_z80_emulator::disasm::{back_up, disassemble};
+use retro_z80_emulator::expr;
+use retro_z80_emulator::listing::{LineStep, Listing, Location};
+use retro_z80_emulator::memedit::{self, MemoryEditor};
+use retro_z80_emulator::profile::{self, Profile};
+use retro_z80_emulator::retroshield::Machine;
+use retro_z80_emulator::symbols::{self, Symbols};
+use retro_z80_emulator::watchpoint::Watchpoints;
+use rz80::CPU;
 use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
 
-mod sd;
-mod serial;
-
-use sd::SdCard;
-use serial::{Intel8251, Mc6850};
-
 //=============================================================================
 // Constants
 //=============================================================================
 
-/// MC6850 ACIA I/O ports
-const ACIA_CTRL: u8 = 0x80;
-const ACIA_DATA: u8 = 0x81;
-
-/// Intel 8251 USART I/O ports
-const USART_DATA: u8 = 0x00;
-const USART_CTRL: u8 = 0x01;
-
 /// Terminal buffer size
 const TERM_COLS: usize = 80;
 const TERM_ROWS: usize = 24;
 
+/// Screen refresh rate the emulation is paced against
+const FRAMES_PER_SECOND: u32 = 60;
+
+/// Cycles per frame when the profile gives no clock (~30 MHz)
+const DEFAULT_CYCLES_PER_FRAME: u32 = 500_000;
+
+/// Most breakpoints and watchpoints shown at once in the breakpoints panel
+const MAX_BREAKPOINT_LINES: usize = 6;
+
+/// Console panel height, borders included, and the output lines it keeps
+const CONSOLE_HEIGHT: u16 = 12;
+const CONSOLE_LINES: usize = 1000;
+
 //=============================================================================
 // Terminal Emulation
 //=============================================================================
@@ -147,16 +156,12 @@ impl TerminalBuffer {
                 self.clear_to_eol();
             }
             // ESC[?25l - Hide cursor
-            (EscapeState::Private, 'l') => {
-                if params.first().copied() == Some(25) {
-                    self.cursor_visible = false;
-                }
+            (EscapeState::Private, 'l') if params.first().copied() == Some(25) => {
+                self.cursor_visible = false;
             }
             // ESC[?25h - Show cursor
-            (EscapeState::Private, 'h') => {
-                if params.first().copied() == Some(25) {
-                    self.cursor_visible = true;
-                }
+            (EscapeState::Private, 'h') if params.first().copied() == Some(25) => {
+                self.cursor_visible = true;
             }
             // ESC[nA - Cursor up
             (EscapeState::Bracket, 'A') => {
@@ -280,34 +285,27 @@ impl TerminalBuffer {
             }
             '\x08' => {
                 // Backspace
-                if self.cursor_x > 0 {
-                    self.cursor_x -= 1;
-                }
+                self.cursor_x = self.cursor_x.saturating_sub(1);
             }
             '\x0C' => {
                 // Form feed - clear screen
                 self.clear();
             }
-            '\x1B' => {
-                // Escape
-                if self.vt220_mode {
-                    self.escape_state = EscapeState::Escape;
-                    self.escape_params.clear();
-                    self.current_param = None;
-                }
-                // If not VT220 mode, just ignore
-            }
-            _ if c >= ' ' => {
-                if self.cursor_x < TERM_COLS && self.cursor_y < TERM_ROWS {
-                    self.buffer[self.cursor_y * TERM_COLS + self.cursor_x] = c;
-                    self.cursor_x += 1;
-                    if self.cursor_x >= TERM_COLS {
-                        self.cursor_x = 0;
-                        self.cursor_y += 1;
-                        if self.cursor_y >= TERM_ROWS {
-                            self.scroll();
-                            self.cursor_y = TERM_ROWS - 1;
-                        }
+            // Escape (ignored if not VT220 mode)
+            '\x1B' if self.vt220_mode => {
+                self.escape_state = EscapeState::Escape;
+                self.escape_params.clear();
+                self.current_param = None;
+            }
+            _ if c >= ' ' && self.cursor_x < TERM_COLS && self.cursor_y < TERM_ROWS => {
+                self.buffer[self.cursor_y * TERM_COLS + self.cursor_x] = c;
+                self.cursor_x += 1;
+                if self.cursor_x >= TERM_COLS {
+                    self.cursor_x = 0;
+                    self.cursor_y += 1;
+                    if self.cursor_y >= TERM_ROWS {
+                        self.scroll();
+                        self.cursor_y = TERM_ROWS - 1;
                     }
                 }
             }
@@ -321,11 +319,7 @@ impl TerminalBuffer {
 
     fn get_lines(&self, max_lines: usize) -> Vec<String> {
         let mut lines = Vec::new();
-        let start = if TERM_ROWS > max_lines {
-            TERM_ROWS - max_lines
-        } else {
-            0
-        };
+        let start = TERM_ROWS.saturating_sub(max_lines);
         for y in start..TERM_ROWS {
             let line: String = (0..TERM_COLS)
                 .map(|x| self.buffer[y * TERM_COLS + x])
@@ -337,345 +331,111 @@ impl TerminalBuffer {
 }
 
 //=============================================================================
-// RetroShield System
+// Application State
 //=============================================================================
 
-struct RetroShield {
-    rom_size: u16,
-    acia: Mc6850,
-    usart: Intel8251,
-    sd: SdCard,
-    terminal: RefCell<TerminalBuffer>,
-    input_buffer: RefCell<VecDeque<u8>>,
-    output_buffer: RefCell<VecDeque<u8>>,  // Buffered output for throttled display
-    uses_8251: RefCell<bool>,
-    int_signaled: RefCell<bool>,
+/// A value the registers and CPU state panels can edit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RegField {
+    /// A register, by its `expr` name
+    Register(&'static str),
+    /// A bit of F
+    Flag(u8),
+    Im,
+    Iff1,
+    Iff2,
 }
 
-impl RetroShield {
-    fn new(storage_dir: PathBuf) -> Self {
-        Self {
-            rom_size: 0x2000,
-            acia: Mc6850::new(),
-            usart: Intel8251::new(),
-            sd: SdCard::new(storage_dir),
-            terminal: RefCell::new(TerminalBuffer::new()),
-            input_buffer: RefCell::new(VecDeque::new()),
-            output_buffer: RefCell::new(VecDeque::new()),
-            uses_8251: RefCell::new(false),
-            int_signaled: RefCell::new(false),
-        }
-    }
-
-    /// Queue a character for output (called by cpu_outp)
-    fn queue_output(&self, c: u8) {
-        self.output_buffer.borrow_mut().push_back(c);
-    }
-
-    /// Consume up to `max_chars` from output buffer into terminal
-    fn flush_output(&self, max_chars: usize) -> usize {
-        let mut output = self.output_buffer.borrow_mut();
-        let mut terminal = self.terminal.borrow_mut();
-        let count = max_chars.min(output.len());
-        for _ in 0..count {
-            if let Some(c) = output.pop_front() {
-                terminal.putchar(c as char);
-            }
-        }
-        count
-    }
-
-    /// Get number of pending output characters
-    fn pending_output(&self) -> usize {
-        self.output_buffer.borrow().len()
-    }
-
-    fn configure_rom(&mut self, filename: &str) {
-        let basename = filename.rsplit('/').next().unwrap_or(filename);
-        if basename.contains("mint") {
-            self.rom_size = 0x0800; // 2KB ROM for MINT
-        } else {
-            self.rom_size = 0x2000; // Default 8KB
-        }
-    }
-
-    fn send_key(&self, c: u8) {
-        self.input_buffer.borrow_mut().push_back(c);
-        *self.int_signaled.borrow_mut() = false; // Allow new interrupt
-    }
-
-    fn input_available(&self) -> bool {
-        !self.input_buffer.borrow().is_empty()
-    }
-
-    fn get_input(&self) -> Option<u8> {
-        let result = self.input_buffer.borrow_mut().pop_front();
-        if result.is_some() {
-            *self.int_signaled.borrow_mut() = false; // Allow new interrupt
-        }
-        result
-    }
-
-    fn uses_8251(&self) -> bool {
-        *self.uses_8251.borrow()
-    }
-
-    fn should_interrupt(&self) -> bool {
-        self.uses_8251() && self.input_available() && !*self.int_signaled.borrow()
-    }
-
-    fn mark_interrupt_sent(&self) {
-        *self.int_signaled.borrow_mut() = true;
-    }
-
-    fn get_terminal_lines(&self, max_lines: usize) -> Vec<String> {
-        self.terminal.borrow().get_lines(max_lines)
-    }
-
-    fn get_cursor(&self) -> (usize, usize) {
-        self.terminal.borrow().get_cursor()
-    }
-
-    fn set_vt220_mode(&self, enabled: bool) {
-        self.terminal.borrow_mut().set_vt220_mode(enabled);
-    }
-
-    fn is_cursor_visible(&self) -> bool {
-        self.terminal.borrow().is_cursor_visible()
-    }
+/// Editable fields in panel order, which is the order the cursor visits
+const REG_FIELDS: [RegField; 23] = [
+    RegField::Register("pc"),
+    RegField::Register("sp"),
+    RegField::Register("af"),
+    RegField::Register("af'"),
+    RegField::Register("bc"),
+    RegField::Register("bc'"),
+    RegField::Register("de"),
+    RegField::Register("de'"),
+    RegField::Register("hl"),
+    RegField::Register("hl'"),
+    RegField::Register("ix"),
+    RegField::Register("iy"),
+    RegField::Flag(0x80),
+    RegField::Flag(0x40),
+    RegField::Flag(0x10),
+    RegField::Flag(0x04),
+    RegField::Flag(0x02),
+    RegField::Flag(0x01),
+    RegField::Im,
+    RegField::Iff1,
+    RegField::Iff2,
+    RegField::Register("i"),
+    RegField::Register("r"),
+];
+
+/// Register editor (Alt+R): a cursor over the registers and CPU state
+/// panels
+struct RegisterEditor {
+    field: usize,
+    // Hex digits typed so far for the selected register
+    input: Option<String>,
 }
 
-impl Bus for RetroShield {
-    fn cpu_inp(&self, port: i32) -> i32 {
-        let port = port as u8;
-        let val = match port {
-            ACIA_CTRL => {
-                let mut status = 0x02; // TDRE always set
-                if self.input_available() {
-                    status |= 0x01; // RDRF
-                }
-                status
-            }
-            ACIA_DATA => self.get_input().unwrap_or(0),
-            USART_CTRL => {
-                *self.uses_8251.borrow_mut() = true; // Mark ROM as using 8251
-                let mut status = 0x85; // TxRDY + TxE + DSR
-                if self.input_available() {
-                    status |= 0x02; // RxRDY
-                }
-                status
-            }
-            USART_DATA => {
-                *self.uses_8251.borrow_mut() = true; // Mark ROM as using 8251
-                self.get_input().unwrap_or(0)
-            }
-            // SD Card ports
-            p if SdCard::handles_port(p) => self.sd.read_port(p),
-            _ => 0xFF,
-        };
-        val as i32
-    }
-
-    fn cpu_outp(&self, port: i32, val: i32) {
-        let port = port as u8;
-        let val = val as u8;
+/// Where `trace on` writes each instruction
+enum Trace {
+    Console,
+    File(BufWriter<File>),
+}
 
-        // SD Card ports
-        if SdCard::handles_port(port) {
-            self.sd.write_port(port, val);
-            return;
-        }
+/// Debugger console (F2): its output, the line being typed and the state
+/// commands continue from
+struct Console {
+    open: bool,
+    input: String,
+    output: VecDeque<String>,
+    // Lines scrolled back from the newest output
+    scroll: usize,
+    history: History,
+    // Where `m` and `dis` without an address carry on
+    next_dump: u16,
+    next_dis: Option<u16>,
+    trace: Option<Trace>,
+}
 
-        match port {
-            ACIA_DATA => {
-                self.queue_output(val);
-            }
-            USART_DATA => {
-                *self.uses_8251.borrow_mut() = true;
-                self.queue_output(val);
-            }
-            USART_CTRL => {
-                *self.uses_8251.borrow_mut() = true;
-                // Mode/command register - ignored
-            }
-            _ => {}
+impl Console {
+    fn new() -> Self {
+        Self {
+            open: false,
+            input: String::new(),
+            output: VecDeque::new(),
+            scroll: 0,
+            history: History::new(),
+            next_dump: 0,
+            next_dis: None,
+            trace: None,
         }
     }
-}
-
-//=============================================================================
-// Disassembler (simplified)
-//=============================================================================
 
-fn disassemble_instruction(cpu: &CPU, addr: u16) -> (String, u8) {
-    let opcode = cpu.mem.r8(addr as i32) as u8;
-    let byte1 = cpu.mem.r8((addr.wrapping_add(1)) as i32) as u8;
-    let byte2 = cpu.mem.r8((addr.wrapping_add(2)) as i32) as u8;
-    let word = (byte2 as u16) << 8 | byte1 as u16;
-
-    // Simplified disassembler - just common opcodes
-    let (mnemonic, len) = match opcode {
-        0x00 => ("NOP".to_string(), 1),
-        0x01 => (format!("LD BC,${:04X}", word), 3),
-        0x02 => ("LD (BC),A".to_string(), 1),
-        0x03 => ("INC BC".to_string(), 1),
-        0x04 => ("INC B".to_string(), 1),
-        0x05 => ("DEC B".to_string(), 1),
-        0x06 => (format!("LD B,${:02X}", byte1), 2),
-        0x07 => ("RLCA".to_string(), 1),
-        0x08 => ("EX AF,AF'".to_string(), 1),
-        0x09 => ("ADD HL,BC".to_string(), 1),
-        0x0A => ("LD A,(BC)".to_string(), 1),
-        0x0B => ("DEC BC".to_string(), 1),
-        0x0C => ("INC C".to_string(), 1),
-        0x0D => ("DEC C".to_string(), 1),
-        0x0E => (format!("LD C,${:02X}", byte1), 2),
-        0x0F => ("RRCA".to_string(), 1),
-        0x10 => (format!("DJNZ ${:04X}", addr.wrapping_add(2).wrapping_add(byte1 as i8 as u16)), 2),
-        0x11 => (format!("LD DE,${:04X}", word), 3),
-        0x12 => ("LD (DE),A".to_string(), 1),
-        0x13 => ("INC DE".to_string(), 1),
-        0x14 => ("INC D".to_string(), 1),
-        0x15 => ("DEC D".to_string(), 1),
-        0x16 => (format!("LD D,${:02X}", byte1), 2),
-        0x17 => ("RLA".to_string(), 1),
-        0x18 => (format!("JR ${:04X}", addr.wrapping_add(2).wrapping_add(byte1 as i8 as u16)), 2),
-        0x19 => ("ADD HL,DE".to_string(), 1),
-        0x1A => ("LD A,(DE)".to_string(), 1),
-        0x1B => ("DEC DE".to_string(), 1),
-        0x1C => ("INC E".to_string(), 1),
-        0x1D => ("DEC E".to_string(), 1),
-        0x1E => (format!("LD E,${:02X}", byte1), 2),
-        0x1F => ("RRA".to_string(), 1),
-        0x20 => (format!("JR NZ,${:04X}", addr.wrapping_add(2).wrapping_add(byte1 as i8 as u16)), 2),
-        0x21 => (format!("LD HL,${:04X}", word), 3),
-        0x22 => (format!("LD (${:04X}),HL", word), 3),
-        0x23 => ("INC HL".to_string(), 1),
-        0x24 => ("INC H".to_string(), 1),
-        0x25 => ("DEC H".to_string(), 1),
-        0x26 => (format!("LD H,${:02X}", byte1), 2),
-        0x27 => ("DAA".to_string(), 1),
-        0x28 => (format!("JR Z,${:04X}", addr.wrapping_add(2).wrapping_add(byte1 as i8 as u16)), 2),
-        0x29 => ("ADD HL,HL".to_string(), 1),
-        0x2A => (format!("LD HL,(${:04X})", word), 3),
-        0x2B => ("DEC HL".to_string(), 1),
-        0x2C => ("INC L".to_string(), 1),
-        0x2D => ("DEC L".to_string(), 1),
-        0x2E => (format!("LD L,${:02X}", byte1), 2),
-        0x2F => ("CPL".to_string(), 1),
-        0x30 => (format!("JR NC,${:04X}", addr.wrapping_add(2).wrapping_add(byte1 as i8 as u16)), 2),
-        0x31 => (format!("LD SP,${:04X}", word), 3),
-        0x32 => (format!("LD (${:04X}),A", word), 3),
-        0x33 => ("INC SP".to_string(), 1),
-        0x34 => ("INC (HL)".to_string(), 1),
-        0x35 => ("DEC (HL)".to_string(), 1),
-        0x36 => (format!("LD (HL),${:02X}", byte1), 2),
-        0x37 => ("SCF".to_string(), 1),
-        0x38 => (format!("JR C,${:04X}", addr.wrapping_add(2).wrapping_add(byte1 as i8 as u16)), 2),
-        0x39 => ("ADD HL,SP".to_string(), 1),
-        0x3A => (format!("LD A,(${:04X})", word), 3),
-        0x3B => ("DEC SP".to_string(), 1),
-        0x3C => ("INC A".to_string(), 1),
-        0x3D => ("DEC A".to_string(), 1),
-        0x3E => (format!("LD A,${:02X}", byte1), 2),
-        0x3F => ("CCF".to_string(), 1),
-        // LD r,r' instructions (0x40-0x7F except 0x76)
-        0x40..=0x75 | 0x77..=0x7F => {
-            let regs = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
-            let dst = ((opcode - 0x40) >> 3) as usize;
-            let src = ((opcode - 0x40) & 7) as usize;
-            (format!("LD {},{}", regs[dst], regs[src]), 1)
-        }
-        0x76 => ("HALT".to_string(), 1),
-        // ALU ops (0x80-0xBF)
-        0x80..=0xBF => {
-            let ops = ["ADD A,", "ADC A,", "SUB ", "SBC A,", "AND ", "XOR ", "OR ", "CP "];
-            let regs = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
-            let op = ((opcode - 0x80) >> 3) as usize;
-            let reg = ((opcode - 0x80) & 7) as usize;
-            (format!("{}{}", ops[op], regs[reg]), 1)
+    fn print(&mut self, line: impl Into<String>) {
+        if self.output.len() == CONSOLE_LINES {
+            self.output.pop_front();
         }
-        0xC0 => ("RET NZ".to_string(), 1),
-        0xC1 => ("POP BC".to_string(), 1),
-        0xC2 => (format!("JP NZ,${:04X}", word), 3),
-        0xC3 => (format!("JP ${:04X}", word), 3),
-        0xC4 => (format!("CALL NZ,${:04X}", word), 3),
-        0xC5 => ("PUSH BC".to_string(), 1),
-        0xC6 => (format!("ADD A,${:02X}", byte1), 2),
-        0xC7 => ("RST $00".to_string(), 1),
-        0xC8 => ("RET Z".to_string(), 1),
-        0xC9 => ("RET".to_string(), 1),
-        0xCA => (format!("JP Z,${:04X}", word), 3),
-        0xCB => (format!("CB ${:02X}", byte1), 2), // CB prefix
-        0xCC => (format!("CALL Z,${:04X}", word), 3),
-        0xCD => (format!("CALL ${:04X}", word), 3),
-        0xCE => (format!("ADC A,${:02X}", byte1), 2),
-        0xCF => ("RST $08".to_string(), 1),
-        0xD0 => ("RET NC".to_string(), 1),
-        0xD1 => ("POP DE".to_string(), 1),
-        0xD2 => (format!("JP NC,${:04X}", word), 3),
-        0xD3 => (format!("OUT (${:02X}),A", byte1), 2),
-        0xD4 => (format!("CALL NC,${:04X}", word), 3),
-        0xD5 => ("PUSH DE".to_string(), 1),
-        0xD6 => (format!("SUB ${:02X}", byte1), 2),
-        0xD7 => ("RST $10".to_string(), 1),
-        0xD8 => ("RET C".to_string(), 1),
-        0xD9 => ("EXX".to_string(), 1),
-        0xDA => (format!("JP C,${:04X}", word), 3),
-        0xDB => (format!("IN A,(${:02X})", byte1), 2),
-        0xDC => (format!("CALL C,${:04X}", word), 3),
-        0xDD => (format!("DD ${:02X}", byte1), 2), // DD prefix (IX)
-        0xDE => (format!("SBC A,${:02X}", byte1), 2),
-        0xDF => ("RST $18".to_string(), 1),
-        0xE0 => ("RET PO".to_string(), 1),
-        0xE1 => ("POP HL".to_string(), 1),
-        0xE2 => (format!("JP PO,${:04X}", word), 3),
-        0xE3 => ("EX (SP),HL".to_string(), 1),
-        0xE4 => (format!("CALL PO,${:04X}", word), 3),
-        0xE5 => ("PUSH HL".to_string(), 1),
-        0xE6 => (format!("AND ${:02X}", byte1), 2),
-        0xE7 => ("RST $20".to_string(), 1),
-        0xE8 => ("RET PE".to_string(), 1),
-        0xE9 => ("JP (HL)".to_string(), 1),
-        0xEA => (format!("JP PE,${:04X}", word), 3),
-        0xEB => ("EX DE,HL".to_string(), 1),
-        0xEC => (format!("CALL PE,${:04X}", word), 3),
-        0xED => (format!("ED ${:02X}", byte1), 2), // ED prefix
-        0xEE => (format!("XOR ${:02X}", byte1), 2),
-        0xEF => ("RST $28".to_string(), 1),
-        0xF0 => ("RET P".to_string(), 1),
-        0xF1 => ("POP AF".to_string(), 1),
-        0xF2 => (format!("JP P,${:04X}", word), 3),
-        0xF3 => ("DI".to_string(), 1),
-        0xF4 => (format!("CALL P,${:04X}", word), 3),
-        0xF5 => ("PUSH AF".to_string(), 1),
-        0xF6 => (format!("OR ${:02X}", byte1), 2),
-        0xF7 => ("RST $30".to_string(), 1),
-        0xF8 => ("RET M".to_string(), 1),
-        0xF9 => ("LD SP,HL".to_string(), 1),
-        0xFA => (format!("JP M,${:04X}", word), 3),
-        0xFB => ("EI".to_string(), 1),
-        0xFC => (format!("CALL M,${:04X}", word), 3),
-        0xFD => (format!("FD ${:02X}", byte1), 2), // FD prefix (IY)
-        0xFE => (format!("CP ${:02X}", byte1), 2),
-        0xFF => ("RST $38".to_string(), 1),
-    };
-
-    (mnemonic, len)
+        self.output.push_back(line.into());
+        self.scroll = 0;
+    }
 }
 
-//=============================================================================
-// Application State
-//=============================================================================
-
 struct App {
-    cpu: CPU,
-    system: RetroShield,
+    machine: Machine,
+    terminal: TerminalBuffer,
     paused: bool,
-    total_cycles: u64,
     cycles_per_frame: u32,
     chars_per_frame: usize,  // Output throttle: max chars to display per frame
     mem_view_addr: u16,
+    // Rows of memory the panel showed last frame, to keep the cursor in view
+    mem_view_rows: Cell<u16>,
+    mem_edit: Option<MemoryEditor>,
+    reg_edit: Option<RegisterEditor>,
     last_update: Instant,
     cycles_since_update: u64,
     effective_mhz: f64,
@@ -689,26 +449,34 @@ struct App {
     last_blink: Instant,
     // VT220 mode
     vt220_mode: bool,
+    // Reason execution last stopped (shown in status bar)
+    message: Option<String>,
+    breakpoints: Breakpoints,
+    // Step over, step out or run-to in progress, with its name for the
+    // status bar
+    goal: Option<(Goal, &'static str)>,
+    // Step by source line in progress
+    line_step: Option<LineStep>,
+    console: Console,
+    symbols: Symbols,
+    // Source listings, and whether to show the disassembly even where
+    // there is source
+    listing: Listing,
+    show_disassembly: bool,
 }
 
 impl App {
-    fn new(rom_file: &str, vt220_mode: bool, storage_dir: PathBuf) -> io::Result<Self> {
-        let mut system = RetroShield::new(storage_dir);
-        system.configure_rom(rom_file);
-        system.set_vt220_mode(vt220_mode);
-
-        let mut cpu = CPU::new_64k();
-
-        // Load ROM
-        let mut file = File::open(rom_file)?;
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer)?;
-
-        for (addr, &byte) in buffer.iter().enumerate() {
-            if addr < 0x10000 {
-                cpu.mem.w8(addr as i32, byte as i32);
-            }
-        }
+    fn new(profile: Profile, rom_file: &Path, vt220_mode: bool, trap_rom: bool, storage_dir: PathBuf) -> io::Result<Self> {
+        let cycles_per_frame = profile
+            .clock_hz
+            .map_or(DEFAULT_CYCLES_PER_FRAME, |hz| (hz / FRAMES_PER_SECOND).max(1));
+        let mut machine = Machine::with_profile(profile, storage_dir)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        machine.trap_rom_writes = trap_rom;
+        machine.load_rom_file(rom_file)?;
+
+        let mut terminal = TerminalBuffer::new();
+        terminal.set_vt220_mode(vt220_mode);
 
         let pid = Pid::from_u32(std::process::id());
         let mut sysinfo = System::new();
@@ -719,13 +487,15 @@ impl App {
         );
 
         Ok(Self {
-            cpu,
-            system,
+            machine,
+            terminal,
             paused: false,
-            total_cycles: 0,
-            cycles_per_frame: 50000,
+            cycles_per_frame,
             chars_per_frame: 120,  // ~120 chars/frame * 60fps = ~7200 chars/sec (readable speed)
             mem_view_addr: 0x2000,
+            mem_view_rows: Cell::new(1),
+            mem_edit: None,
+            reg_edit: None,
             last_update: Instant::now(),
             cycles_since_update: 0,
             effective_mhz: 0.0,
@@ -736,14 +506,17 @@ impl App {
             cursor_visible: true,
             last_blink: Instant::now(),
             vt220_mode,
+            message: None,
+            breakpoints: Breakpoints::new(),
+            goal: None,
+            line_step: None,
+            console: Console::new(),
+            symbols: Symbols::new(),
+            listing: Listing::new(),
+            show_disassembly: false,
         })
     }
 
-    /// Initialize SD card DMA - must be called after App is constructed
-    fn init_sd_dma(&mut self) {
-        self.system.sd.set_cpu_mem(&mut self.cpu.mem);
-    }
-
     fn update_cursor_blink(&mut self) {
         if self.last_blink.elapsed() >= Duration::from_millis(500) {
             self.cursor_visible = !self.cursor_visible;
@@ -753,46 +526,493 @@ impl App {
 
     /// Flush buffered output to terminal at throttled rate
     fn flush_output(&mut self) {
-        self.system.flush_output(self.chars_per_frame);
+        let console = &self.machine.system.console;
+        for _ in 0..self.chars_per_frame {
+            match console.pop_output() {
+                Some(c) => self.terminal.putchar(c as char),
+                None => break,
+            }
+        }
+    }
+
+    fn send_key(&self, c: u8) {
+        self.machine.system.send_key(c);
     }
 
-    fn step(&mut self) {
-        let cycles = self.cpu.step(&self.system);
-        self.total_cycles += cycles as u64;
-        self.cycles_since_update += cycles as u64;
-
-        // Trigger interrupt for 8251 ROMs when input is available
-        // Check after step so any EI instruction has taken effect
-        if self.system.should_interrupt() && self.cpu.iff1 {
-            // rz80 only supports IM 2, so we manually handle IM 0/1
-            let im = self.cpu.reg.im;
-            if im == 2 {
-                self.cpu.irq();
-            } else if im == 1 {
-                // IM 1: RST 38H - push PC and jump to $0038
-                self.cpu.iff1 = false;
-                self.cpu.iff2 = false;
-                let pc = self.cpu.reg.pc();
-                let sp = self.cpu.reg.sp().wrapping_sub(2);
-                self.cpu.reg.set_sp(sp);
-                self.cpu.mem.w8(sp, pc & 0xFF);
-                self.cpu.mem.w8(sp + 1, (pc >> 8) & 0xFF);
-                self.cpu.reg.set_pc(0x0038);
-            }
-            // IM 0 not commonly used, skip for now
-            self.system.mark_interrupt_sent();
+    /// Execute one instruction. Returns false (and pauses) if a trap fired.
+    fn step(&mut self) -> bool {
+        if self.console.trace.is_some() {
+            self.trace();
+        }
+        match self.machine.step() {
+            Ok(cycles) => {
+                self.cycles_since_update += cycles as u64;
+                true
+            }
+            Err(trap) => {
+                self.paused = true;
+                self.goal = None;
+                self.line_step = None;
+                self.message = Some(trap.to_string());
+                false
+            }
         }
     }
 
     fn run_frame(&mut self) {
-        for _ in 0..self.cycles_per_frame {
-            if self.cpu.halt {
+        let target = self.machine.total_cycles + self.cycles_per_frame as u64;
+        while self.machine.total_cycles < target {
+            let awaits_return = self.goal.is_some_and(|(goal, _)| goal.awaits_return())
+                || self.line_step.is_some_and(|step| step.awaits_return());
+            let returning = awaits_return && breakpoint::is_return(&self.machine.cpu);
+            if self.machine.is_stopped()
+                || !self.step()
+                || self.check_breakpoints()
+                || self.check_goal(returning)
+                || self.check_line_step(returning)
+            {
                 break;
             }
-            self.step();
         }
     }
 
+    /// Pause if the run in progress reached its goal. `returning` tells
+    /// whether the instruction just executed was a return.
+    fn check_goal(&mut self, returning: bool) -> bool {
+        let Some((goal, name)) = self.goal.as_mut() else {
+            return false;
+        };
+        if !goal.reached(&self.machine.cpu, returning) {
+            goal.count_step();
+            return false;
+        }
+        let pc = self.machine.cpu.reg.pc() as u16;
+        if let Goal::Steps { .. } = goal {
+            self.console.print(console::listing_line(&self.machine.cpu, pc, &self.symbols).0);
+        }
+        self.message = Some(format!("{} stopped at ${:04X}", name, pc));
+        self.paused = true;
+        self.goal = None;
+        true
+    }
+
+    /// Pause if the step by source line in progress is done
+    fn check_line_step(&mut self, returning: bool) -> bool {
+        let Some(step) = self.line_step.as_mut() else {
+            return false;
+        };
+        if !step.reached(&self.listing, &self.machine.cpu, returning) {
+            return false;
+        }
+        let pc = self.machine.cpu.reg.pc() as u16;
+        self.message = Some(format!("Stopped at {} (${:04X})", self.source_location(pc), pc));
+        self.paused = true;
+        self.line_step = None;
+        true
+    }
+
+    /// Step to the next source line, into calls or (`over`) over them
+    fn step_line(&mut self, over: bool) -> Result<(), String> {
+        if self.listing.is_empty() {
+            return Err("No listing loaded (-y file.lst)".to_string());
+        }
+        self.goal = None;
+        self.line_step = Some(LineStep::new(&self.listing, &self.machine.cpu, over));
+        self.message = None;
+        self.paused = false;
+        Ok(())
+    }
+
+    /// `FILE:LINE` of the source line covering an address, or `$ADDR`
+    fn source_location(&self, addr: u16) -> String {
+        match self.listing.locate(addr) {
+            Some(at) => format!("{}:{}", self.listing.files[at.file].name, self.listing.line(at).number),
+            None => format!("${:04X}", addr),
+        }
+    }
+
+    /// Run until a goal is reached (or a breakpoint, watchpoint or trap
+    /// stops the machine first)
+    fn run_to_goal(&mut self, goal: Goal, name: &'static str) {
+        self.goal = Some((goal, name));
+        self.line_step = None;
+        self.message = None;
+        self.paused = false;
+    }
+
+    /// Step over a CALL, RST, repeating block instruction or DJNZ; any other
+    /// instruction is stepped
+    fn step_over(&mut self) {
+        match Goal::step_over(&self.machine.cpu) {
+            Some(goal) => self.run_to_goal(goal, "Step over"),
+            None => self.single_step(),
+        }
+    }
+
+    fn single_step(&mut self) {
+        self.paused = true;
+        self.goal = None;
+        self.line_step = None;
+        self.step();
+    }
+
+    /// Pause if a breakpoint at PC triggers. Checked after each step, so
+    /// running again from a breakpoint first leaves it.
+    fn check_breakpoints(&mut self) -> bool {
+        if self.breakpoints.is_empty() {
+            return false;
+        }
+        match self.breakpoints.check(&self.machine.cpu) {
+            Some(bp) => {
+                self.message = Some(format!("Breakpoint #{} at ${:04X}, hit {}", bp.id, bp.addr, bp.hits));
+                self.paused = true;
+                self.goal = None;
+                self.line_step = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Set a breakpoint at PC, or remove the one there
+    fn toggle_breakpoint_at_pc(&mut self) {
+        let pc = self.machine.cpu.reg.pc() as u16;
+        self.message = Some(match self.breakpoints.at(pc).map(|bp| bp.id) {
+            Some(id) => {
+                let _ = self.breakpoints.remove(id);
+                format!("Breakpoint #{} removed", id)
+            }
+            None => format!("Breakpoint #{} at ${:04X}", self.breakpoints.add(pc, None, 1), pc),
+        });
+    }
+
+    /// Log the instruction about to execute
+    fn trace(&mut self) {
+        let (line, _) = console::listing_line(&self.machine.cpu, self.machine.cpu.reg.pc() as u16, &self.symbols);
+        match self.console.trace.as_mut() {
+            Some(Trace::File(file)) => {
+                if let Err(e) = writeln!(file, "{}", line) {
+                    self.console.trace = None;
+                    self.console.print(format!("Error: trace stopped: {}", e));
+                }
+            }
+            Some(Trace::Console) => self.console.print(line),
+            None => {}
+        }
+    }
+
+    /// Run a console line, echoing it and any output or error to the console
+    fn command(&mut self, line: &str) {
+        self.console.print(format!("> {}", line));
+        self.console.history.push(line);
+        let parsed = self.listing.resolve(line).and_then(|line| Command::parse(&line, &self.machine.cpu, &self.symbols));
+        let result = match parsed {
+            Ok(Some(command)) => self.execute(command),
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            self.console.print(format!("Error: {}", e));
+        }
+    }
+
+    fn execute(&mut self, command: Command) -> Result<(), String> {
+        let enabled = |on: bool| if on { "enabled" } else { "disabled" };
+        match command {
+            Command::Help => console::HELP.iter().for_each(|line| self.console.print(*line)),
+            Command::Break(Some((addr, condition, after))) => {
+                let id = self.breakpoints.add(addr, condition, after);
+                self.console.print(format!("Breakpoint #{} at ${:04X}", id, addr));
+            }
+            Command::Break(None) => {
+                let lines: Vec<String> = self.breakpoints.iter().map(|bp| bp.to_string()).collect();
+                self.print_list(lines, "No breakpoints");
+            }
+            Command::Delete(None) => {
+                self.breakpoints.clear();
+                self.console.print("All breakpoints removed");
+            }
+            Command::Delete(Some(id)) => {
+                self.breakpoints.remove(id)?;
+                self.console.print(format!("Breakpoint #{} removed", id));
+            }
+            Command::Toggle(id) => {
+                let on = self.breakpoints.toggle(id)?;
+                self.console.print(format!("Breakpoint #{} {}", id, enabled(on)));
+            }
+            Command::Watch(Some(watch)) => {
+                let id = self.machine.watchpoints.add(watch);
+                self.console.print(format!("Watchpoint #{} on {}", id, watch));
+            }
+            Command::Watch(None) => {
+                let lines: Vec<String> = self.machine.watchpoints.iter().map(|wp| wp.to_string()).collect();
+                self.print_list(lines, "No watchpoints");
+            }
+            Command::WatchDelete(None) => {
+                self.machine.watchpoints.clear();
+                self.console.print("All watchpoints removed");
+            }
+            Command::WatchDelete(Some(id)) => {
+                self.machine.watchpoints.remove(id)?;
+                self.console.print(format!("Watchpoint #{} removed", id));
+            }
+            Command::WatchToggle(id) => {
+                let on = self.machine.watchpoints.toggle(id)?;
+                self.console.print(format!("Watchpoint #{} {}", id, enabled(on)));
+            }
+            Command::Go(None) => {
+                self.goal = None;
+                self.line_step = None;
+                self.message = None;
+                self.paused = false;
+            }
+            Command::Go(Some(addr)) => {
+                self.run_to_goal(Goal::run_to(addr), "Run to");
+                self.console.print(format!("Running to ${:04X}", addr));
+            }
+            // Run as a goal so a large count does not hold up the UI
+            Command::Step(count) => self.run_to_goal(Goal::steps(count), "Step"),
+            Command::Over => self.step_over(),
+            Command::Line => self.step_line(false)?,
+            Command::Next => self.step_line(true)?,
+            Command::Out => {
+                let goal = Goal::step_out(&self.machine.cpu);
+                self.run_to_goal(goal, "Step out");
+            }
+            Command::Reset => {
+                self.reset();
+                self.console.print("Reset");
+            }
+            Command::Memory(addr, len) => {
+                let start = addr.unwrap_or(self.console.next_dump);
+                if let Some(addr) = addr {
+                    self.show_memory(addr);
+                }
+                for line in console::dump(&self.machine.cpu, start, len) {
+                    self.console.print(line);
+                }
+                self.console.next_dump = (start as u32 + len) as u16;
+            }
+            Command::Fill { start, end, byte } => {
+                let bytes = vec![byte; (end - start as u32) as usize];
+                self.machine.cpu.mem.write(start as i32, &bytes);
+                self.console.print(format!("Filled ${:04X}-${:04X} with ${:02X}", start, end - 1, byte));
+            }
+            Command::Copy { start, end, dest } => {
+                let bytes = self.read_memory(start, end);
+                self.machine.cpu.mem.write(dest as i32, &bytes);
+                self.console.print(format!("Copied ${:04X}-${:04X} to ${:04X}", start, end - 1, dest));
+            }
+            Command::Load { path, addr } => {
+                let data = fs::read(&path).map_err(|e| format!("{}: {}", path, e))?;
+                let len = data.len().min(0x10000 - addr as usize);
+                self.machine.cpu.mem.write(addr as i32, &data[..len]);
+                let end = addr as usize + len.max(1) - 1;
+                self.console.print(format!("Loaded {} bytes at ${:04X}-${:04X}", len, addr, end));
+            }
+            Command::Save { start, end, path } => {
+                let bytes = self.read_memory(start, end);
+                fs::write(&path, &bytes).map_err(|e| format!("{}: {}", path, e))?;
+                self.console.print(format!("Saved ${:04X}-${:04X} ({} bytes) to {}", start, end - 1, bytes.len(), path));
+            }
+            Command::Registers(assignments) => {
+                for (name, value) in assignments {
+                    console::set_register(&mut self.machine.cpu, &name, value)?;
+                }
+                for line in console::registers(&self.machine.cpu) {
+                    self.console.print(line);
+                }
+            }
+            Command::Disassemble(addr, count) => {
+                let pc = self.machine.cpu.reg.pc() as u16;
+                let start = addr.or(self.console.next_dis).unwrap_or(pc);
+                let (lines, next) = console::disassembly(&self.machine.cpu, start, count, &self.symbols);
+                for line in lines {
+                    self.console.print(line);
+                }
+                self.console.next_dis = Some(next);
+            }
+            Command::TraceOn(None) => {
+                self.console.trace = Some(Trace::Console);
+                self.console.print("Tracing to the console");
+            }
+            Command::TraceOn(Some(path)) => {
+                let file = File::create(&path).map_err(|e| format!("{}: {}", path, e))?;
+                self.console.trace = Some(Trace::File(BufWriter::new(file)));
+                self.console.print(format!("Tracing to {}", path));
+            }
+            Command::TraceOff => {
+                if let Some(Trace::File(mut file)) = self.console.trace.take() {
+                    file.flush().map_err(|e| e.to_string())?;
+                }
+                self.console.print("Trace off");
+            }
+        }
+        Ok(())
+    }
+
+    fn print_list(&mut self, lines: Vec<String>, empty: &str) {
+        if lines.is_empty() {
+            self.console.print(empty);
+        }
+        for line in lines {
+            self.console.print(line);
+        }
+    }
+
+    /// Bytes from START up to END
+    fn read_memory(&self, start: u16, end: u32) -> Vec<u8> {
+        (start as u32..end).map(|a| self.machine.cpu.mem.r8(a as i32) as u8).collect()
+    }
+
+    /// Scroll the memory view to an address, moving the editor cursor there
+    fn show_memory(&mut self, addr: u16) {
+        self.mem_view_addr = addr;
+        if let Some(editor) = self.mem_edit.as_mut() {
+            editor.move_to(addr);
+        }
+    }
+
+    /// Handle a key while the console is open. Returns false for keys it
+    /// leaves to the debugger (function keys, Ctrl and Alt combinations).
+    fn console_key(&mut self, key: KeyEvent) -> bool {
+        let console = &mut self.console;
+        if !console.open {
+            return false;
+        }
+        let page = (CONSOLE_HEIGHT - 3) as usize;
+        match key.code {
+            KeyCode::Enter => {
+                let line = std::mem::take(&mut console.input);
+                self.command(&line);
+            }
+            KeyCode::Esc => console.open = false,
+            KeyCode::Backspace => {
+                console.input.pop();
+            }
+            KeyCode::Tab => {
+                let completion = console::complete(&console.input, &self.symbols);
+                console.input = completion.line;
+                if !completion.candidates.is_empty() {
+                    console.print(completion.candidates.join("  "));
+                }
+            }
+            KeyCode::Up => {
+                if let Some(line) = console.history.older() {
+                    console.input = line.to_string();
+                }
+            }
+            KeyCode::Down => {
+                if let Some(line) = console.history.newer() {
+                    console.input = line.to_string();
+                }
+            }
+            KeyCode::PageUp => console.scroll = (console.scroll + page).min(console.output.len().saturating_sub(page)),
+            KeyCode::PageDown => console.scroll = console.scroll.saturating_sub(page),
+            KeyCode::Char(c) if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                console.input.push(c)
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Handle a key while the memory editor is open. Returns false for keys
+    /// it leaves to the debugger (function keys).
+    fn memory_key(&mut self, code: KeyCode) -> bool {
+        let Some(editor) = self.mem_edit.as_mut() else {
+            return false;
+        };
+        let cursor = editor.cursor;
+        match code {
+            KeyCode::Esc => self.mem_edit = None,
+            KeyCode::Tab => editor.toggle_ascii(),
+            KeyCode::Left => editor.move_to(cursor.saturating_sub(1)),
+            KeyCode::Right => editor.move_to(cursor.saturating_add(1)),
+            KeyCode::Up => editor.move_to(cursor.saturating_sub(16)),
+            KeyCode::Down => editor.move_to(cursor.saturating_add(16)),
+            KeyCode::PageUp => editor.move_to(cursor.saturating_sub(256)),
+            KeyCode::PageDown => editor.move_to(cursor.saturating_add(256)),
+            KeyCode::Enter => {
+                self.mem_view_addr = editor.follow(&self.machine.cpu);
+                return true;
+            }
+            KeyCode::Backspace => {
+                if let Some(addr) = editor.back() {
+                    self.mem_view_addr = addr;
+                }
+                return true;
+            }
+            KeyCode::Char('g') if !editor.ascii => {
+                self.console.open = true;
+                self.console.input = "m ".to_string();
+            }
+            KeyCode::Char(c) => {
+                editor.type_char(&mut self.machine.cpu, c);
+            }
+            _ => return false,
+        }
+        // Keep the cursor in view
+        if let Some(editor) = &self.mem_edit {
+            self.mem_view_addr = memedit::scroll(self.mem_view_addr, editor.cursor, self.mem_view_rows.get());
+        }
+        true
+    }
+
+    /// Handle a key while the register editor is open. Returns false for
+    /// keys it leaves to the debugger (function keys).
+    fn register_key(&mut self, code: KeyCode) -> bool {
+        let Some(editor) = self.reg_edit.as_mut() else {
+            return false;
+        };
+        let field = REG_FIELDS[editor.field];
+        let cpu = &mut self.machine.cpu;
+        match code {
+            KeyCode::Esc if editor.input.is_some() => editor.input = None,
+            KeyCode::Esc => self.reg_edit = None,
+            KeyCode::Up | KeyCode::Left => {
+                editor.field = (editor.field + REG_FIELDS.len() - 1) % REG_FIELDS.len();
+                editor.input = None;
+            }
+            KeyCode::Down | KeyCode::Right | KeyCode::Tab => {
+                editor.field = (editor.field + 1) % REG_FIELDS.len();
+                editor.input = None;
+            }
+            KeyCode::Backspace => {
+                if let Some(input) = editor.input.as_mut() {
+                    input.pop();
+                }
+            }
+            KeyCode::Enter if editor.input.is_some() => {
+                let input = editor.input.take().unwrap_or_default();
+                if let (RegField::Register(name), Ok(value)) = (field, u16::from_str_radix(&input, 16)) {
+                    if let Err(e) = expr::set_register(cpu, name, value) {
+                        self.message = Some(format!("Error: {}", e));
+                    }
+                }
+            }
+            KeyCode::Enter | KeyCode::Char(' ') => match field {
+                RegField::Register(_) => editor.input = Some(String::new()),
+                RegField::Flag(mask) => cpu.reg.set_f(cpu.reg.f() ^ mask as i32),
+                RegField::Im => cpu.reg.im = (cpu.reg.im + 1) % 3,
+                RegField::Iff1 => cpu.iff1 = !cpu.iff1,
+                RegField::Iff2 => cpu.iff2 = !cpu.iff2,
+            },
+            KeyCode::Char(c) => match (field, c.to_digit(16)) {
+                (RegField::Register(name), Some(_)) => {
+                    let input = editor.input.get_or_insert_with(String::new);
+                    if input.len() < register_digits(name) {
+                        input.push(c.to_ascii_uppercase());
+                    }
+                }
+                (RegField::Im, Some(im @ 0..=2)) => cpu.reg.im = im as i32,
+                _ => {}
+            },
+            _ => return false,
+        }
+        true
+    }
+
     fn update_metrics(&mut self) {
         let now = Instant::now();
         let elapsed = now.duration_since(self.last_update);
@@ -816,10 +1036,20 @@ impl App {
     }
 
     fn reset(&mut self) {
-        self.cpu.reg.reset();
-        self.total_cycles = 0;
+        self.machine.reset();
         self.cycles_since_update = 0;
-        self.system.terminal.borrow_mut().clear();
+        self.terminal.clear();
+        self.message = None;
+        self.goal = None;
+        self.line_step = None;
+    }
+}
+
+/// Hex digits of a register: 4 for pairs, 2 for single registers
+fn register_digits(name: &str) -> usize {
+    match name {
+        "i" | "r" => 2,
+        _ => 4,
     }
 }
 
@@ -827,79 +1057,91 @@ impl App {
 // UI Rendering
 //=============================================================================
 
-fn render_registers(f: &mut Frame, area: Rect, cpu: &CPU) {
-    let pc = cpu.reg.pc() as u16;
-    let sp = cpu.reg.sp() as u16;
-    let af = cpu.reg.af() as u16;
-    let bc = cpu.reg.bc() as u16;
-    let de = cpu.reg.de() as u16;
-    let hl = cpu.reg.hl() as u16;
-    let ix = cpu.reg.ix() as u16;
-    let iy = cpu.reg.iy() as u16;
-    let flags = cpu.reg.f() as u8;
+/// The register editor's selected field and the digits typed into it
+type RegSelection<'a> = Option<(RegField, Option<&'a str>)>;
+
+/// A field's value, reversed when selected; digits being typed are shown
+/// in place of the value, padded with `_`
+fn field_span(field: RegField, text: String, style: Style, selection: RegSelection) -> Span<'static> {
+    match selection {
+        Some((selected, input)) if selected == field => {
+            let text = match input {
+                Some(input) => format!("{:_<1$}", input, text.len()),
+                None => text,
+            };
+            Span::styled(text, style.add_modifier(Modifier::REVERSED))
+        }
+        _ => Span::styled(text, style),
+    }
+}
 
-    let flag_s = if flags & 0x80 != 0 { "S" } else { "-" };
-    let flag_z = if flags & 0x40 != 0 { "Z" } else { "-" };
-    let flag_h = if flags & 0x10 != 0 { "H" } else { "-" };
-    let flag_pv = if flags & 0x04 != 0 { "P" } else { "-" };
-    let flag_n = if flags & 0x02 != 0 { "N" } else { "-" };
-    let flag_c = if flags & 0x01 != 0 { "C" } else { "-" };
+fn render_registers(f: &mut Frame, area: Rect, cpu: &CPU, selection: RegSelection) {
+    let label = |text: &'static str| Span::styled(text, Style::default().fg(Color::Gray));
+    let pair = |name: &'static str, color: Color| {
+        let value = expr::register(cpu, name).unwrap_or(0);
+        field_span(RegField::Register(name), format!("{:04X}", value), Style::default().fg(color), selection)
+    };
 
-    let text = vec![
-        Line::from(vec![
-            Span::styled("PC:", Style::default().fg(Color::Gray)),
-            Span::styled(format!("{:04X}", pc), Style::default().fg(Color::Green)),
-            Span::raw("  "),
-            Span::styled("SP:", Style::default().fg(Color::Gray)),
-            Span::styled(format!("{:04X}", sp), Style::default().fg(Color::White)),
-        ]),
+    let mut text: Vec<Line> = [
+        ("PC:", "pc", Color::Green, "SP:", "sp"),
+        ("AF:", "af", Color::White, "AF':", "af'"),
+        ("BC:", "bc", Color::White, "BC':", "bc'"),
+        ("DE:", "de", Color::White, "DE':", "de'"),
+        ("HL:", "hl", Color::White, "HL':", "hl'"),
+        ("IX:", "ix", Color::White, "IY:", "iy"),
+    ]
+    .into_iter()
+    .map(|(left_label, left, color, right_label, right)| {
         Line::from(vec![
-            Span::styled("AF:", Style::default().fg(Color::Gray)),
-            Span::styled(format!("{:04X}", af), Style::default().fg(Color::White)),
+            label(left_label),
+            pair(left, color),
             Span::raw("  "),
-            Span::styled("BC:", Style::default().fg(Color::Gray)),
-            Span::styled(format!("{:04X}", bc), Style::default().fg(Color::White)),
-        ]),
-        Line::from(vec![
-            Span::styled("DE:", Style::default().fg(Color::Gray)),
-            Span::styled(format!("{:04X}", de), Style::default().fg(Color::White)),
-            Span::raw("  "),
-            Span::styled("HL:", Style::default().fg(Color::Gray)),
-            Span::styled(format!("{:04X}", hl), Style::default().fg(Color::White)),
-        ]),
-        Line::from(vec![
-            Span::styled("IX:", Style::default().fg(Color::Gray)),
-            Span::styled(format!("{:04X}", ix), Style::default().fg(Color::White)),
-            Span::raw("  "),
-            Span::styled("IY:", Style::default().fg(Color::Gray)),
-            Span::styled(format!("{:04X}", iy), Style::default().fg(Color::White)),
-        ]),
-        Line::from(vec![
-            Span::styled("Flags: ", Style::default().fg(Color::Gray)),
-            Span::styled(
-                format!("{}{}-{}-{}{}{}", flag_s, flag_z, flag_h, flag_pv, flag_n, flag_c),
-                Style::default().fg(Color::Yellow),
-            ),
-        ]),
-    ];
+            label(right_label),
+            pair(right, Color::White),
+        ])
+    })
+    .collect();
+
+    let flags = cpu.reg.f() as u8;
+    let style = Style::default().fg(Color::Yellow);
+    let mut flag_spans = vec![label("Flags: ")];
+    for (mask, name) in [(0x80, 'S'), (0x40, 'Z'), (0x20, '-'), (0x10, 'H'), (0x08, '-'), (0x04, 'P'), (0x02, 'N'), (0x01, 'C')] {
+        flag_spans.push(match name {
+            '-' => Span::styled("-", style),
+            _ => {
+                let shown = if flags & mask != 0 { name } else { '-' };
+                field_span(RegField::Flag(mask), shown.to_string(), style, selection)
+            }
+        });
+    }
+    text.push(Line::from(flag_spans));
 
     let block = Block::default()
         .title(" Registers ")
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(Color::Cyan));
+        .border_style(Style::default().fg(if selection.is_some() { Color::Yellow } else { Color::Cyan }));
 
     let paragraph = Paragraph::new(text).block(block);
     f.render_widget(paragraph, area);
 }
 
-fn render_disassembly(f: &mut Frame, area: Rect, cpu: &CPU) {
+fn render_disassembly(f: &mut Frame, area: Rect, cpu: &CPU, breakpoints: &Breakpoints, symbols: &Symbols) {
     let pc = cpu.reg.pc() as u16;
-    let mut addr = pc.saturating_sub(6);
+    let read = |a: u16| cpu.mem.r8(a as i32) as u8;
+    let mut addr = back_up(read, pc, 3);
     let mut lines = Vec::new();
     let visible_lines = (area.height as usize).saturating_sub(2);
 
-    for _ in 0..visible_lines {
-        let (mnemonic, len) = disassemble_instruction(cpu, addr);
+    while lines.len() < visible_lines {
+        if let Some(name) = symbols.label(addr) {
+            lines.push(Line::from(Span::styled(format!("  {}:", name), Style::default().fg(Color::Yellow))));
+        }
+        let ins = disassemble(read, addr);
+        let mnemonic = match ins.target.and_then(|t| symbols.label(t)) {
+            Some(name) => ins.text_with_target(name),
+            None => ins.text.clone(),
+        };
+        let len = ins.len;
 
         // Build hex bytes string
         let mut hex = String::new();
@@ -909,8 +1151,14 @@ fn render_disassembly(f: &mut Frame, area: Rect, cpu: &CPU) {
 
         let is_current = addr == pc;
         let marker = if is_current { ">" } else { " " };
+        let breakpoint = match breakpoints.at(addr) {
+            Some(bp) if bp.enabled => Span::styled("*", Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)),
+            Some(_) => Span::styled("o", Style::default().fg(Color::DarkGray)),
+            None => Span::raw(" "),
+        };
 
         let line = Line::from(vec![
+            breakpoint,
             Span::styled(
                 marker,
                 Style::default()
@@ -944,37 +1192,132 @@ fn render_disassembly(f: &mut Frame, area: Rect, cpu: &CPU) {
     f.render_widget(paragraph, area);
 }
 
-fn render_memory(f: &mut Frame, area: Rect, cpu: &CPU, start_addr: u16) {
+/// The source around the line at PC, in place of the disassembly
+fn render_source(f: &mut Frame, area: Rect, listing: &Listing, at: Location, breakpoints: &Breakpoints) {
+    let file = &listing.files[at.file];
+    let visible_lines = (area.height as usize).saturating_sub(2);
+    // Keep a third of the panel above the current line
+    let first = at.line.saturating_sub(visible_lines / 3);
+
+    let lines: Vec<Line> = file.lines[first..]
+        .iter()
+        .enumerate()
+        .take(visible_lines)
+        .map(|(i, line)| {
+            let is_current = first + i == at.line;
+            let breakpoint = match line.code.and_then(|(addr, _)| breakpoints.at(addr)) {
+                Some(bp) if bp.enabled => Span::styled("*", Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)),
+                Some(_) => Span::styled("o", Style::default().fg(Color::DarkGray)),
+                None => Span::raw(" "),
+            };
+            let text_style = if is_current {
+                Style::default().fg(Color::White).bg(Color::DarkGray).add_modifier(Modifier::BOLD)
+            } else if line.code.is_some() {
+                Style::default().fg(Color::White)
+            } else {
+                Style::default().fg(Color::Gray)
+            };
+            Line::from(vec![
+                breakpoint,
+                Span::styled(
+                    if is_current { ">" } else { " " },
+                    Style::default().fg(Color::Green).add_modifier(Modifier::BOLD),
+                ),
+                Span::styled(format!("{:>5} ", line.number), Style::default().fg(Color::DarkGray)),
+                Span::styled(line.text.clone(), text_style),
+            ])
+        })
+        .collect();
+
+    let block = Block::default()
+        .title(format!(" Source: {}:{} ", file.name, file.lines[at.line].number))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan));
+
+    let paragraph = Paragraph::new(lines).block(block);
+    f.render_widget(paragraph, area);
+}
+
+/// The memory view, with the editor's cursor (and whether it is in the
+/// ASCII column) if editing
+fn render_memory(f: &mut Frame, area: Rect, cpu: &CPU, start_addr: u16, cursor: Option<(u16, bool)>) {
     let mut lines = Vec::new();
     let visible_lines = (area.height as usize).saturating_sub(2);
     let mut addr = start_addr;
 
+    let hex_style = Style::default().fg(Color::Rgb(136, 170, 204));
+    let ascii_style = Style::default().fg(Color::Rgb(170, 204, 170));
+    // The cursor is reversed in the column being typed into and underlined
+    // in the other
+    let cursor_style = |style: Style, active: bool| match active {
+        true => style.add_modifier(Modifier::REVERSED),
+        false => style.add_modifier(Modifier::UNDERLINED),
+    };
+
     for _ in 0..visible_lines {
-        let mut hex = String::new();
-        let mut ascii = String::new();
+        let mut spans = vec![Span::styled(format!("{:04X}: ", addr), Style::default().fg(Color::DarkGray))];
+        let mut ascii = Vec::new();
 
         for i in 0..16 {
-            let byte = cpu.mem.r8((addr.wrapping_add(i)) as i32) as u8;
-            hex.push_str(&format!("{:02X} ", byte));
-            ascii.push(if byte >= 0x20 && byte < 0x7F {
-                byte as char
-            } else {
-                '.'
-            });
+            let byte_addr = addr.wrapping_add(i);
+            let byte = cpu.mem.r8(byte_addr as i32) as u8;
+            let c = if (0x20..0x7F).contains(&byte) { byte as char } else { '.' };
+            match cursor {
+                Some((at, in_ascii)) if at == byte_addr => {
+                    spans.push(Span::styled(format!("{:02X}", byte), cursor_style(hex_style, !in_ascii)));
+                    spans.push(Span::styled(" ", hex_style));
+                    ascii.push(Span::styled(c.to_string(), cursor_style(ascii_style, in_ascii)));
+                }
+                _ => {
+                    spans.push(Span::styled(format!("{:02X} ", byte), hex_style));
+                    ascii.push(Span::styled(c.to_string(), ascii_style));
+                }
+            }
         }
-
-        let line = Line::from(vec![
-            Span::styled(format!("{:04X}: ", addr), Style::default().fg(Color::DarkGray)),
-            Span::styled(hex, Style::default().fg(Color::Rgb(136, 170, 204))),
-            Span::styled(ascii, Style::default().fg(Color::Rgb(170, 204, 170))),
-        ]);
-        lines.push(line);
+        spans.extend(ascii);
+        lines.push(Line::from(spans));
 
         addr = addr.wrapping_add(16);
     }
 
+    let title = match cursor {
+        Some((at, _)) => format!(" Memory @ ${:04X}, editing ${:04X} ", start_addr, at),
+        None => format!(" Memory @ ${:04X} ", start_addr),
+    };
     let block = Block::default()
-        .title(format!(" Memory @ ${:04X} ", start_addr))
+        .title(title)
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(if cursor.is_some() { Color::Yellow } else { Color::Cyan }));
+
+    let paragraph = Paragraph::new(lines).block(block);
+    f.render_widget(paragraph, area);
+}
+
+fn render_breakpoints(f: &mut Frame, area: Rect, breakpoints: &Breakpoints, watchpoints: &Watchpoints, pc: u16) {
+    let mut lines: Vec<Line> = breakpoints
+        .iter()
+        .map(|bp| {
+            let style = if !bp.enabled {
+                Style::default().fg(Color::DarkGray)
+            } else if bp.addr == pc {
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(Color::White)
+            };
+            Line::from(Span::styled(bp.to_string(), style))
+        })
+        .collect();
+    lines.extend(watchpoints.iter().map(|wp| {
+        let style = if wp.enabled {
+            Style::default().fg(Color::Yellow)
+        } else {
+            Style::default().fg(Color::DarkGray)
+        };
+        Line::from(Span::styled(format!("watch {}", wp), style))
+    }));
+
+    let block = Block::default()
+        .title(" Breakpoints ")
         .borders(Borders::ALL)
         .border_style(Style::default().fg(Color::Cyan));
 
@@ -982,7 +1325,9 @@ fn render_memory(f: &mut Frame, area: Rect, cpu: &CPU, start_addr: u16) {
     f.render_widget(paragraph, area);
 }
 
-fn render_stack(f: &mut Frame, area: Rect, cpu: &CPU) {
+/// The stack, with words that point near a label (return addresses,
+/// mostly) named after it
+fn render_stack(f: &mut Frame, area: Rect, cpu: &CPU, symbols: &Symbols) {
     let mut lines = Vec::new();
     let visible_lines = (area.height as usize).saturating_sub(2);
     let sp = cpu.reg.sp() as u16;
@@ -999,6 +1344,10 @@ fn render_stack(f: &mut Frame, area: Rect, cpu: &CPU) {
             Span::styled(marker, Style::default().fg(Color::Green)),
             Span::styled(format!("{:04X}: ", addr), Style::default().fg(Color::DarkGray)),
             Span::styled(format!("{:04X}", word), Style::default().fg(Color::White)),
+            Span::styled(
+                symbols.describe(word).map_or(String::new(), |name| format!(" {}", name)),
+                Style::default().fg(Color::Yellow),
+            ),
         ]);
         lines.push(line);
     }
@@ -1012,53 +1361,50 @@ fn render_stack(f: &mut Frame, area: Rect, cpu: &CPU) {
     f.render_widget(paragraph, area);
 }
 
-fn render_cpu_state(f: &mut Frame, area: Rect, cpu: &CPU) {
-    let im = cpu.reg.im;
-    let iff1 = if cpu.iff1 { "1" } else { "0" };
-    let iff2 = if cpu.iff2 { "1" } else { "0" };
-    let halt = if cpu.halt { "1" } else { "0" };
-    let r = cpu.reg.r as u8;
-    let i = cpu.reg.i as u8;
+fn render_cpu_state(f: &mut Frame, area: Rect, cpu: &CPU, selection: RegSelection) {
+    let label = |text: &'static str| Span::styled(text, Style::default().fg(Color::Gray));
+    let white = Style::default().fg(Color::White);
+    let bit = |on: bool| if on { "1" } else { "0" }.to_string();
+    let byte = |name: &'static str| {
+        let value = expr::register(cpu, name).unwrap_or(0);
+        field_span(RegField::Register(name), format!("{:02X}", value), white, selection)
+    };
 
     let lines = vec![
         Line::from(vec![
-            Span::styled("IM:", Style::default().fg(Color::Gray)),
-            Span::styled(format!("{} ", im), Style::default().fg(Color::Yellow)),
-            Span::styled("IFF1:", Style::default().fg(Color::Gray)),
-            Span::styled(format!("{} ", iff1), Style::default().fg(Color::White)),
-            Span::styled("IFF2:", Style::default().fg(Color::Gray)),
-            Span::styled(format!("{}", iff2), Style::default().fg(Color::White)),
+            label("IM:"),
+            field_span(RegField::Im, cpu.reg.im.to_string(), Style::default().fg(Color::Yellow), selection),
+            label(" IFF1:"),
+            field_span(RegField::Iff1, bit(cpu.iff1), white, selection),
+            label(" IFF2:"),
+            field_span(RegField::Iff2, bit(cpu.iff2), white, selection),
         ]),
         Line::from(vec![
-            Span::styled("HALT:", Style::default().fg(Color::Gray)),
-            Span::styled(format!("{} ", halt), Style::default().fg(if cpu.halt { Color::Red } else { Color::White })),
-            Span::styled("I:", Style::default().fg(Color::Gray)),
-            Span::styled(format!("{:02X} ", i), Style::default().fg(Color::White)),
-            Span::styled("R:", Style::default().fg(Color::Gray)),
-            Span::styled(format!("{:02X}", r), Style::default().fg(Color::White)),
+            label("HALT:"),
+            Span::styled(bit(cpu.halt), Style::default().fg(if cpu.halt { Color::Red } else { Color::White })),
+            label(" I:"),
+            byte("i"),
+            label(" R:"),
+            byte("r"),
         ]),
     ];
 
     let block = Block::default()
         .title(" CPU State ")
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(Color::Cyan));
+        .border_style(Style::default().fg(if selection.is_some() { Color::Yellow } else { Color::Cyan }));
 
     let paragraph = Paragraph::new(lines).block(block);
     f.render_widget(paragraph, area);
 }
 
-fn render_terminal(f: &mut Frame, area: Rect, system: &RetroShield, cursor_visible: bool) {
+fn render_terminal(f: &mut Frame, area: Rect, terminal: &TerminalBuffer, cursor_visible: bool) {
     let visible_lines = (area.height as usize).saturating_sub(2);
-    let term_lines = system.get_terminal_lines(visible_lines);
-    let (cursor_x, cursor_y) = system.get_cursor();
+    let term_lines = terminal.get_lines(visible_lines);
+    let (cursor_x, cursor_y) = terminal.get_cursor();
 
     // Calculate which line the cursor is on relative to visible area
-    let start_line = if TERM_ROWS > visible_lines {
-        TERM_ROWS - visible_lines
-    } else {
-        0
-    };
+    let start_line = TERM_ROWS.saturating_sub(visible_lines);
     let cursor_line_in_view = if cursor_y >= start_line {
         Some(cursor_y - start_line)
     } else {
@@ -1113,8 +1459,48 @@ fn render_terminal(f: &mut Frame, area: Rect, system: &RetroShield, cursor_visib
     f.render_widget(paragraph, area);
 }
 
+/// The console's newest output (or what it is scrolled back to) above the
+/// line being typed
+fn render_console(f: &mut Frame, area: Rect, console: &Console) {
+    let rows = (area.height as usize).saturating_sub(3);
+    let end = console.output.len() - console.scroll;
+    let start = end.saturating_sub(rows);
+    let mut lines: Vec<Line> = console
+        .output
+        .range(start..end)
+        .map(|line| {
+            let style = if line.starts_with("> ") {
+                Style::default().fg(Color::Yellow)
+            } else if line.starts_with("Error: ") {
+                Style::default().fg(Color::Red)
+            } else {
+                Style::default().fg(Color::White)
+            };
+            Line::from(Span::styled(line.clone(), style))
+        })
+        .collect();
+    lines.resize(rows, Line::from(""));
+    lines.push(Line::from(vec![
+        Span::styled("> ", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)),
+        Span::raw(console.input.clone()),
+        Span::styled(" ", Style::default().bg(Color::Green)),
+    ]));
+
+    let title = match console.scroll {
+        0 => " Console  Enter:Run Tab:Complete Up/Down:History PgUp/PgDn:Scroll Esc:Close ".to_string(),
+        n => format!(" Console (scrolled back {} lines) ", n),
+    };
+    let block = Block::default()
+        .title(title)
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Yellow));
+
+    let paragraph = Paragraph::new(lines).block(block);
+    f.render_widget(paragraph, area);
+}
+
 fn render_status(f: &mut Frame, area: Rect, app: &App) {
-    let status_text = if app.cpu.halt {
+    let status_text = if app.machine.is_stopped() {
         Span::styled("[HALTED]", Style::default().fg(Color::Red).add_modifier(Modifier::BOLD))
     } else if app.paused {
         Span::styled("[PAUSED]", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
@@ -1122,19 +1508,29 @@ fn render_status(f: &mut Frame, area: Rect, app: &App) {
         Span::styled("[RUNNING]", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD))
     };
 
-    let help = " F5:Run F6:Step F7:Pause F8:Reset F9/10:Mem Alt+/-:Speed F12:Quit";
+    let help = match (&app.mem_edit, &app.reg_edit) {
+        (Some(_), _) => " Arrows:Move Tab:Hex/ASCII Enter:Follow Bksp:Back g:Go to Esc:Done F12:Quit",
+        (_, Some(_)) => " Arrows:Select 0-F:Type Enter:Set/Toggle Space:Toggle Esc:Done F12:Quit",
+        _ => " F1:Mem Alt+R:Regs F2:Console F3:BP@PC F4:Over F11:Out Alt+S/N:Line F5:Run F6:Step F7:Pause F8:Reset F9/10:Mem Alt+/-:Speed F12:Quit",
+    };
 
     // Show pending output buffer size if significant
-    let pending = app.system.pending_output();
+    let pending = app.machine.system.console.pending_output();
     let pending_text = if pending > 100 {
         Span::styled(format!("Buf:{} ", pending), Style::default().fg(Color::Yellow))
     } else {
         Span::raw("")
     };
 
+    let message_text = match app.message {
+        Some(ref msg) => Span::styled(format!("{} ", msg), Style::default().fg(Color::Red)),
+        None => Span::raw(""),
+    };
+
     let line = Line::from(vec![
         status_text,
         Span::raw(" "),
+        message_text,
         Span::styled(
             format!("Z80:{:.2}MHz ", app.effective_mhz),
             Style::default().fg(Color::Cyan),
@@ -1149,7 +1545,7 @@ fn render_status(f: &mut Frame, area: Rect, app: &App) {
         ),
         pending_text,
         Span::styled(
-            format!("Cyc:{}", app.total_cycles),
+            format!("Cyc:{}", app.machine.total_cycles),
             Style::default().fg(Color::Gray),
         ),
         Span::styled(help, Style::default().fg(Color::DarkGray)),
@@ -1165,7 +1561,11 @@ fn ui(f: &mut Frame, app: &App) {
     // Main layout: top area for panels, bottom for status
     let main_chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Min(10), Constraint::Length(1)])
+        .constraints([
+            Constraint::Min(10),
+            Constraint::Length(if app.console.open { CONSOLE_HEIGHT } else { 0 }),
+            Constraint::Length(1),
+        ])
         .split(size);
 
     // Top area: left (registers+memory) and right (disasm+stack+state+terminal)
@@ -1174,10 +1574,16 @@ fn ui(f: &mut Frame, app: &App) {
         .constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
         .split(main_chunks[0]);
 
-    // Left side: registers on top, memory below
+    // Left side: registers on top, memory below, then breakpoints and
+    // watchpoints if any
+    let breakpoint_lines = (app.breakpoints.len() + app.machine.watchpoints.len()).min(MAX_BREAKPOINT_LINES) as u16;
     let left_chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Length(8), Constraint::Min(6)])
+        .constraints([
+            Constraint::Length(9),
+            Constraint::Min(6),
+            Constraint::Length(if breakpoint_lines > 0 { breakpoint_lines + 2 } else { 0 }),
+        ])
         .split(top_chunks[0]);
 
     // Right side: upper area (disasm+stack+state) and terminal below
@@ -1198,20 +1604,37 @@ fn ui(f: &mut Frame, app: &App) {
         .constraints([Constraint::Min(6), Constraint::Length(4)])
         .split(upper_right_chunks[1]);
 
-    render_registers(f, left_chunks[0], &app.cpu);
-    render_memory(f, left_chunks[1], &app.cpu, app.mem_view_addr);
-    render_disassembly(f, upper_right_chunks[0], &app.cpu);
-    render_stack(f, stack_state_chunks[0], &app.cpu);
-    render_cpu_state(f, stack_state_chunks[1], &app.cpu);
+    let cpu = &app.machine.cpu;
+    let selection = app
+        .reg_edit
+        .as_ref()
+        .map(|editor| (REG_FIELDS[editor.field], editor.input.as_deref()));
+    render_registers(f, left_chunks[0], cpu, selection);
+    app.mem_view_rows.set(left_chunks[1].height.saturating_sub(2));
+    let cursor = app.mem_edit.as_ref().map(|editor| (editor.cursor, editor.ascii));
+    render_memory(f, left_chunks[1], cpu, app.mem_view_addr, cursor);
+    if breakpoint_lines > 0 {
+        let pc = cpu.reg.pc() as u16;
+        render_breakpoints(f, left_chunks[2], &app.breakpoints, &app.machine.watchpoints, pc);
+    }
+    match app.listing.locate(cpu.reg.pc() as u16).filter(|_| !app.show_disassembly) {
+        Some(at) => render_source(f, upper_right_chunks[0], &app.listing, at, &app.breakpoints),
+        None => render_disassembly(f, upper_right_chunks[0], cpu, &app.breakpoints, &app.symbols),
+    }
+    render_stack(f, stack_state_chunks[0], cpu, &app.symbols);
+    render_cpu_state(f, stack_state_chunks[1], cpu, selection);
     // In VT220 mode, use terminal's cursor visibility (controlled by escape sequences)
     // Otherwise use app's blinking cursor
     let cursor_visible = if app.vt220_mode {
-        app.system.is_cursor_visible() && app.cursor_visible
+        app.terminal.is_cursor_visible() && app.cursor_visible
     } else {
         app.cursor_visible
     };
-    render_terminal(f, right_chunks[1], &app.system, cursor_visible);
-    render_status(f, main_chunks[1], app);
+    render_terminal(f, right_chunks[1], &app.terminal, cursor_visible);
+    if app.console.open {
+        render_console(f, main_chunks[1], &app.console);
+    }
+    render_status(f, main_chunks[2], app);
 }
 
 //=============================================================================
@@ -1223,7 +1646,7 @@ fn print_usage(program: &str, show_full: bool) {
         eprintln!("RetroShield Z80 TUI Debugger v{}", env!("CARGO_PKG_VERSION"));
         eprintln!();
     }
-    eprintln!("Usage: {} [OPTIONS] <rom.bin>", program);
+    eprintln!("Usage: {} [OPTIONS] [rom.bin]", program);
     if !show_full {
         eprintln!("Try '{} --help' for more information.", program);
         return;
@@ -1232,15 +1655,36 @@ fn print_usage(program: &str, show_full: bool) {
     eprintln!("Options:");
     eprintln!("  -h, --help      Show this help message");
     eprintln!("  -v, --vt220     Enable VT220 escape sequence interpretation");
+    eprintln!("  -m, --machine   Machine profile (default: <rom>.toml next to the ROM, if present)");
+    eprintln!("  -i, --input     Input filter on top of the profile's, e.g. upcase,eol=cr,bs=del,strip7");
+    eprintln!("  -t, --trap-rom  Pause on writes to ROM (default: ignore them)");
     eprintln!("  -s, --storage   SD card storage directory (default: storage)");
+    eprintln!("  -y, --symbols   Symbol file or listing: .sym, .map or .lst (repeatable; default:");
+    eprintln!("                  <rom>.sym, .map and .lst next to the ROM, if present)");
     eprintln!();
     eprintln!("TUI Debugger Controls:");
+    eprintln!("  F2        Debugger console (numbers are hex; type help for the commands):");
+    eprintln!("            b ADDR [if COND] [after N], d N|all, t N, w [io] ADDR [LEN] [r|w|rw],");
+    eprintln!("            wd N|all, wt N, g [ADDR], s [N], over, out, reset, m [ADDR [LEN]],");
+    eprintln!("            fill START END BYTE, copy START END DEST, load FILE ADDR,");
+    eprintln!("            save START END FILE, r [NAME=VALUE...], dis [ADDR [N]], trace on [FILE]|off,");
+    eprintln!("            line, next; with a listing, FILE:LINE works as an address");
+    eprintln!("  Alt+R     Edit registers: arrows select, type hex and Enter to set, Space");
+    eprintln!("            toggles a flag or IFF (IM cycles 0-2), Esc leaves");
+    eprintln!("  F1        Edit memory: arrows move, type hex (Tab: ASCII), Enter follows");
+    eprintln!("            the pointer under the cursor, Backspace goes back, Esc leaves");
+    eprintln!("  F3        Set or clear a breakpoint at PC");
+    eprintln!("  F4        Step over a CALL, RST, LDIR-style block instruction or DJNZ");
     eprintln!("  F5        Run continuously");
     eprintln!("  F6        Step one instruction");
     eprintln!("  F7        Pause execution");
     eprintln!("  F8        Reset CPU");
     eprintln!("  F9/F10    Memory view scroll up/down");
     eprintln!("  PgUp/PgDn Memory view scroll (16 lines)");
+    eprintln!("  F11       Step out: run until the current routine returns");
+    eprintln!("  Alt+S     Step one source line, into calls (needs a listing)");
+    eprintln!("  Alt+N     Step one source line, over calls");
+    eprintln!("  Alt+D     Show the disassembly instead of the source");
     eprintln!("  +/-       Adjust run speed");
     eprintln!("  F12       Quit");
     eprintln!("  Other     Send to emulated terminal");
@@ -1256,8 +1700,12 @@ fn main() -> io::Result<()> {
 
     // Parse arguments
     let mut vt220_mode = false;
+    let mut trap_rom = false;
     let mut rom_file: Option<String> = None;
+    let mut machine_file: Option<String> = None;
+    let mut input_filter: Option<String> = None;
     let mut storage_dir: Option<String> = None;
+    let mut symbol_files: Vec<PathBuf> = Vec::new();
 
     let mut i = 1;
     while i < args.len() {
@@ -1267,12 +1715,31 @@ fn main() -> io::Result<()> {
                 process::exit(0);
             }
             "--vt220" | "-v" => vt220_mode = true,
+            "--trap-rom" | "-t" => trap_rom = true,
+            "-m" | "--machine" => {
+                i += 1;
+                if i < args.len() {
+                    machine_file = Some(args[i].clone());
+                }
+            }
+            "-i" | "--input" => {
+                i += 1;
+                if i < args.len() {
+                    input_filter = Some(args[i].clone());
+                }
+            }
             "-s" | "--storage" => {
                 i += 1;
                 if i < args.len() {
                     storage_dir = Some(args[i].clone());
                 }
             }
+            "-y" | "--symbols" => {
+                i += 1;
+                if i < args.len() {
+                    symbol_files.push(PathBuf::from(&args[i]));
+                }
+            }
             arg if !arg.starts_with('-') => rom_file = Some(arg.to_string()),
             _ => {
                 eprintln!("Unknown option: {}", args[i]);
@@ -1283,22 +1750,53 @@ fn main() -> io::Result<()> {
         i += 1;
     }
 
-    let rom_file = match rom_file {
-        Some(f) => f,
-        None => {
-            eprintln!("Error: ROM file required");
+    let (mut profile, rom_file) = match profile::resolve(machine_file.as_deref(), rom_file.as_deref()) {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            eprintln!("Error: {}", e);
             print_usage(&args[0], false);
             process::exit(1);
         }
     };
+    if let Some(spec) = input_filter {
+        if let Err(e) = profile.input.apply_spec(&spec) {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
 
     let storage_path = PathBuf::from(storage_dir.unwrap_or_else(|| "storage".to_string()));
 
     // Initialize app
-    let mut app = App::new(&rom_file, vt220_mode, storage_path)?;
-
-    // Initialize SD card DMA (must be after App is fully constructed)
-    app.init_sd_dma();
+    let mut app = App::new(profile, &rom_file, vt220_mode, trap_rom, storage_path)?;
+
+    // Symbols and listings from -y, or else the symbol file and listing
+    // next to the ROM, which need not hold any. A listing is both.
+    let given = !symbol_files.is_empty();
+    if !given {
+        symbol_files.extend(symbols::sibling(&rom_file));
+        let listing = rom_file.with_extension("lst");
+        if listing.is_file() && !symbol_files.contains(&listing) {
+            symbol_files.push(listing);
+        }
+    }
+    for path in &symbol_files {
+        let symbols = app.symbols.load(path);
+        let listing = app.listing.load(path);
+        if let Ok(count) = symbols {
+            app.console.print(format!("Loaded {} symbols from {}", count, path.display()));
+        }
+        if let Ok(count) = listing {
+            app.console.print(format!("Loaded {} source lines from {}", count, path.display()));
+        }
+        if let (Err(e), Err(_)) = (symbols, listing) {
+            if given {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+            app.console.print(format!("Error: {}", e));
+        }
+    }
 
     // Setup terminal
     enable_raw_mode()?;
@@ -1319,76 +1817,116 @@ fn main() -> io::Result<()> {
         let timeout = tick_rate.saturating_sub(last_tick.elapsed());
         if event::poll(timeout)? {
             if let Event::Key(key) = event::read()? {
-                match key.code {
-                    KeyCode::F(12) => break,
-                    KeyCode::F(5) => app.paused = false,
-                    KeyCode::F(6) => {
-                        app.paused = true;
-                        app.step();
-                    }
-                    KeyCode::F(7) => app.paused = true,
-                    KeyCode::F(8) => app.reset(),
-                    KeyCode::F(9) => {
-                        app.mem_view_addr = app.mem_view_addr.saturating_sub(16);
-                    }
-                    KeyCode::F(10) => {
-                        app.mem_view_addr = app.mem_view_addr.saturating_add(16);
-                    }
-                    KeyCode::PageUp => {
-                        app.mem_view_addr = app.mem_view_addr.saturating_sub(256);
-                    }
-                    KeyCode::PageDown => {
-                        app.mem_view_addr = app.mem_view_addr.saturating_add(256);
-                    }
-                    KeyCode::Char(c) => {
-                        if key.modifiers.contains(KeyModifiers::CONTROL) {
-                            // Ctrl+C sends 0x03, Ctrl+other sends control codes
-                            let code = (c as u8) & 0x1F;
-                            app.system.send_key(code);
-                        } else if key.modifiers.contains(KeyModifiers::ALT) {
-                            // Alt+= to increase speed, Alt+- to decrease
-                            if c == '=' || c == '+' {
-                                app.cycles_per_frame = (app.cycles_per_frame * 2).min(1_000_000);
-                            } else if c == '-' {
-                                app.cycles_per_frame = (app.cycles_per_frame / 2).max(1000);
+                if !app.console_key(key) && !app.memory_key(key.code) && !app.register_key(key.code) {
+                    match key.code {
+                        KeyCode::F(12) => break,
+                        KeyCode::F(1) => {
+                            app.reg_edit = None;
+                            app.console.open = false;
+                            app.mem_edit = match app.mem_edit {
+                                Some(_) => None,
+                                None => Some(MemoryEditor::new(app.mem_view_addr)),
+                            };
+                        }
+                        KeyCode::F(2) => {
+                            app.mem_edit = None;
+                            app.reg_edit = None;
+                            app.console.open = !app.console.open;
+                        }
+                        KeyCode::F(3) => app.toggle_breakpoint_at_pc(),
+                        KeyCode::F(4) => app.step_over(),
+                        KeyCode::F(11) => {
+                            let goal = Goal::step_out(&app.machine.cpu);
+                            app.run_to_goal(goal, "Step out");
+                        }
+                        KeyCode::F(5) => {
+                            app.goal = None;
+                            app.line_step = None;
+                            app.paused = false;
+                        }
+                        KeyCode::F(6) => app.single_step(),
+                        KeyCode::F(7) => {
+                            app.goal = None;
+                            app.line_step = None;
+                            app.paused = true;
+                        }
+                        KeyCode::F(8) => app.reset(),
+                        KeyCode::F(9) => {
+                            app.mem_view_addr = app.mem_view_addr.saturating_sub(16);
+                        }
+                        KeyCode::F(10) => {
+                            app.mem_view_addr = app.mem_view_addr.saturating_add(16);
+                        }
+                        KeyCode::PageUp => {
+                            app.mem_view_addr = app.mem_view_addr.saturating_sub(256);
+                        }
+                        KeyCode::PageDown => {
+                            app.mem_view_addr = app.mem_view_addr.saturating_add(256);
+                        }
+                        KeyCode::Char(c) => {
+                            if key.modifiers.contains(KeyModifiers::CONTROL) {
+                                // Ctrl+C sends 0x03, Ctrl+other sends control codes
+                                let code = (c as u8) & 0x1F;
+                                app.send_key(code);
+                            } else if key.modifiers.contains(KeyModifiers::ALT) {
+                                // Alt+= to increase speed, Alt+- to decrease
+                                if c == 'r' {
+                                    app.mem_edit = None;
+                                    app.console.open = false;
+                                    app.reg_edit = match app.reg_edit {
+                                        Some(_) => None,
+                                        None => Some(RegisterEditor { field: 0, input: None }),
+                                    };
+                                } else if c == '=' || c == '+' {
+                                    app.cycles_per_frame = (app.cycles_per_frame * 2).min(8_000_000);
+                                } else if c == '-' {
+                                    app.cycles_per_frame = (app.cycles_per_frame / 2).max(1000);
+                                } else if c == 's' || c == 'n' {
+                                    // Alt+S steps a source line into calls, Alt+N over them
+                                    if let Err(e) = app.step_line(c == 'n') {
+                                        app.message = Some(e);
+                                    }
+                                } else if c == 'd' {
+                                    app.show_disassembly = !app.show_disassembly;
+                                }
+                            } else {
+                                // Send character to emulated system
+                                app.send_key(c as u8);
                             }
-                        } else {
-                            // Send character to emulated system
-                            app.system.send_key(c as u8);
                         }
+                        KeyCode::Enter => app.send_key(b'\r'),
+                        KeyCode::Backspace => app.send_key(0x08),
+                        KeyCode::Esc => app.send_key(0x1B),
+                        // Arrow keys send VT100 escape sequences
+                        KeyCode::Up => {
+                            app.send_key(0x1B);
+                            app.send_key(b'[');
+                            app.send_key(b'A');
+                        }
+                        KeyCode::Down => {
+                            app.send_key(0x1B);
+                            app.send_key(b'[');
+                            app.send_key(b'B');
+                        }
+                        KeyCode::Right => {
+                            app.send_key(0x1B);
+                            app.send_key(b'[');
+                            app.send_key(b'C');
+                        }
+                        KeyCode::Left => {
+                            app.send_key(0x1B);
+                            app.send_key(b'[');
+                            app.send_key(b'D');
+                        }
+                        _ => {}
                     }
-                    KeyCode::Enter => app.system.send_key(b'\r'),
-                    KeyCode::Backspace => app.system.send_key(0x08),
-                    KeyCode::Esc => app.system.send_key(0x1B),
-                    // Arrow keys send VT100 escape sequences
-                    KeyCode::Up => {
-                        app.system.send_key(0x1B);
-                        app.system.send_key(b'[');
-                        app.system.send_key(b'A');
-                    }
-                    KeyCode::Down => {
-                        app.system.send_key(0x1B);
-                        app.system.send_key(b'[');
-                        app.system.send_key(b'B');
-                    }
-                    KeyCode::Right => {
-                        app.system.send_key(0x1B);
-                        app.system.send_key(b'[');
-                        app.system.send_key(b'C');
-                    }
-                    KeyCode::Left => {
-                        app.system.send_key(0x1B);
-                        app.system.send_key(b'[');
-                        app.system.send_key(b'D');
-                    }
-                    _ => {}
                 }
             }
         }
 
         // Run emulation if not paused
         if last_tick.elapsed() >= tick_rate {
-            if !app.paused && !app.cpu.halt {
+            if !app.paused && !app.machine.is_stopped() {
                 app.run_frame();
             }
             // Flush buffered output at throttled rate (always, even when paused)
@@ -0,0 +1,203 @@
+//! WASM Z80 Emulator for RetroShield
+//!
+//! A browser-based Z80 emulator using wasm-bindgen.
+
+use std::path::PathBuf;
+
+use wasm_bindgen::prelude::*;
+
+use crate::disasm;
+use crate::profile::Profile;
+use crate::retroshield::Machine;
+
+/// Terminal output callback
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = console)]
+    fn log(s: &str);
+}
+
+/// WASM-exposed Z80 Emulator
+#[wasm_bindgen]
+pub struct Z80Emulator {
+    machine: Machine,
+    halted: bool,
+    trap_message: String,
+}
+
+#[wasm_bindgen]
+impl Z80Emulator {
+    /// Create a new emulator instance
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Z80Emulator {
+        Z80Emulator {
+            machine: Machine::new(PathBuf::from("storage")),
+            halted: false,
+            trap_message: String::new(),
+        }
+    }
+
+    /// Configure the machine from profile text (the contents of a
+    /// `roms/*.toml` file). Call before `load_rom`; `rom.file` is ignored.
+    #[wasm_bindgen]
+    pub fn load_profile(&mut self, text: &str) -> Result<(), JsValue> {
+        let profile = Profile::parse(text).map_err(|e| JsValue::from_str(&e))?;
+        self.machine.configure(profile).map_err(|e| JsValue::from_str(&e))?;
+        self.halted = false;
+        self.trap_message.clear();
+        Ok(())
+    }
+
+    /// Nominal clock from the profile in Hz (0 if the profile gives none)
+    #[wasm_bindgen]
+    pub fn get_clock_hz(&self) -> u32 {
+        self.machine.profile().clock_hz.unwrap_or(0)
+    }
+
+    /// Load ROM data into memory
+    #[wasm_bindgen]
+    pub fn load_rom(&mut self, data: &[u8]) {
+        self.machine.load_rom(data);
+        // Reset CPU state
+        self.machine.reset();
+        self.halted = false;
+        self.trap_message.clear();
+    }
+
+    /// Reset the CPU
+    #[wasm_bindgen]
+    pub fn reset(&mut self) {
+        self.machine.reset();
+        self.halted = false;
+        self.trap_message.clear();
+        // Clear buffers
+        self.machine.system.console.clear();
+    }
+
+    /// Run for a specified number of cycles
+    /// Returns the actual number of cycles executed
+    #[wasm_bindgen]
+    pub fn run(&mut self, max_cycles: u32) -> u32 {
+        if self.halted {
+            return 0;
+        }
+
+        let start = self.machine.total_cycles;
+        if let Err(trap) = self.machine.run(max_cycles) {
+            // Stop until reset, like a halt
+            self.trap_message = trap.to_string();
+            self.halted = true;
+        }
+        self.halted |= self.machine.is_stopped();
+
+        (self.machine.total_cycles - start) as u32
+    }
+
+    /// Change the input filter on top of the profile's, from a
+    /// comma-separated list such as "upcase,eol=cr,bs=del,strip7"
+    #[wasm_bindgen]
+    pub fn set_input_filter(&mut self, spec: &str) -> Result<(), JsValue> {
+        let console = &self.machine.system.console;
+        let mut filter = console.filter();
+        filter.apply_spec(spec).map_err(|e| JsValue::from_str(&e))?;
+        console.set_filter(filter);
+        Ok(())
+    }
+
+    /// Send a character to the emulator
+    #[wasm_bindgen]
+    pub fn send_char(&mut self, c: u8) {
+        self.machine.system.send_key(c);
+    }
+
+    /// Send a string to the emulator
+    #[wasm_bindgen]
+    pub fn send_string(&mut self, s: &str) {
+        for c in s.bytes() {
+            self.machine.system.send_key(c);
+        }
+    }
+
+    /// Get output from the emulator (clears output buffer)
+    #[wasm_bindgen]
+    pub fn get_output(&mut self) -> Vec<u8> {
+        self.machine.system.console.take_output()
+    }
+
+    /// Get output as a string
+    #[wasm_bindgen]
+    pub fn get_output_string(&mut self) -> String {
+        let bytes = self.machine.system.console.take_output();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    /// Check if CPU is halted with interrupts disabled (or stopped by a trap)
+    #[wasm_bindgen]
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Get current PC
+    #[wasm_bindgen]
+    pub fn get_pc(&self) -> u16 {
+        self.machine.cpu.reg.pc() as u16
+    }
+
+    /// Get total cycles executed
+    #[wasm_bindgen]
+    pub fn get_cycles(&self) -> u64 {
+        self.machine.total_cycles
+    }
+
+    /// Read memory at address
+    #[wasm_bindgen]
+    pub fn read_memory(&self, addr: u16) -> u8 {
+        self.machine.cpu.mem.r8(addr as i32) as u8
+    }
+
+    /// Disassemble the instruction at an address, e.g. "LD (IX+$05),A"
+    #[wasm_bindgen]
+    pub fn disassemble(&self, addr: u16) -> String {
+        disasm::disassemble(|a| self.read_memory(a), addr).text
+    }
+
+    /// Length in bytes of the instruction at an address (1-4)
+    #[wasm_bindgen]
+    pub fn instruction_length(&self, addr: u16) -> u8 {
+        disasm::disassemble(|a| self.read_memory(a), addr).len
+    }
+
+    /// Stop execution on writes to ROM instead of ignoring them
+    #[wasm_bindgen]
+    pub fn set_trap_rom_writes(&mut self, enabled: bool) {
+        self.machine.trap_rom_writes = enabled;
+    }
+
+    /// Get the reason execution was stopped by a trap (empty if none)
+    #[wasm_bindgen]
+    pub fn get_trap_message(&self) -> String {
+        self.trap_message.clone()
+    }
+
+    /// Pulse /NMI
+    #[wasm_bindgen]
+    pub fn nmi(&mut self) {
+        self.machine.nmi();
+        // A HALT can be left by /NMI, a trap cannot
+        if self.trap_message.is_empty() {
+            self.halted = false;
+        }
+    }
+
+    /// Set whether to use Intel 8251 mode (for Grant's BASIC, etc.)
+    #[wasm_bindgen]
+    pub fn set_8251_mode(&mut self, enabled: bool) {
+        self.machine.system.set_8251_mode(enabled);
+    }
+}
+
+impl Default for Z80Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
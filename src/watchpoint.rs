@@ -0,0 +1,308 @@
+//! Memory and I/O watchpoints
+//!
+//! A watchpoint stops the machine after an instruction reads or writes a
+//! watched memory range or I/O port. rz80 accesses memory without going
+//! through the bus, so memory accesses are predicted from the instruction
+//! about to execute (see `access`) and the old values saved; port accesses
+//! are recorded by the device bus as they happen.
+//!
+//! Accesses made while accepting an interrupt (the pushed return address,
+//! the IM 2 table read) and SD card DMA transfers are not seen.
+
+use std::fmt;
+
+use rz80::CPU;
+
+use crate::access::{self, Region};
+use crate::expr::Expr;
+use crate::symbols::Symbols;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Space {
+    Memory,
+    Io,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// What a watchpoint covers: an inclusive address or port range, and the
+/// kinds of access that stop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watch {
+    pub space: Space,
+    pub start: u16,
+    pub end: u16,
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Watch {
+    fn matches(&self, space: Space, access: Access, addr: u16) -> bool {
+        let kind = match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+        };
+        kind && self.space == space && (self.start..=self.end).contains(&addr)
+    }
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (prefix, digits) = match self.space {
+            Space::Memory => ("", 4),
+            Space::Io => ("port ", 2),
+        };
+        write!(f, "{}${:02$X}", prefix, self.start, digits)?;
+        if self.end != self.start {
+            write!(f, "-${:01$X}", self.end, digits)?;
+        }
+        let mode = match (self.read, self.write) {
+            (true, true) => "rw",
+            (true, false) => "r",
+            _ => "w",
+        };
+        write!(f, " {}", mode)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Watchpoint {
+    pub id: u32,
+    pub watch: Watch,
+    pub enabled: bool,
+    /// Accesses seen, counting every watched address of every instruction
+    pub hits: u64,
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} {} ({} hit{})", self.id, self.watch, self.hits, if self.hits == 1 { "" } else { "s" })?;
+        if !self.enabled {
+            write!(f, " off")?;
+        }
+        Ok(())
+    }
+}
+
+/// An access that stopped the machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub id: u32,
+    /// Address of the instruction that made the access
+    pub pc: u16,
+    pub space: Space,
+    pub access: Access,
+    pub addr: u16,
+    /// Value before a memory write (ports keep no value)
+    pub old: Option<u8>,
+    /// Value read or written
+    pub value: u8,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Watchpoint #{}: ", self.id)?;
+        match (self.space, self.access) {
+            (Space::Memory, Access::Read) => write!(f, "read of {:04X}", self.addr)?,
+            (Space::Memory, Access::Write) => write!(f, "write to {:04X}", self.addr)?,
+            (Space::Io, Access::Read) => write!(f, "IN from port {:02X}", self.addr)?,
+            (Space::Io, Access::Write) => write!(f, "OUT to port {:02X}", self.addr)?,
+        }
+        write!(f, " at PC={:04X}, ", self.pc)?;
+        match self.old {
+            Some(old) => write!(f, "${:02X} -> ${:02X}", old, self.value),
+            None => write!(f, "${:02X}", self.value),
+        }
+    }
+}
+
+/// A port access recorded by the device bus: the access, port and value
+pub type IoAccess = (Access, u8, u8);
+
+/// Memory the next instruction will touch, saved before it executes
+pub struct Pending {
+    pc: u16,
+    read: Option<(Region, [u8; 2])>,
+    write: Option<(Region, [u8; 2])>,
+}
+
+/// The watchpoints of one machine, numbered from 1
+#[derive(Debug, Default)]
+pub struct Watchpoints {
+    list: Vec<Watchpoint>,
+    last_id: u32,
+}
+
+impl Watchpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a watchpoint and return its number
+    pub fn add(&mut self, watch: Watch) -> u32 {
+        self.last_id += 1;
+        self.list.push(Watchpoint {
+            id: self.last_id,
+            watch,
+            enabled: true,
+            hits: 0,
+        });
+        self.last_id
+    }
+
+    pub fn remove(&mut self, id: u32) -> Result<Watchpoint, String> {
+        let index = self.index(id)?;
+        Ok(self.list.remove(index))
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+
+    /// Enable or disable a watchpoint. Returns whether it is now enabled.
+    pub fn toggle(&mut self, id: u32) -> Result<bool, String> {
+        let index = self.index(id)?;
+        let wp = &mut self.list[index];
+        wp.enabled = !wp.enabled;
+        Ok(wp.enabled)
+    }
+
+    fn index(&self, id: u32) -> Result<usize, String> {
+        self.list
+            .iter()
+            .position(|wp| wp.id == id)
+            .ok_or_else(|| format!("no watchpoint #{}", id))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Watchpoint> {
+        self.list.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    /// Whether any enabled watchpoint covers a space
+    pub fn watches(&self, space: Space) -> bool {
+        self.list.iter().any(|wp| wp.enabled && wp.watch.space == space)
+    }
+
+    /// Count a hit on every enabled watchpoint covering an access, and
+    /// return the first one's number
+    fn hit(&mut self, space: Space, access: Access, addr: u16) -> Option<u32> {
+        let mut first = None;
+        for wp in self.list.iter_mut().filter(|wp| wp.enabled && wp.watch.matches(space, access, addr)) {
+            wp.hits += 1;
+            first = first.or(Some(wp.id));
+        }
+        first
+    }
+
+    /// Save the memory the instruction at PC is about to read and write
+    pub fn before(&self, cpu: &CPU) -> Pending {
+        let memory = self.watches(Space::Memory);
+        let save = |region: Region| {
+            let mut bytes = [0; 2];
+            for (byte, addr) in bytes.iter_mut().zip(region.addrs()) {
+                *byte = cpu.mem.r8(addr as i32) as u8;
+            }
+            (region, bytes)
+        };
+        Pending {
+            pc: cpu.reg.pc() as u16,
+            read: memory.then(|| access::memory_read(cpu)).flatten().map(save),
+            write: memory.then(|| access::memory_write(cpu)).flatten().map(save),
+        }
+    }
+
+    /// Check the instruction's accesses once it has executed: the memory
+    /// saved by `before`, and the port access the bus recorded, if any
+    pub fn after(&mut self, pending: Pending, cpu: &CPU, io: Option<IoAccess>) -> Option<WatchHit> {
+        let mut stop = None;
+        let mut report = |hit: Option<u32>, space, access, addr, old, value| {
+            if let Some(id) = hit {
+                stop = stop.or(Some(WatchHit { id, pc: pending.pc, space, access, addr, old, value }));
+            }
+        };
+
+        if let Some((region, old)) = pending.read {
+            for (addr, value) in region.addrs().zip(old) {
+                report(self.hit(Space::Memory, Access::Read, addr), Space::Memory, Access::Read, addr, None, value);
+            }
+        }
+        if let Some((region, old)) = pending.write {
+            for (addr, old) in region.addrs().zip(old) {
+                let value = cpu.mem.r8(addr as i32) as u8;
+                let hit = self.hit(Space::Memory, Access::Write, addr);
+                report(hit, Space::Memory, Access::Write, addr, Some(old), value);
+            }
+        }
+        if let Some((access, port, value)) = io {
+            report(self.hit(Space::Io, access, port as u16), Space::Io, access, port as u16, None, value);
+        }
+        stop
+    }
+}
+
+/// Parse `[io] ADDR [LEN] [r|w|rw]`, with words separated by spaces or
+/// commas. ADDR and LEN are expressions evaluated now. Memory watches
+/// default to writes, ports to both directions.
+pub fn parse_spec(spec: &str, cpu: &CPU) -> Result<Watch, String> {
+    parse_spec_with(spec, cpu, 10, &Symbols::new())
+}
+
+/// Parse a watchpoint with plain numbers in `radix` and names looked up in
+/// `symbols`
+pub fn parse_spec_with(spec: &str, cpu: &CPU, radix: u32, symbols: &Symbols) -> Result<Watch, String> {
+    let mut words: Vec<&str> = spec.split([' ', ',']).filter(|w| !w.is_empty()).collect();
+
+    let space = match words.first() {
+        Some(w) if w.eq_ignore_ascii_case("io") => {
+            words.remove(0);
+            Space::Io
+        }
+        _ => Space::Memory,
+    };
+    let (read, write) = match words.last().map(|w| w.to_ascii_lowercase()).as_deref() {
+        Some(mode @ ("r" | "w" | "rw")) => {
+            let access = (mode.contains('r'), mode.contains('w'));
+            words.pop();
+            access
+        }
+        _ => (space == Space::Io, true),
+    };
+    let limit: i64 = match space {
+        Space::Memory => 0xFFFF,
+        Space::Io => 0xFF,
+    };
+
+    let value = |word: &str| -> Result<i64, String> { Ok(Expr::parse_with(word, radix, symbols)?.eval(cpu)) };
+    let (start, len) = match words[..] {
+        [addr] => (value(addr)?, 1),
+        [addr, len] => (value(addr)?, value(len)?),
+        [] => return Err("missing address".to_string()),
+        _ => return Err("expected [io] ADDR [LEN] [r|w|rw]".to_string()),
+    };
+    if !(0..=limit).contains(&start) {
+        return Err(format!("address {} is out of range", start));
+    }
+    if len < 1 || start + len - 1 > limit {
+        return Err(format!("length {} is out of range", len));
+    }
+
+    Ok(Watch {
+        space,
+        start: start as u16,
+        end: (start + len - 1) as u16,
+        read,
+        write,
+    })
+}
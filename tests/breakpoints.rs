@@ -0,0 +1,254 @@
+//! Breakpoint conditions and hit counting, and stepping over and out
+
+use retro_z80_emulator::breakpoint::{is_return, parse_spec, Breakpoints, Goal};
+use retro_z80_emulator::expr::{self, Expr};
+use retro_z80_emulator::retroshield::Machine;
+use rz80::CPU;
+
+fn cpu() -> CPU {
+    let mut cpu = CPU::new_64k();
+    cpu.reg.set_pc(0x0100);
+    cpu.reg.set_af(0x0C42);
+    cpu.reg.set_hl(0x2000);
+    cpu.reg.set_ix(0x8034);
+    cpu.reg.set_af_(0xBEEF);
+    cpu.mem.write(0x2000, &[0x34, 0x12]);
+    cpu
+}
+
+fn eval(text: &str) -> i64 {
+    Expr::parse(text).unwrap_or_else(|e| panic!("{}: {}", text, e)).eval(&cpu())
+}
+
+#[test]
+fn expressions() {
+    assert_eq!(eval("42"), 42);
+    assert_eq!(eval("$2A + 0x10"), 0x3A);
+    assert_eq!(eval("2 + 3 * 4"), 14);
+    assert_eq!(eval("(2 + 3) * 4"), 20);
+    assert_eq!(eval("-1 + 10 % 4 - 7 / 2"), -2);
+    assert_eq!(eval("1 << 4 | 1"), 17);
+    assert_eq!(eval("$F0 & ~$30 ^ 1"), 0xC1);
+    assert_eq!(eval("!0 + !5"), 1);
+    assert_eq!(eval("5 / 0"), 0);
+}
+
+#[test]
+fn registers_and_memory() {
+    assert_eq!(eval("A"), 0x0C);
+    assert_eq!(eval("f"), 0x42);
+    assert_eq!(eval("HL"), 0x2000);
+    assert_eq!(eval("ixh * 256 + IXL"), 0x8034);
+    assert_eq!(eval("af'"), 0xBEEF);
+    assert_eq!(eval("[hl]"), 0x34);
+    assert_eq!(eval("[HL+1]"), 0x12);
+    assert_eq!(eval("w[hl]"), 0x1234);
+    assert_eq!(eval("F & $40"), 0x40);
+}
+
+#[test]
+fn setting_registers() {
+    let mut cpu = cpu();
+    for (name, value) in [("PC", 0x0200), ("hl'", 0x1234), ("ixh", 0x12), ("ixl", 0x56), ("a", 0x99), ("i", 0x3F)] {
+        expr::set_register(&mut cpu, name, value).unwrap();
+    }
+    assert_eq!(cpu.reg.pc(), 0x0200);
+    assert_eq!(cpu.reg.hl_(), 0x1234);
+    assert_eq!(cpu.reg.ix(), 0x1256);
+    assert_eq!(cpu.reg.af(), 0x9942);
+    assert_eq!(expr::register(&cpu, "I"), Some(0x3F));
+
+    assert_eq!(expr::set_register(&mut cpu, "a", 0x100), Err("$100 does not fit in A".to_string()));
+    assert!(expr::set_register(&mut cpu, "xy", 0).is_err());
+}
+
+#[test]
+fn conditions() {
+    assert_eq!(eval("HL==$2000 && A>10"), 1);
+    assert_eq!(eval("HL==$2000 && A>12"), 0);
+    assert_eq!(eval("A<10 || w[HL]==$1234"), 1);
+    assert_eq!(eval("A >= 12 == 1"), 1);
+}
+
+#[test]
+fn parse_errors() {
+    for (text, error) in [
+        ("", "unexpected end of expression"),
+        ("HL ==", "unexpected end of expression"),
+        ("(1 + 2", "expected \")\""),
+        ("(1 + 2]", "expected \")\", found \"]\""),
+        ("[hl", "expected \"]\""),
+        ("foo > 1", "unknown register \"foo\""),
+        ("1 2", "unexpected number 2"),
+        ("A = 1", "unexpected \"=\""),
+        ("$XYZ", "invalid number"),
+    ] {
+        let e = Expr::parse(text).unwrap_err();
+        assert!(e.starts_with(error), "{}: {}", text, e);
+    }
+}
+
+#[test]
+fn breakpoint_specs() {
+    let cpu = cpu();
+    let (addr, condition, after) = parse_spec("$0200 if HL==$2000 && A>10 after 3", &cpu).unwrap();
+    assert_eq!(addr, 0x0200);
+    assert_eq!(condition.unwrap().to_string(), "HL==$2000 && A>10");
+    assert_eq!(after, 3);
+
+    let (addr, condition, after) = parse_spec("pc + 3", &cpu).unwrap();
+    assert_eq!((addr, condition.is_none(), after), (0x0103, true, 1));
+
+    assert!(parse_spec("", &cpu).is_err());
+    assert!(parse_spec("$0200 if", &cpu).is_err());
+    assert!(parse_spec("$0200 after 2 if A", &cpu).is_err());
+    assert!(parse_spec("$0200 after x", &cpu).is_err());
+    assert!(parse_spec("$10000", &cpu).is_err());
+}
+
+#[test]
+fn breakpoints_stop_on_conditions_and_hit_counts() {
+    let mut cpu = cpu();
+    let mut breakpoints = Breakpoints::new();
+    let plain = breakpoints.add(0x0100, None, 1);
+    let counted = breakpoints.add(0x0200, None, 3);
+    let conditional = breakpoints.add(0x0300, Some(Expr::parse("A == 0").unwrap()), 1);
+
+    assert_eq!(breakpoints.check(&cpu).map(|bp| bp.id), Some(plain));
+
+    cpu.reg.set_pc(0x0200);
+    assert!(breakpoints.check(&cpu).is_none());
+    assert!(breakpoints.check(&cpu).is_none());
+    assert_eq!(breakpoints.check(&cpu).map(|bp| bp.id), Some(counted));
+    assert_eq!(breakpoints.check(&cpu).map(|bp| bp.hits), Some(4));
+
+    // Hits only count when the condition holds
+    cpu.reg.set_pc(0x0300);
+    assert!(breakpoints.check(&cpu).is_none());
+    cpu.reg.set_a(0);
+    assert_eq!(breakpoints.check(&cpu).map(|bp| bp.id), Some(conditional));
+    assert_eq!(breakpoints.iter().find(|bp| bp.id == conditional).unwrap().hits, 1);
+
+    // Disabled breakpoints neither stop nor count
+    assert_eq!(breakpoints.toggle(conditional), Ok(false));
+    assert!(breakpoints.check(&cpu).is_none());
+    assert_eq!(breakpoints.at(0x0300).map(|bp| bp.enabled), Some(false));
+    assert_eq!(breakpoints.toggle(conditional), Ok(true));
+
+    assert!(breakpoints.remove(plain).is_ok());
+    assert!(breakpoints.remove(plain).is_err());
+    cpu.reg.set_pc(0x0100);
+    assert!(breakpoints.check(&cpu).is_none());
+    assert_eq!(breakpoints.len(), 2);
+}
+
+#[test]
+fn breakpoint_listing() {
+    let mut breakpoints = Breakpoints::new();
+    breakpoints.add(0x0100, None, 1);
+    let id = breakpoints.add(0x2043, Some(Expr::parse("A>10").unwrap()), 3);
+    breakpoints.toggle(id).unwrap();
+    let lines: Vec<String> = breakpoints.iter().map(|bp| bp.to_string()).collect();
+    assert_eq!(lines, ["#1 $0100 (0 hits)", "#2 $2043 if A>10 after 3 (0 hits) off"]);
+}
+
+/// Run a machine until it reaches a goal, returning the number of steps
+fn run_to(machine: &mut Machine, mut goal: Goal) -> usize {
+    for steps in 1..10_000 {
+        let returned = is_return(&machine.cpu);
+        machine.step().expect("no trap");
+        if goal.reached(&machine.cpu, returned) {
+            return steps;
+        }
+        goal.count_step();
+    }
+    panic!("goal {:?} not reached", goal);
+}
+
+#[test]
+fn step_over_and_out() {
+    let storage = std::env::temp_dir().join(format!("retroshield-test-step-{}", std::process::id()));
+    let mut m = Machine::new(storage);
+    #[rustfmt::skip]
+    m.cpu.mem.write(0x8000, &[
+        0xCD, 0x10, 0x80, // 8000  call $8010
+        0x06, 0x05,       // 8003  ld b,5
+        0x10, 0xFE,       // 8005  djnz $8005
+        0xCF, 0x42,       // 8007  rst 8 / db $42   inline argument
+        0x76,             // 8009  halt
+    ]);
+    m.cpu.mem.write(0x8010, &[0xCD, 0x20, 0x80, 0xC9]); // call $8020 / ret
+    m.cpu.mem.write(0x8020, &[0xC9]); // ret
+    // rst 8 skips the byte after it: ex (sp),hl / inc hl / ex (sp),hl / ret
+    m.cpu.mem.write(0x0008, &[0xE3, 0x23, 0xE3, 0xC9]);
+    m.cpu.reg.set_pc(0x8000);
+    m.cpu.reg.set_sp(0xF000);
+
+    // Over a call with a nested call and return inside
+    let goal = Goal::step_over(&m.cpu).expect("call");
+    assert_eq!(run_to(&mut m, goal), 4);
+    assert_eq!((m.cpu.reg.pc(), m.cpu.reg.sp()), (0x8003, 0xF000));
+
+    // Plain instructions are simply stepped
+    assert_eq!(Goal::step_over(&m.cpu), None);
+    m.step().unwrap();
+
+    // Over the whole DJNZ loop
+    let goal = Goal::step_over(&m.cpu).expect("djnz");
+    assert_eq!(goal, Goal::Reach { addr: 0x8007, sp: 0xF000 });
+    assert_eq!(run_to(&mut m, goal), 5);
+
+    // Over an RST that returns past its inline byte
+    let goal = Goal::step_over(&m.cpu).expect("rst");
+    run_to(&mut m, goal);
+    assert_eq!(m.cpu.reg.pc(), 0x8009);
+
+    // Out of the nested routine, then out of its caller
+    m.cpu.reg.set_pc(0x8000);
+    m.step().unwrap();
+    m.step().unwrap();
+    assert_eq!(m.cpu.reg.pc(), 0x8020);
+    let goal = Goal::step_out(&m.cpu);
+    run_to(&mut m, goal);
+    assert_eq!((m.cpu.reg.pc(), m.cpu.reg.sp()), (0x8013, 0xEFFE));
+    let goal = Goal::step_out(&m.cpu);
+    run_to(&mut m, goal);
+    assert_eq!((m.cpu.reg.pc(), m.cpu.reg.sp()), (0x8003, 0xF000));
+
+    // Run to an address
+    assert_eq!(run_to(&mut m, Goal::run_to(0x8007)), 6);
+
+    // A number of instructions, wherever they lead
+    assert_eq!(run_to(&mut m, Goal::steps(3)), 3);
+    assert_eq!(m.cpu.reg.pc(), 0x000A);
+    assert_eq!(run_to(&mut m, Goal::steps(1)), 1);
+
+    // Conditional calls inside a routine called from $8003
+    #[rustfmt::skip]
+    m.cpu.mem.write(0x8030, &[
+        0xAF,             // 8030  xor a
+        0xC4, 0x20, 0x80, // 8031  call nz,$8020
+        0xFE, 0x01,       // 8034  cp 1
+        0xCC, 0x20, 0x80, // 8036  call z,$8020
+        0xC4, 0x20, 0x80, // 8039  call nz,$8020
+        0xC9,             // 803C  ret
+    ]);
+    m.cpu.mem.write(0xEFFE, &[0x03, 0x80]);
+    m.cpu.reg.set_pc(0x8030);
+    m.cpu.reg.set_sp(0xEFFE);
+    m.step().unwrap();
+
+    // Not taken: stops on the next instruction, not back in the caller
+    let goal = Goal::step_over(&m.cpu).expect("call nz");
+    assert_eq!(run_to(&mut m, goal), 1);
+    assert_eq!((m.cpu.reg.pc(), m.cpu.reg.sp()), (0x8034, 0xEFFE));
+    m.step().unwrap();
+    let goal = Goal::step_over(&m.cpu).expect("call z");
+    assert_eq!(run_to(&mut m, goal), 1);
+    assert_eq!((m.cpu.reg.pc(), m.cpu.reg.sp()), (0x8039, 0xEFFE));
+
+    // Taken: back from the routine
+    let goal = Goal::step_over(&m.cpu).expect("call nz");
+    assert_eq!(run_to(&mut m, goal), 2);
+    assert_eq!((m.cpu.reg.pc(), m.cpu.reg.sp()), (0x803C, 0xEFFE));
+}
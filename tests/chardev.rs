@@ -0,0 +1,159 @@
+//! Socket and pseudo-terminal console devices
+
+#![cfg(unix)]
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::UnixStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use retro_z80_emulator::chardev::{CharDevice, PtyDevice, SocketServerDevice};
+
+/// Poll a device until it has a byte, for up to a second
+fn read(device: &mut dyn CharDevice) -> Option<u8> {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(1) {
+        if let Some(c) = device.read() {
+            return Some(c);
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    None
+}
+
+/// Poll a device until the client has gone
+fn wait_for_detach(device: &mut SocketServerDevice) {
+    let start = Instant::now();
+    while device.connected() && start.elapsed() < Duration::from_secs(1) {
+        assert_eq!(device.read(), None);
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert!(!device.connected());
+}
+
+/// Connect, exchange bytes both ways, turn a second client away,
+/// disconnect and reconnect
+fn serve<S: Read + Write>(mut device: SocketServerDevice, connect: impl Fn() -> S) {
+    // Output with nobody attached is dropped
+    device.write(b'-');
+    assert!(!device.connected());
+
+    let mut client = connect();
+    client.write_all(b"hi").unwrap();
+    assert_eq!(read(&mut device), Some(b'h'));
+    assert_eq!(read(&mut device), Some(b'i'));
+    assert!(device.connected());
+    device.write(b'X');
+    let mut buf = [0u8; 1];
+    client.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"X");
+
+    let mut second = connect();
+    assert_eq!(device.read(), None);
+    let mut busy = Vec::new();
+    second.read_to_end(&mut busy).unwrap();
+    assert_eq!(busy, b"Serial port busy\r\n");
+
+    drop(client);
+    wait_for_detach(&mut device);
+
+    let mut client = connect();
+    client.write_all(b"again").unwrap();
+    assert_eq!(read(&mut device), Some(b'a'));
+    device.write(b'Y');
+    client.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"Y");
+}
+
+#[test]
+fn tcp_server() {
+    let device = SocketServerDevice::tcp("127.0.0.1:0", false, false).unwrap();
+    let addr = device.local_addr();
+    assert_eq!(device.describe(), Some(addr.clone()));
+    serve(device, || TcpStream::connect(&addr).unwrap());
+}
+
+#[test]
+fn unix_server() {
+    let path = std::env::temp_dir().join(format!("retroshield-test-{}.sock", std::process::id()));
+    let device = SocketServerDevice::unix(&path, false, false).unwrap();
+    serve(device, || UnixStream::connect(&path).unwrap());
+    // The socket file goes with the device
+    assert!(!path.exists());
+}
+
+#[test]
+fn waits_for_a_client() {
+    let mut device = SocketServerDevice::tcp("127.0.0.1:0", true, false).unwrap();
+    let addr = device.local_addr();
+    let client = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut buf = [0u8; 1];
+        stream.read_exact(&mut buf).unwrap();
+        buf[0]
+    });
+
+    // Blocks until the client attaches, so the byte is not lost
+    let start = Instant::now();
+    device.write(b'A');
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert!(device.connected());
+    assert_eq!(client.join().unwrap(), b'A');
+}
+
+#[test]
+fn telnet_clients() {
+    let mut device = SocketServerDevice::tcp("127.0.0.1:0", false, true).unwrap();
+    let mut client = TcpStream::connect(device.local_addr()).unwrap();
+    // Option negotiation, CR NUL, an escaped IAC, a subnegotiation
+    client.write_all(&[255, 253, 1, b'a', b'\r', 0, b'b', 255, 255, 255, 250, 24, 0, 255, 240, b'c']).unwrap();
+    let received: Vec<u8> = std::iter::from_fn(|| read(&mut device)).take(5).collect();
+    assert_eq!(received, [b'a', b'\r', b'b', 255, b'c']);
+
+    // Put in character mode on connecting; IAC is doubled on the way out
+    device.write(255);
+    let mut buf = [0u8; 8];
+    client.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [255, 251, 1, 255, 251, 3, 255, 255]);
+}
+
+#[test]
+fn pty_round_trip() {
+    let link = std::env::temp_dir().join(format!("retroshield-test-pty-{}", std::process::id()));
+    let mut device = PtyDevice::open(Some(&link)).unwrap();
+    let path = device.path().to_path_buf();
+    assert_eq!(device.describe(), Some(format!("{} ({})", path.display(), link.display())));
+    assert_eq!(std::fs::read_link(&link).unwrap(), path);
+
+    // Raw mode: bytes pass unchanged both ways
+    let mut terminal = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(&link)
+        .unwrap();
+    terminal.write_all(b"a\r\x03").unwrap();
+    assert_eq!(read(&mut device), Some(b'a'));
+    assert_eq!(read(&mut device), Some(b'\r'));
+    assert_eq!(read(&mut device), Some(0x03));
+    assert_eq!(device.read(), None);
+    for &c in b"ok\n" {
+        device.write(c);
+    }
+    let mut buf = [0u8; 3];
+    terminal.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ok\n");
+
+    // With nobody reading, output is dropped once the buffer fills
+    let start = Instant::now();
+    for _ in 0..1_000_000 {
+        device.write(b'x');
+    }
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    drop(device);
+    assert!(std::fs::symlink_metadata(&link).is_err());
+}
@@ -0,0 +1,158 @@
+//! Debugger console command parsing, history and completion
+
+use retro_z80_emulator::console::{self, complete, Command, History};
+use retro_z80_emulator::expr::Expr;
+use retro_z80_emulator::symbols::Symbols;
+use retro_z80_emulator::watchpoint::{Space, Watch};
+use rz80::CPU;
+
+fn cpu() -> CPU {
+    let mut cpu = CPU::new_64k();
+    cpu.reg.set_pc(0x0100);
+    cpu.reg.set_hl(0x2000);
+    cpu
+}
+
+fn parse(line: &str) -> Command {
+    Command::parse(line, &cpu(), &Symbols::new()).unwrap_or_else(|e| panic!("{}: {}", line, e)).expect("a command")
+}
+
+fn error(line: &str) -> String {
+    Command::parse(line, &cpu(), &Symbols::new()).expect_err(line)
+}
+
+#[test]
+fn numbers_are_hex() {
+    assert_eq!(Expr::parse_radix("0100", 16).unwrap().eval(&cpu()), 0x100);
+    assert_eq!(Expr::parse_radix("#100 + $10 + 0x10", 16).unwrap().eval(&cpu()), 132);
+    assert_eq!(Expr::parse_radix("hl+10", 16).unwrap().eval(&cpu()), 0x2010);
+    assert_eq!(Expr::parse("#100").unwrap().eval(&cpu()), 100);
+    assert_eq!(Expr::parse_radix("ff00 + bc + 0bc", 16).unwrap().eval(&cpu()), 0xFFBC);
+    assert!(Expr::parse("ff00").is_err());
+}
+
+#[test]
+fn commands() {
+    assert_eq!(parse("b 0100"), Command::Break(Some((0x0100, None, 1))));
+    assert_eq!(parse("b"), Command::Break(None));
+    let Command::Break(Some((addr, Some(condition), after))) = parse("b 0A20 if a>10 after 10") else {
+        panic!("conditional breakpoint");
+    };
+    assert_eq!((addr, condition.to_string(), after), (0x0A20, "a>10".to_string(), 16));
+    assert_eq!(parse("d all"), Command::Delete(None));
+    assert_eq!(parse("wt 2"), Command::WatchToggle(2));
+
+    let watch = Watch { space: Space::Memory, start: 0x2043, end: 0x2043, read: true, write: true };
+    assert_eq!(parse("w 2043 rw"), Command::Watch(Some(watch)));
+
+    assert_eq!(parse("m 8000 40"), Command::Memory(Some(0x8000), 0x40));
+    assert_eq!(parse("m"), Command::Memory(None, console::DUMP_LEN));
+    assert_eq!(parse("fill 8000 8100 00"), Command::Fill { start: 0x8000, end: 0x8100, byte: 0 });
+    assert_eq!(parse("fill ff00 10000 e5"), Command::Fill { start: 0xFF00, end: 0x10000, byte: 0xE5 });
+    assert_eq!(parse("copy 8000 8010 hl"), Command::Copy { start: 0x8000, end: 0x8010, dest: 0x2000 });
+    assert_eq!(parse("load file.bin 8000"), Command::Load { path: "file.bin".to_string(), addr: 0x8000 });
+    let save = Command::Save { start: 0x8000, end: 0x9000, path: "out.bin".to_string() };
+    assert_eq!(parse("save 8000 9000 out.bin"), save);
+    assert_eq!(parse("r pc=0100 A=ff im=2"), Command::Registers(vec![
+        ("pc".to_string(), 0x100),
+        ("a".to_string(), 0xFF),
+        ("im".to_string(), 2),
+    ]));
+    assert_eq!(parse("dis 0100"), Command::Disassemble(Some(0x100), console::DIS_COUNT));
+    assert_eq!(parse("dis pc 4"), Command::Disassemble(Some(0x100), 4));
+    assert_eq!(parse("trace on"), Command::TraceOn(None));
+    assert_eq!(parse("trace on out.log"), Command::TraceOn(Some("out.log".to_string())));
+    assert_eq!(parse("trace off"), Command::TraceOff);
+    assert_eq!(parse("g"), Command::Go(None));
+    assert_eq!(parse("g 0200"), Command::Go(Some(0x200)));
+    assert_eq!(parse("s 10"), Command::Step(16));
+    assert_eq!(Command::parse("   ", &cpu(), &Symbols::new()), Ok(None));
+}
+
+#[test]
+fn command_errors() {
+    assert_eq!(error("frob"), "unknown command \"frob\" (try help)");
+    assert_eq!(error("fill 8000 8100"), "usage: fill START END BYTE");
+    assert_eq!(error("over 1"), "over takes no arguments");
+    assert_eq!(error("fill 8100 8000 0"), "end $8000 is not after start $8100");
+    assert_eq!(error("fill 8000 8100 100"), "byte 256 is out of range");
+    assert_eq!(error("copy 8000 9000 f800"), "copy runs past the top of memory");
+    assert_eq!(error("r pc"), "expected NAME=VALUE, found \"pc\"");
+    assert_eq!(error("r xy=1"), "unknown register \"xy\"");
+    assert_eq!(error("m 10000"), "address 65536 is out of range");
+    assert_eq!(error("s 0"), "count 0 is out of range");
+    assert!(error("b 0100 if").contains("condition"));
+}
+
+#[test]
+fn registers_dump_and_disassembly() {
+    let mut cpu = cpu();
+    console::set_register(&mut cpu, "im", 2).unwrap();
+    console::set_register(&mut cpu, "iff1", 1).unwrap();
+    console::set_register(&mut cpu, "af", 0x12C1).unwrap();
+    assert!(console::set_register(&mut cpu, "im", 3).is_err());
+    let [main, _, flags] = console::registers(&cpu);
+    assert!(main.starts_with("PC=0100 SP="), "{}", main);
+    assert!(main.contains(" AF=12C1 "), "{}", main);
+    assert_eq!(flags, "Flags: SZ-----C");
+    assert!(console::registers(&cpu)[1].ends_with("IM=2 IFF1=1 IFF2=0"));
+
+    cpu.mem.write(0x8000, b"Hello\x00\x01");
+    let lines = console::dump(&cpu, 0x8000, 0x14);
+    assert_eq!(lines.len(), 2);
+    assert_eq!(&lines[0][..27], "8000: 48 65 6C 6C 6F 00 01 ");
+    assert!(lines[0].ends_with("  Hello..........."));
+    assert!(lines[1].starts_with("8010: 00 00 00 00") && lines[1].ends_with("  ...."));
+    assert_eq!(console::dump(&cpu, 0xFFF8, 0x40).len(), 1);
+
+    cpu.mem.write(0x0100, &[0x3E, 0x2A, 0xC3, 0x00, 0x01]);
+    let (lines, next) = console::disassembly(&cpu, 0x0100, 2, &Symbols::new());
+    assert_eq!(lines, ["0100: 3E 2A       LD A,$2A", "0102: C3 00 01    JP $0100"]);
+    assert_eq!(next, 0x0105);
+}
+
+#[test]
+fn history() {
+    let mut history = History::new();
+    assert_eq!(history.older(), None);
+    history.push("m 8000");
+    history.push("dis");
+    history.push("dis");
+    history.push("  ");
+    assert_eq!(history.older(), Some("dis"));
+    assert_eq!(history.older(), Some("m 8000"));
+    assert_eq!(history.older(), None);
+    assert_eq!(history.newer(), Some("dis"));
+    assert_eq!(history.newer(), Some(""));
+    assert_eq!(history.newer(), None);
+}
+
+#[test]
+fn completion() {
+    let none = Symbols::new();
+    let c = complete("tr", &none);
+    assert_eq!((c.line.as_str(), c.candidates.len()), ("trace ", 0));
+    let c = complete("w", &none);
+    assert_eq!(c.line, "w");
+    assert_eq!(c.candidates, ["w", "wd", "wt"]);
+    assert_eq!(complete("r pc=0 hl", &none).line, "r pc=0 hl");
+    assert_eq!(complete("r pc=0 hl'", &none).line, "r pc=0 hl'=");
+    assert_eq!(complete("r i", &none).candidates, ["i", "iff1", "iff2", "im", "ix", "ixh", "ixl", "iy", "iyh", "iyl"]);
+    assert_eq!(complete("m 80", &none).candidates.len(), 0);
+
+    let dir = std::env::temp_dir().join(format!("retroshield-test-complete-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("roms")).unwrap();
+    std::fs::write(dir.join("program.bin"), [0]).unwrap();
+    let dir = dir.to_str().unwrap();
+    assert_eq!(complete(&format!("load {}/pro", dir), &none).line, format!("load {}/program.bin ", dir));
+    assert_eq!(complete(&format!("save 0 10 {}/r", dir), &none).line, format!("save 0 10 {}/roms/", dir));
+    let c = complete(&format!("load {}/", dir), &none);
+    assert_eq!(c.candidates, ["program.bin", "roms/"]);
+
+    // Names sharing a prefix beyond ASCII
+    std::fs::write(format!("{}/aé1", dir), [0]).unwrap();
+    std::fs::write(format!("{}/aé2", dir), [0]).unwrap();
+    let c = complete(&format!("load {}/a", dir), &none);
+    assert_eq!((c.line, c.candidates), (format!("load {}/aé", dir), vec!["aé1".to_string(), "aé2".to_string()]));
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,234 @@
+//! Disassembler output and instruction lengths across every prefix group,
+//! and the ROM disassembler tracing a small image
+
+use retro_z80_emulator::disasm::{back_up, disassemble_bytes, Flow, MAX_INSTRUCTION_LEN};
+
+fn check(bytes: &[u8], text: &str) {
+    let ins = disassemble_bytes(bytes, 0x1000);
+    assert_eq!(ins.text, text, "{:02X?}", bytes);
+    assert_eq!(ins.len as usize, bytes.len(), "length of {} ({:02X?})", text, bytes);
+}
+
+#[test]
+fn unprefixed() {
+    check(&[0x00], "NOP");
+    check(&[0x08], "EX AF,AF'");
+    check(&[0x01, 0x34, 0x12], "LD BC,$1234");
+    check(&[0x22, 0x00, 0x80], "LD ($8000),HL");
+    check(&[0x3A, 0xFF, 0x7F], "LD A,($7FFF)");
+    check(&[0x36, 0x2A], "LD (HL),$2A");
+    check(&[0x76], "HALT");
+    check(&[0x7E], "LD A,(HL)");
+    check(&[0x96], "SUB (HL)");
+    check(&[0xC6, 0x01], "ADD A,$01");
+    check(&[0xE3], "EX (SP),HL");
+    check(&[0xE9], "JP (HL)");
+    check(&[0xF1], "POP AF");
+    check(&[0xD3, 0x80], "OUT ($80),A");
+    check(&[0xDB, 0x81], "IN A,($81)");
+    check(&[0xFF], "RST $38");
+}
+
+#[test]
+fn jumps_show_their_targets() {
+    check(&[0x18, 0xFE], "JR $1000");
+    check(&[0x20, 0x10], "JR NZ,$1012");
+    check(&[0x10, 0x80], "DJNZ $0F82");
+    check(&[0xC3, 0x00, 0x20], "JP $2000");
+    check(&[0xDC, 0x34, 0x12], "CALL C,$1234");
+
+    assert_eq!(disassemble_bytes(&[0x38, 0x05], 0x1000).target, Some(0x1007));
+    assert_eq!(disassemble_bytes(&[0xCD, 0x00, 0x30], 0x1000).target, Some(0x3000));
+    assert_eq!(disassemble_bytes(&[0xD7], 0x1000).target, Some(0x0010));
+    assert_eq!(disassemble_bytes(&[0xC9], 0x1000).target, None);
+}
+
+#[test]
+fn control_flow() {
+    let flow = |bytes: &[u8]| disassemble_bytes(bytes, 0x1000).flow;
+    assert_eq!(flow(&[0x00]), Flow::Next);
+    assert_eq!(flow(&[0xC3, 0x00, 0x20]), Flow::Jump);
+    assert_eq!(flow(&[0x18, 0x00]), Flow::Jump);
+    assert_eq!(flow(&[0xE9]), Flow::Jump);
+    assert_eq!(flow(&[0xDD, 0xE9]), Flow::Jump);
+    assert_eq!(flow(&[0x20, 0x00]), Flow::Branch);
+    assert_eq!(flow(&[0x10, 0x00]), Flow::Branch);
+    assert_eq!(flow(&[0xCA, 0x00, 0x20]), Flow::Branch);
+    assert_eq!(flow(&[0xCD, 0x00, 0x20]), Flow::Call);
+    assert_eq!(flow(&[0xC4, 0x00, 0x20]), Flow::Call);
+    assert_eq!(flow(&[0xEF]), Flow::Restart);
+    assert_eq!(flow(&[0xC9]), Flow::Return);
+    assert_eq!(flow(&[0xED, 0x4D]), Flow::Return);
+    assert_eq!(flow(&[0xD8]), Flow::ConditionalReturn);
+    assert_eq!(disassemble_bytes(&[0xCD, 0x00, 0x20], 0).text_with_target("init"), "CALL init");
+}
+
+#[test]
+fn cb_prefix() {
+    check(&[0xCB, 0x00], "RLC B");
+    check(&[0xCB, 0x36], "SLL (HL)");
+    check(&[0xCB, 0x3F], "SRL A");
+    check(&[0xCB, 0x46], "BIT 0,(HL)");
+    check(&[0xCB, 0xBF], "RES 7,A");
+    check(&[0xCB, 0xD1], "SET 2,C");
+}
+
+#[test]
+fn ed_prefix() {
+    check(&[0xED, 0xB0], "LDIR");
+    check(&[0xED, 0xBB], "OTDR");
+    check(&[0xED, 0xA1], "CPI");
+    check(&[0xED, 0x44], "NEG");
+    check(&[0xED, 0x4D], "RETI");
+    check(&[0xED, 0x45], "RETN");
+    check(&[0xED, 0x5E], "IM 2");
+    check(&[0xED, 0x47], "LD I,A");
+    check(&[0xED, 0x6F], "RLD");
+    check(&[0xED, 0x78], "IN A,(C)");
+    check(&[0xED, 0x70], "IN (C)");
+    check(&[0xED, 0x71], "OUT (C),0");
+    check(&[0xED, 0x42], "SBC HL,BC");
+    check(&[0xED, 0x7A], "ADC HL,SP");
+    check(&[0xED, 0x43, 0x00, 0x90], "LD ($9000),BC");
+    check(&[0xED, 0x7B, 0xFE, 0xFF], "LD SP,($FFFE)");
+    // Undefined ED opcodes are two-byte NOPs
+    check(&[0xED, 0x00], "DB $ED,$00");
+    check(&[0xED, 0x77], "DB $ED,$77");
+}
+
+#[test]
+fn index_prefixes() {
+    check(&[0xDD, 0x21, 0x00, 0x40], "LD IX,$4000");
+    check(&[0xFD, 0x2A, 0x00, 0x40], "LD IY,($4000)");
+    check(&[0xDD, 0x7E, 0x05], "LD A,(IX+$05)");
+    check(&[0xFD, 0x77, 0xFB], "LD (IY-$05),A");
+    check(&[0xDD, 0x36, 0x02, 0x2A], "LD (IX+$02),$2A");
+    check(&[0xDD, 0x34, 0x80], "INC (IX-$80)");
+    check(&[0xDD, 0x86, 0x7F], "ADD A,(IX+$7F)");
+    check(&[0xDD, 0x29], "ADD IX,IX");
+    check(&[0xFD, 0x09], "ADD IY,BC");
+    check(&[0xDD, 0xE5], "PUSH IX");
+    check(&[0xFD, 0xE9], "JP (IY)");
+    check(&[0xDD, 0xF9], "LD SP,IX");
+    check(&[0xFD, 0xE3], "EX (SP),IY");
+    // H and L stay themselves next to a memory operand
+    check(&[0xDD, 0x66, 0x01], "LD H,(IX+$01)");
+    check(&[0xDD, 0x75, 0x01], "LD (IX+$01),L");
+}
+
+#[test]
+fn undocumented_index_halves() {
+    check(&[0xDD, 0x24], "INC IXH");
+    check(&[0xFD, 0x2E, 0x10], "LD IYL,$10");
+    check(&[0xDD, 0x65], "LD IXH,IXL");
+    check(&[0xDD, 0x7C], "LD A,IXH");
+    check(&[0xFD, 0xA5], "AND IYL");
+}
+
+#[test]
+fn index_cb() {
+    check(&[0xDD, 0xCB, 0x05, 0x06], "RLC (IX+$05)");
+    check(&[0xFD, 0xCB, 0xFF, 0x46], "BIT 0,(IY-$01)");
+    check(&[0xFD, 0xCB, 0x10, 0x7E], "BIT 7,(IY+$10)");
+    check(&[0xDD, 0xCB, 0x00, 0xFE], "SET 7,(IX+$00)");
+    // Undocumented: the result is also copied to a register
+    check(&[0xDD, 0xCB, 0x05, 0x00], "RLC (IX+$05),B");
+    check(&[0xFD, 0xCB, 0x01, 0x8F], "RES 1,(IY+$01),A");
+    // BIT has no register copy; all z forms read the same
+    check(&[0xDD, 0xCB, 0x05, 0x41], "BIT 0,(IX+$05)");
+}
+
+#[test]
+fn ineffective_prefixes_stand_alone() {
+    check(&[0xDD], "DB $DD");
+    assert_eq!(disassemble_bytes(&[0xDD, 0x00], 0).text, "DB $DD");
+    assert_eq!(disassemble_bytes(&[0xDD, 0x00], 0).len, 1);
+    assert_eq!(disassemble_bytes(&[0xFD, 0xEB], 0).len, 1);
+    assert_eq!(disassemble_bytes(&[0xDD, 0xFD, 0x21, 0, 0], 0).len, 1);
+    assert_eq!(disassemble_bytes(&[0xDD, 0xED, 0xB0], 0).len, 1);
+    // DD before a jump is ignored, and must not keep the target
+    assert_eq!(disassemble_bytes(&[0xDD, 0xC3, 0, 0], 0).target, None);
+}
+
+#[test]
+fn every_encoding_has_a_sane_length() {
+    for prefix in [&[][..], &[0xCB], &[0xED], &[0xDD], &[0xFD], &[0xDD, 0xCB, 0x05], &[0xFD, 0xCB, 0x05]] {
+        for op in 0..=255u8 {
+            let mut bytes = prefix.to_vec();
+            bytes.extend_from_slice(&[op, 0x12, 0x34, 0x56]);
+            let ins = disassemble_bytes(&bytes, 0);
+            assert!((1..=MAX_INSTRUCTION_LEN).contains(&ins.len), "{:02X?}: {}", &bytes[..prefix.len() + 1], ins.len);
+            assert!(!ins.text.is_empty());
+        }
+    }
+}
+
+#[test]
+fn back_up_lands_on_the_address() {
+    // LD A,$3E / LD (IX+$05),$3E / LD IX,$3E3E, then the target NOP
+    let code = [0x3E, 0x3E, 0xDD, 0x36, 0x05, 0x3E, 0xDD, 0x21, 0x3E, 0x3E, 0x00];
+    let read = |a: u16| code.get(a as usize).copied().unwrap_or(0);
+    assert_eq!(back_up(read, 10, 3), 0);
+    assert_eq!(back_up(read, 10, 2), 2);
+    assert_eq!(back_up(read, 10, 1), 6);
+}
+
+/// A ROM at $0000 that sets up IM 2 with its table at $0100 and calls a
+/// routine that points at a string
+#[rustfmt::skip]
+const IMAGE: [u8; 0x32] = [
+    0xF3,             // 0000  di
+    0x31, 0x00, 0xF0, // 0001  ld sp,$f000
+    0x3E, 0x01,       // 0004  ld a,$01
+    0xED, 0x47,       // 0006  ld i,a
+    0xED, 0x5E,       // 0008  im 2
+    0xFB,             // 000A  ei
+    0xCD, 0x20, 0x00, // 000B  call $0020
+    0x18, 0xFE,       // 000E  jr $
+    b'H', b'e', b'l', b'l', b'o', 0x00, b'x', b'y', b'z', // 0010
+    0, 0, 0, 0, 0, 0, 0,
+    0x21, 0x10, 0x00, // 0020  ld hl,$0010
+    0xC9,             // 0023  ret
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0x3E, 0x2A,       // 0030  ld a,$2a, the ACIA's handler
+];
+
+#[test]
+fn traced_image() {
+    let dir = std::env::temp_dir().join(format!("retroshield-test-disasm-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut image = IMAGE.to_vec();
+    // RETI, then the table entry for vector $10
+    image.extend_from_slice(&[0xED, 0x4D]);
+    image.resize(0x110, 0);
+    image.extend_from_slice(&[0x30, 0x00]);
+    std::fs::write(dir.join("rom.bin"), &image).unwrap();
+    std::fs::write(dir.join("rom.sym"), "print = $0020\n").unwrap();
+    std::fs::write(dir.join("rom.toml"), "[serial]\nchip = \"6850\"\n[interrupts]\nmode = \"serial\"\nacia_vector = 0x10\n").unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_retroshield-disasm"))
+        .args(["-n", dir.join("rom.bin").to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let text = String::from_utf8(output.stdout).unwrap();
+    // The statements, without the address and byte comments
+    let lines: Vec<&str> = text.lines().map(|l| l.split(';').next().unwrap().trim_end()).collect();
+    let has = |line: &str| lines.contains(&line);
+
+    assert_eq!(text.lines().next(), Some("; rom.bin: 274 bytes at $0000, 24 traced as code"));
+    // Labels for the entry, jump targets and the IM 2 handler; the symbol
+    // file names one
+    for label in ["L0000:", "L000E:", "print:", "L0030:"] {
+        assert!(has(label), "{} missing from\n{}", label, text);
+    }
+    assert!(has("        CALL    print"));
+    assert!(has("        JR      L000E"));
+    assert!(has("        LD      HL,$0010"));
+    assert!(has("        RETI"));
+    // Untraced bytes are data, printable runs strings ending at a zero
+    assert!(has("        DB      \"Hello\",$00"));
+    assert!(has("        DB      $78,$79,$7A,$00,$00,$00,$00,$00,$00,$00"));
+    assert!(text.ends_with("        END\n"));
+    std::fs::remove_dir_all(dir).unwrap();
+}
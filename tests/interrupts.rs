@@ -0,0 +1,179 @@
+//! Interrupt delivery: IM 0, 1 and 2, /NMI, EI's delay and RETN
+
+use retro_z80_emulator::interrupt::InterruptController;
+use retro_z80_emulator::profile::Profile;
+use retro_z80_emulator::retroshield::Machine;
+use rz80::Bus;
+
+fn storage(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("retroshield-test-{}-{}", name, std::process::id()))
+}
+
+/// A machine running EI / NOP / NOP... at $2000 in interrupt mode `im`
+fn machine(im: i32) -> Machine {
+    let mut m = Machine::new(storage("int"));
+    m.cpu.mem.write(0x2000, &[0xFB, 0x00, 0x00, 0x00, 0x00]);
+    m.cpu.reg.set_pc(0x2000);
+    m.cpu.reg.set_sp(0xF000);
+    m.cpu.reg.im = im;
+    m
+}
+
+/// The return address an interrupt pushed
+fn pushed(m: &Machine) -> i32 {
+    m.cpu.mem.r16(m.cpu.reg.sp())
+}
+
+#[test]
+fn ei_delay_and_im1() {
+    let mut m = machine(1);
+    let line = m.system.interrupts.line("TEST");
+    line.assert();
+
+    // Not taken straight after EI, but after the instruction that follows
+    m.step().unwrap();
+    assert_eq!(m.cpu.reg.pc(), 0x2001);
+    m.step().unwrap();
+    assert_eq!((m.cpu.reg.pc(), pushed(&m)), (0x0038, 0x2002));
+    assert!(!m.cpu.iff1 && !m.cpu.iff2);
+
+    // Nothing more is taken while interrupts are disabled
+    m.cpu.mem.write(0x0038, &[0x00, 0x00]);
+    m.step().unwrap();
+    assert_eq!(m.cpu.reg.pc(), 0x0039);
+}
+
+#[test]
+fn im0_executes_the_bus_instruction() {
+    let mut m = machine(0);
+    let line = m.system.interrupts.line("TEST");
+
+    // Nothing driving the bus reads as RST 38H
+    line.assert();
+    m.step().unwrap();
+    m.step().unwrap();
+    assert_eq!(m.cpu.reg.pc(), 0x0038);
+
+    let mut m = machine(0);
+    let line = m.system.interrupts.line("TEST");
+    line.set_bus_data(&[0xCF]);
+    line.assert();
+    m.step().unwrap();
+    m.step().unwrap();
+    assert_eq!((m.cpu.reg.pc(), pushed(&m)), (0x0008, 0x2002));
+
+    let mut m = machine(0);
+    let line = m.system.interrupts.line("TEST");
+    line.set_bus_data(&[0xCD, 0x34, 0x12]);
+    line.assert();
+    m.step().unwrap();
+    m.step().unwrap();
+    assert_eq!((m.cpu.reg.pc(), pushed(&m)), (0x1234, 0x2002));
+}
+
+#[test]
+fn im2_uses_the_device_vector() {
+    let mut m = machine(2);
+    m.cpu.reg.i = 0x80;
+    m.cpu.mem.write(0x8010, &[0x56, 0x34]);
+    let line = m.system.interrupts.line("TEST");
+    line.set_bus_data(&[0x10]);
+    line.assert();
+    m.step().unwrap();
+    m.step().unwrap();
+    assert_eq!((m.cpu.reg.pc(), pushed(&m)), (0x3456, 0x2002));
+}
+
+#[test]
+fn serial_vector_from_the_profile() {
+    let profile = Profile::parse("[serial]\nchip = \"6850\"\n[interrupts]\nmode = \"serial\"\nacia_vector = 0x20\n").unwrap();
+    let mut m = Machine::with_profile(profile, storage("int-acia")).unwrap();
+    // EI / NOP / JR back to the NOP
+    m.cpu.mem.write(0x2000, &[0xFB, 0x00, 0x18, 0xFD]);
+    m.cpu.mem.write(0x8020, &[0x00, 0x90]);
+    m.cpu.reg.set_pc(0x2000);
+    m.cpu.reg.set_sp(0xF000);
+    m.cpu.reg.im = 2;
+    m.cpu.reg.i = 0x80;
+
+    // Master reset, then receive interrupts on
+    m.system.cpu_outp(0x80, 0x03);
+    m.system.cpu_outp(0x80, 0x96);
+    m.system.send_key(b'x');
+    for _ in 0..1000 {
+        m.step().unwrap();
+        if m.cpu.reg.pc() == 0x9000 {
+            return;
+        }
+    }
+    panic!("ACIA interrupt not taken through its vector");
+}
+
+#[test]
+fn halt_resumes_on_an_interrupt() {
+    let mut m = machine(1);
+    m.cpu.mem.write(0x2001, &[0x76]);
+    m.step().unwrap();
+    m.step().unwrap();
+    assert!(m.is_halted());
+    m.step().unwrap();
+    assert_eq!(m.cpu.reg.pc(), 0x2001);
+
+    m.system.interrupts.line("TEST").assert();
+    m.step().unwrap();
+    assert_eq!((m.cpu.reg.pc(), pushed(&m)), (0x0038, 0x2002));
+    assert!(!m.is_halted());
+}
+
+#[test]
+fn nmi_and_retn() {
+    let mut m = machine(1);
+    m.cpu.mem.write(0x0066, &[0xED, 0x45]);
+    m.step().unwrap();
+    m.step().unwrap();
+    assert!(m.cpu.iff1);
+
+    m.nmi();
+    m.step().unwrap();
+    assert_eq!((m.cpu.reg.pc(), pushed(&m)), (0x0066, 0x2003));
+    assert!(!m.cpu.iff1 && m.cpu.iff2);
+
+    // RETN restores IFF1 from IFF2
+    m.step().unwrap();
+    assert_eq!(m.cpu.reg.pc(), 0x2003);
+    assert!(m.cpu.iff1);
+
+    // Taken even with interrupts disabled, which RETN keeps so
+    m.cpu.iff1 = false;
+    m.cpu.iff2 = false;
+    m.nmi();
+    m.step().unwrap();
+    assert_eq!((m.cpu.reg.pc(), pushed(&m)), (0x0066, 0x2004));
+    m.step().unwrap();
+    assert_eq!(m.cpu.reg.pc(), 0x2004);
+    assert!(!m.cpu.iff1);
+}
+
+#[test]
+fn daisy_chain_priority() {
+    let controller = InterruptController::new();
+    let first = controller.line("FIRST");
+    let second = controller.line("SECOND");
+    first.set_bus_data(&[0x10]);
+    second.set_bus_data(&[0x20]);
+    assert!(!controller.int_asserted());
+    assert_eq!(controller.acknowledge(), [0xFF; 3]);
+
+    second.assert();
+    assert_eq!(controller.acknowledge(), [0x20, 0xFF, 0xFF]);
+    first.assert();
+    assert_eq!(controller.asserted_by(), ["FIRST", "SECOND"]);
+    assert_eq!(controller.acknowledge(), [0x10, 0xFF, 0xFF]);
+    first.release();
+    assert!(second.is_asserted() && !first.is_asserted());
+
+    controller.nmi();
+    controller.reset();
+    assert!(!controller.int_asserted());
+    assert!(!controller.take_nmi());
+}
@@ -0,0 +1,195 @@
+//! Assembler listings, source lines and stepping by line
+
+use retro_z80_emulator::breakpoint::is_return;
+use retro_z80_emulator::console::Command;
+use retro_z80_emulator::listing::{LineStep, Listing, Location};
+use retro_z80_emulator::retroshield::Machine;
+use retro_z80_emulator::symbols::Symbols;
+use rz80::CPU;
+
+/// sjasmplus listing of a program at $8000 with an included file
+const PROGRAM: &str = "\
+# file opened: main.asm
+1     0000              ; Test program
+2     0000              \torg $8000
+3     8000 31 00 F0     start:\tld sp,$f000
+4     8003 CD 10 80     \tcall print
+5     8006 CD 40 00     \tcall $0040
+6     8009 06 02        \tld b,2
+7     800B 10 FE        loop:\tdjnz loop
+8     800D 18 FE        \tjr $
+9     800F              \tinclude \"util.asm\"
+# file opened: util.asm
+1+    800F              ; Print
+2+    800F              \torg $8010
+3+    8010 3E 2A        print:\tld a,42
+4+    8012 C9           \tret
+# file closed: util.asm
+10    8013              table:\tdb 1,2,3,4,5
+10    8013 01 02 03 04
+10    8017 05
+# file closed: main.asm
+";
+
+fn program() -> Listing {
+    let mut listing = Listing::new();
+    assert_eq!(listing.read(PROGRAM, "prog.asm"), 10);
+    listing
+}
+
+/// `FILE:LINE` of a location
+fn at(listing: &Listing, at: Location) -> String {
+    format!("{}:{}", listing.files[at.file].name, listing.line(at).number)
+}
+
+#[test]
+fn sjasmplus_listing() {
+    let listing = program();
+    let names: Vec<&str> = listing.files.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, ["main.asm", "util.asm"]);
+    assert_eq!(listing.files[0].lines.len(), 10);
+    assert_eq!(listing.files[1].lines.len(), 4);
+
+    let start = listing.locate(0x8000).unwrap();
+    assert_eq!(listing.line(start).text, "start:  ld sp,$f000");
+    assert_eq!(listing.line(start).code, Some((0x8000, 3)));
+    assert_eq!(at(&listing, listing.locate(0x8002).unwrap()), "main.asm:3");
+    assert_eq!(at(&listing, listing.locate(0x8011).unwrap()), "util.asm:3");
+    assert_eq!(listing.line(listing.locate(0x8012).unwrap()).text, "        ret");
+
+    // Rows repeating a line number belong to that line
+    let table = listing.locate(0x8017).unwrap();
+    assert_eq!(at(&listing, table), "main.asm:10");
+    assert_eq!(listing.line(table).text, "table:  db 1,2,3,4,5");
+    assert_eq!(listing.line(table).code, Some((0x8013, 4)));
+
+    assert_eq!(listing.locate(0x800F), None);
+    assert_eq!(listing.locate(0x8018), None);
+    assert_eq!(listing.line_start(0x8003), listing.locate(0x8003));
+    assert_eq!(listing.line_start(0x8004), None);
+}
+
+#[test]
+fn zmac_and_z80asm_listings() {
+    let mut listing = Listing::new();
+    let zmac = "\
+   1:                   ; zmac
+   2:    0100                   org 100h
+   3:    0100  3e 2a    start:  ld a,42
+   4:    0102  c3 00 01         jp start
+
+    Symbol Table:
+
+start             100
+";
+    assert_eq!(listing.read(zmac, "zmac.asm"), 2);
+    assert_eq!(at(&listing, listing.locate(0x0103).unwrap()), "zmac.asm:4");
+    assert_eq!(listing.line(listing.locate(0x0100).unwrap()).text, "start:  ld a,42");
+
+    let z80asm = "\
+1                       ; z80asm
+2     0200  21 00 80            ld hl,$8000
+3     0203  7E          loop:   ld a,(hl)
+";
+    assert_eq!(listing.read(z80asm, "z80asm.asm"), 2);
+    assert_eq!(at(&listing, listing.locate(0x0203).unwrap()), "z80asm.asm:3");
+    assert_eq!(listing.line(listing.locate(0x0200).unwrap()).code, Some((0x0200, 3)));
+    assert_eq!(listing.files.len(), 2);
+}
+
+#[test]
+fn file_and_line_addresses() {
+    let listing = program();
+    assert_eq!(listing.find("main.asm", 4), Ok(0x8003));
+    assert_eq!(listing.find("main", 9), Ok(0x8013));
+    assert_eq!(listing.find("src/util.asm", 1), Ok(0x8010));
+    assert_eq!(listing.find("util", 4), Ok(0x8012));
+    assert_eq!(listing.find("util", 5), Err("no code at or after util:5".to_string()));
+    assert_eq!(listing.find("other.asm", 1), Err("no source file \"other.asm\"".to_string()));
+
+    assert_eq!(listing.resolve("b main.asm:7 if b==1"), Ok("b $800B if b==1".to_string()));
+    assert_eq!(listing.resolve("g util:3"), Ok("g $8010".to_string()));
+    assert_eq!(listing.resolve("r pc=0100"), Ok("r pc=0100".to_string()));
+    assert!(listing.resolve("b main:99").is_err());
+}
+
+#[test]
+fn files() {
+    let dir = std::env::temp_dir().join(format!("retroshield-test-listing-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("prog.lst"), "3     0100  3E 2A       ld a,42\n").unwrap();
+    std::fs::write(dir.join("prog.sym"), "start = $0100\n").unwrap();
+
+    let mut listing = Listing::new();
+    assert_eq!(listing.load(&dir.join("prog.lst")), Ok(1));
+    assert_eq!(listing.files[0].name, "prog.asm");
+    assert!(listing.load(&dir.join("prog.sym")).unwrap_err().ends_with("no listing lines found"));
+    assert!(listing.load(&dir.join("missing.lst")).is_err());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// Step a source line, returning the number of instructions it took
+fn step_line(machine: &mut Machine, listing: &Listing, over: bool) -> usize {
+    let mut step = LineStep::new(listing, &machine.cpu, over);
+    for steps in 1..10_000 {
+        let returned = step.awaits_return() && is_return(&machine.cpu);
+        machine.step().expect("no trap");
+        if step.reached(listing, &machine.cpu, returned) {
+            return steps;
+        }
+    }
+    panic!("line step from {:?} not done", step);
+}
+
+#[test]
+fn stepping_by_line() {
+    let listing = program();
+    let storage = std::env::temp_dir().join(format!("retroshield-test-line-{}", std::process::id()));
+    let mut m = Machine::new(storage);
+    #[rustfmt::skip]
+    m.cpu.mem.write(0x8000, &[
+        0x31, 0x00, 0xF0, 0xCD, 0x10, 0x80, 0xCD, 0x40, 0x00,
+        0x06, 0x02, 0x10, 0xFE, 0x18, 0xFE,
+    ]);
+    m.cpu.mem.write(0x8010, &[0x3E, 0x2A, 0xC9]);
+    // A ROM routine the listing does not cover: nop / ret
+    m.cpu.mem.write(0x0040, &[0x00, 0xC9]);
+    m.cpu.reg.set_pc(0x8000);
+    let line = |m: &Machine| at(&listing, listing.locate(m.cpu.reg.pc() as u16).unwrap());
+
+    // Into the called routine, and back out of it
+    assert_eq!(step_line(&mut m, &listing, false), 1);
+    assert_eq!(step_line(&mut m, &listing, false), 1);
+    assert_eq!(line(&m), "util.asm:3");
+    assert_eq!(step_line(&mut m, &listing, false), 1);
+    assert_eq!(step_line(&mut m, &listing, false), 1);
+    assert_eq!(line(&m), "main.asm:5");
+
+    // Code outside the listing is run through
+    assert_eq!(step_line(&mut m, &listing, false), 3);
+    assert_eq!(line(&m), "main.asm:6");
+
+    // Over a call into the listing
+    m.cpu.reg.set_pc(0x8003);
+    assert_eq!(step_line(&mut m, &listing, true), 3);
+    assert_eq!((line(&m).as_str(), m.cpu.reg.sp()), ("main.asm:5", 0xF000));
+
+    // Each pass of a loop is a step; so is a jump to itself
+    m.cpu.reg.set_pc(0x8009);
+    assert_eq!(step_line(&mut m, &listing, false), 1);
+    assert_eq!(step_line(&mut m, &listing, false), 1);
+    assert_eq!(line(&m), "main.asm:7");
+    assert_eq!(step_line(&mut m, &listing, false), 1);
+    assert_eq!(line(&m), "main.asm:8");
+    assert_eq!(step_line(&mut m, &listing, true), 1);
+    assert_eq!(line(&m), "main.asm:8");
+}
+
+#[test]
+fn commands() {
+    let cpu = CPU::new_64k();
+    let parse = |line: &str| Command::parse(line, &cpu, &Symbols::new());
+    assert_eq!(parse("line"), Ok(Some(Command::Line)));
+    assert_eq!(parse("next"), Ok(Some(Command::Next)));
+    assert_eq!(parse("next 2"), Err("next takes no arguments".to_string()));
+}
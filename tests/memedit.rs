@@ -0,0 +1,72 @@
+//! Memory panel editor: cursor, scrolling and typing
+
+use retro_z80_emulator::memedit::{scroll, MemoryEditor};
+use rz80::CPU;
+
+#[test]
+fn scrolling() {
+    // Already in view: the view stays put
+    assert_eq!(scroll(0x8000, 0x8000, 4), 0x8000);
+    assert_eq!(scroll(0x8000, 0x803F, 4), 0x8000);
+    // Past the bottom or above the top: by as few whole rows as it takes
+    assert_eq!(scroll(0x8000, 0x8040, 4), 0x8010);
+    assert_eq!(scroll(0x8000, 0x8145, 4), 0x8110);
+    assert_eq!(scroll(0x8000, 0x7FFF, 4), 0x7FF0);
+    assert_eq!(scroll(0x8000, 0x7EF1, 4), 0x7EF0);
+    // A view that does not start on a row keeps its alignment
+    assert_eq!(scroll(0x8008, 0x8000, 4), 0x7FF8);
+    // The ends of memory, and a panel too small to have rows
+    assert_eq!(scroll(0x0005, 0x0000, 4), 0x0000);
+    assert_eq!(scroll(0xFFC0, 0xFFFF, 4), 0xFFC0);
+    assert_eq!(scroll(0xFFF0, 0xFFFF, 0), 0xFFF0);
+    assert_eq!(scroll(0x8000, 0x8010, 0), 0x8010);
+}
+
+#[test]
+fn typing_hex_and_ascii() {
+    let mut cpu = CPU::new_64k();
+    let mut editor = MemoryEditor::new(0x8000);
+    cpu.mem.write(0x8000, &[0x12, 0x34]);
+
+    // High nibble first; the cursor moves on after the low one
+    assert!(editor.type_char(&mut cpu, 'a'));
+    assert_eq!((cpu.mem.r8(0x8000), editor.cursor, editor.low_nibble), (0xA2, 0x8000, true));
+    assert!(editor.type_char(&mut cpu, 'B'));
+    assert_eq!((cpu.mem.r8(0x8000), editor.cursor, editor.low_nibble), (0xAB, 0x8001, false));
+    assert!(!editor.type_char(&mut cpu, 'x'));
+    assert_eq!(cpu.mem.r8(0x8001), 0x34);
+
+    // Moving drops a half-typed byte
+    editor.type_char(&mut cpu, '9');
+    editor.move_to(0x8002);
+    assert!(!editor.low_nibble);
+    assert_eq!(cpu.mem.r8(0x8001), 0x94);
+
+    editor.toggle_ascii();
+    assert!(editor.type_char(&mut cpu, 'g'));
+    assert!(!editor.type_char(&mut cpu, '\t'));
+    assert!(!editor.type_char(&mut cpu, 'é'));
+    assert_eq!((cpu.mem.r8(0x8002), editor.cursor), (b'g' as i32, 0x8003));
+
+    // The cursor stops at the end of memory
+    editor.move_to(0xFFFF);
+    editor.type_char(&mut cpu, 'z');
+    assert_eq!((cpu.mem.r8(0xFFFF), editor.cursor), (b'z' as i32, 0xFFFF));
+}
+
+#[test]
+fn following_pointers() {
+    let mut cpu = CPU::new_64k();
+    cpu.mem.write(0x8000, &[0x10, 0x90]);
+    cpu.mem.write(0x9010, &[0x00, 0xA0]);
+    let mut editor = MemoryEditor::new(0x8000);
+    assert_eq!(editor.back(), None);
+
+    assert_eq!(editor.follow(&cpu), 0x9010);
+    assert_eq!(editor.follow(&cpu), 0xA000);
+    assert_eq!(editor.cursor, 0xA000);
+    assert_eq!(editor.back(), Some(0x9010));
+    assert_eq!(editor.back(), Some(0x8000));
+    assert_eq!(editor.cursor, 0x8000);
+    assert_eq!(editor.back(), None);
+}
@@ -0,0 +1,196 @@
+//! Machine profiles: the TOML subset, validation and finding the profile
+
+use std::path::{Path, PathBuf};
+
+use retro_z80_emulator::profile::{self, InterruptMode, MemRange, Profile, SerialChip, SerialPorts};
+use retro_z80_emulator::serial::{Backspace, LineEnding, SerialTiming};
+
+fn error(text: &str) -> String {
+    Profile::parse(text).unwrap_err()
+}
+
+#[test]
+fn full_profile() {
+    let p = Profile::parse(
+        r#"
+# A full profile
+name = "Test # machine"     # a comment after a string with a hash
+
+[rom]
+file = "test.bin"
+load = 0x4000
+size = 0x2000
+
+[ram]
+ranges = ["0x0000-0x3FFF", "0xC000-0xFFFF",]
+
+[serial]
+chip = "8251"
+baud = 9600
+
+[sd]
+enabled = false
+
+[dump]
+base_port = 0x90
+
+[cpu]
+reset_pc = 0x4000
+clock_hz = 4_000_000
+
+[interrupts]
+mode = "serial"
+usart_vector = 0x20
+
+[input]
+upcase = true
+line_ending = "crlf"
+strip_bit7 = true
+backspace = "del"
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(p.name, "Test # machine");
+    assert_eq!(p.rom_file, Some(PathBuf::from("test.bin")));
+    assert_eq!((p.rom_load, p.rom_size), (0x4000, 0x2000));
+    assert_eq!(p.ram, [MemRange { start: 0, end: 0x3FFF }, MemRange { start: 0xC000, end: 0xFFFF }]);
+    // The 8251 moves to its own ports unless they are given
+    assert_eq!(p.serial, SerialChip::Intel8251);
+    assert_eq!(p.serial_ports, SerialPorts { control: 0x01, data: 0x00 });
+    assert_eq!(p.usart_ports(), Some(p.serial_ports));
+    assert_eq!(p.acia_ports(), None);
+    assert_eq!(p.serial_timing(), Some(SerialTiming { baud: 9600, clock_hz: 4_000_000 }));
+    assert_eq!((p.sd_port, p.dump_port), (None, Some(0x90)));
+    assert_eq!((p.reset_pc, p.clock_hz), (0x4000, Some(4_000_000)));
+    assert_eq!(p.interrupts, InterruptMode::Serial);
+    assert_eq!((p.acia_vector, p.usart_vector), (0xFF, 0x20));
+    assert!(p.input.upcase && p.input.strip_bit7);
+    assert_eq!((p.input.line_ending, p.input.backspace), (LineEnding::CrLf, Backspace::Del));
+    assert!(p.is_rom(0x5FFF) && !p.is_rom(0x6000));
+}
+
+#[test]
+fn defaults() {
+    assert_eq!(Profile::parse("").unwrap(), Profile::default());
+
+    // RAM fills what the ROM leaves, on both sides of it
+    let p = Profile::parse("[rom]\nload = 0x8000\nsize = 0x1000").unwrap();
+    assert_eq!(p.ram, [MemRange { start: 0, end: 0x7FFF }, MemRange { start: 0x9000, end: 0xFFFF }]);
+    let p = Profile::parse("[rom]\nsize = 0").unwrap();
+    assert_eq!((p.rom_range(), p.ram.len()), (None, 1));
+
+    // Integers in any base, with separators
+    let p = Profile::parse("[cpu]\nreset_pc = 0b1_0000\nclock_hz = 0o17").unwrap();
+    assert_eq!((p.reset_pc, p.clock_hz), (16, Some(15)));
+}
+
+#[test]
+fn syntax_errors() {
+    assert_eq!(error("name"), "line 1: expected key = value");
+    assert_eq!(error("\n[rom"), "line 2: unterminated section header");
+    assert_eq!(error("[a b]"), "line 1: invalid section name \"a b\"");
+    assert_eq!(error("a.b = 1"), "line 1: invalid key \"a.b\"");
+    assert_eq!(error("name = \"a\"\nname = \"b\""), "line 2: duplicate key \"name\"");
+    assert_eq!(error("name = \"abc"), "line 1: invalid string \"abc");
+    assert_eq!(error("[ram]\nranges = [\"0-0xFFFF\""), "line 2: unterminated array");
+    assert_eq!(error("[cpu]\nreset_pc = 0xG"), "line 2: invalid value \"0xG\"");
+    assert_eq!(error("[cpu]\nreset_pc = -0x10"), "line 2: invalid value \"-0x10\"");
+}
+
+#[test]
+fn value_errors() {
+    assert_eq!(error("name = 1"), "line 1: expected a string");
+    assert_eq!(error("[rom]\nload = \"0\""), "line 2: expected an integer");
+    assert_eq!(error("[rom]\nload = 0x10000"), "line 2: 65536 is out of range");
+    assert_eq!(error("[sd]\nenabled = 1"), "line 2: expected true or false");
+    assert_eq!(error("[ram]\nranges = \"0-0xFFFF\""), "line 2: expected an array");
+    assert_eq!(error("[ram]\nranges = [\"0x2000\"]"), "line 2: expected \"start-end\", got \"0x2000\"");
+    assert_eq!(error("[ram]\nranges = [\"0-0x10000\"]"), "line 2: invalid address range \"0-0x10000\"");
+    assert_eq!(error("[serial]\nchip = \"16550\""), "line 2: unknown serial chip \"16550\"");
+    assert_eq!(error("[interrupts]\nmode = \"nmi\""), "line 2: unknown interrupt mode \"nmi\"");
+    assert!(error("[input]\nline_ending = \"lfcr\"").starts_with("line 2: "));
+    // The first unknown key is reported
+    assert_eq!(error("[cpu]\nspeed = 4\n[rom]\nbase = 0"), "line 2: unknown key \"cpu.speed\"");
+}
+
+#[test]
+fn validation() {
+    assert_eq!(error("[serial]\nbaud = 9600"), "serial.baud needs cpu.clock_hz to time characters");
+    assert_eq!(
+        error("[rom]\nload = 0x100"),
+        "ROM at $0100 size $2000 is not aligned to 1024 byte pages"
+    );
+    assert_eq!(error("[rom]\nload = 0xF000"), "ROM at $F000 size $2000 extends past $FFFF");
+    assert_eq!(
+        error("[ram]\nranges = [\"0x8000-0x7FFF\"]"),
+        "RAM range $8000-$7FFF is empty"
+    );
+    assert_eq!(
+        error("[ram]\nranges = [\"0x2000-0x21FF\"]"),
+        "RAM range $2000-$21FF is not aligned to 1024 byte pages"
+    );
+    assert_eq!(
+        error("[ram]\nranges = [\"0x1000-0x2FFF\"]"),
+        "RAM range $1000-$2FFF overlaps ROM $0000-$1FFF"
+    );
+    assert_eq!(
+        error("[ram]\nranges = [\"0x2000-0x7FFF\", \"0x4000-0x43FF\"]"),
+        "RAM ranges $2000-$7FFF and $4000-$43FF overlap"
+    );
+}
+
+/// A directory holding a ROM and, if `toml` is given, its profile
+fn rom_dir(name: &str, toml: Option<&str>) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("retroshield-test-profile-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("rom.bin"), [0u8; 16]).unwrap();
+    if let Some(toml) = toml {
+        std::fs::write(dir.join("rom.toml"), toml).unwrap();
+    }
+    dir
+}
+
+fn path(p: &Path) -> &str {
+    p.to_str().unwrap()
+}
+
+#[test]
+fn finding_the_profile() {
+    let dir = rom_dir("sibling", Some("name = \"Sibling\"\n[rom]\nfile = \"rom.bin\"\n"));
+    let rom = dir.join("rom.bin");
+    assert_eq!(profile::sibling_profile(&rom), Some(dir.join("rom.toml")));
+
+    // The profile next to the ROM; a relative rom.file is beside the profile
+    let (p, found) = profile::resolve(None, Some(path(&rom))).unwrap();
+    assert_eq!((p.name.as_str(), found), ("Sibling", rom.clone()));
+    assert_eq!(p.rom_file, Some(dir.join("rom.bin")));
+    let (_, found) = profile::resolve(Some(path(&dir.join("rom.toml"))), None).unwrap();
+    assert_eq!(found, rom);
+
+    // A ROM given alongside the profile wins over its rom.file
+    let (_, found) = profile::resolve(Some(path(&dir.join("rom.toml"))), Some("other.bin")).unwrap();
+    assert_eq!(found, PathBuf::from("other.bin"));
+
+    // Without a profile beside it the ROM runs on the default machine
+    let bare = rom_dir("bare", None);
+    assert_eq!(profile::sibling_profile(&bare.join("rom.bin")), None);
+    let (p, _) = profile::resolve(None, Some(path(&bare.join("rom.bin")))).unwrap();
+    assert_eq!(p, Profile::default());
+
+    assert_eq!(profile::resolve(None, None).unwrap_err(), "ROM file or --machine profile required");
+    std::fs::write(dir.join("norom.toml"), "name = \"Bare\"").unwrap();
+    assert_eq!(
+        profile::resolve(Some(path(&dir.join("norom.toml"))), None).unwrap_err(),
+        "profile \"Bare\" does not name a ROM file"
+    );
+    std::fs::write(dir.join("bad.toml"), "[cpu]\nspeed = 1").unwrap();
+    let bad = dir.join("bad.toml");
+    assert_eq!(
+        profile::resolve(Some(path(&bad)), None).unwrap_err(),
+        format!("{}: line 2: unknown key \"cpu.speed\"", bad.display())
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+    std::fs::remove_dir_all(bare).unwrap();
+}
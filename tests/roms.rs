@@ -0,0 +1,91 @@
+//! Boot each bundled ROM under the shared machine model and drive a short
+//! serial session from its script in `tests/scripts/`
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use retro_z80_emulator::profile;
+use retro_z80_emulator::retroshield::Machine;
+use retro_z80_emulator::script::Script;
+
+fn root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Boot `roms/<name>.toml` and run `tests/scripts/<name>.script` against it
+fn run_rom_script(name: &str) {
+    let profile_path = root().join("roms").join(format!("{}.toml", name));
+    let (profile, rom) = profile::resolve(profile_path.to_str(), None)
+        .unwrap_or_else(|e| panic!("{}: {}", name, e));
+
+    let storage = std::env::temp_dir().join(format!("retroshield-test-{}-{}", name, std::process::id()));
+    let mut machine = Machine::with_profile(profile, storage.clone()).unwrap();
+    machine.load_rom_file(&rom).unwrap_or_else(|e| panic!("{}: {}", rom.display(), e));
+
+    let script_path = root().join("tests/scripts").join(format!("{}.script", name));
+    let text = fs::read_to_string(&script_path).unwrap_or_else(|e| panic!("{}: {}", script_path.display(), e));
+    let script = Script::parse(&text).unwrap_or_else(|e| panic!("{}: {}", script_path.display(), e));
+
+    let mut transcript = Vec::new();
+    let result = script.run(&mut machine, 0, &mut transcript);
+    let _ = fs::remove_dir_all(&storage);
+    if let Err(e) = result {
+        panic!(
+            "{}: {}\n--- serial output ---\n{}",
+            script_path.display(),
+            e,
+            String::from_utf8_lossy(&transcript)
+        );
+    }
+}
+
+#[test]
+fn mint() {
+    run_rom_script("mint.z80");
+}
+
+#[test]
+fn firth() {
+    run_rom_script("firth.z80");
+}
+
+#[test]
+fn monty() {
+    run_rom_script("monty.z80");
+}
+
+#[test]
+fn pascal() {
+    run_rom_script("pascal");
+}
+
+#[test]
+fn grant_searle_basic() {
+    run_rom_script("basic_gs47b");
+}
+
+#[test]
+fn grants_basic_8251() {
+    run_rom_script("grantz80_basic_new");
+}
+
+#[test]
+fn efex() {
+    run_rom_script("efex");
+}
+
+#[test]
+fn every_rom_has_a_script() {
+    let mut missing: Vec<PathBuf> = Vec::new();
+    for entry in fs::read_dir(root().join("roms")).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            let script = root().join("tests/scripts").join(format!("{}.script", name));
+            if !script.exists() {
+                missing.push(script);
+            }
+        }
+    }
+    assert!(missing.is_empty(), "ROMs without a smoke script: {:?}", missing);
+}
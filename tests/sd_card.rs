@@ -0,0 +1,106 @@
+//! Drive the SD card through its I/O ports from a small Z80 program: write
+//! a file, read it back byte by byte, then again with a DMA block read
+
+use std::fs;
+use std::path::Path;
+
+use retro_z80_emulator::profile;
+use retro_z80_emulator::retroshield::Machine;
+
+const PROGRAM: u16 = 0x8000;
+const NAME: u16 = 0x8080;
+const DATA: u16 = 0x8088;
+const READ_BUFFER: u16 = 0x9000;
+const DMA_BUFFER: u16 = 0xA000;
+
+#[rustfmt::skip]
+const CODE: &[u8] = &[
+    0xF3,             // 8000  di
+    0x21, 0x80, 0x80, // 8001  ld hl,NAME
+    0x7E,             // 8004  ld a,(hl)      send the file name
+    0xD3, 0x13,       // 8005  out (SD_FNAME),a
+    0x23,             // 8007  inc hl
+    0xB7,             // 8008  or a
+    0x20, 0xF9,       // 8009  jr nz,8004
+    0x3E, 0x02,       // 800B  ld a,CMD_CREATE
+    0xD3, 0x10,       // 800D  out (SD_CMD),a
+    0x21, 0x88, 0x80, // 800F  ld hl,DATA
+    0x7E,             // 8012  ld a,(hl)      write the text
+    0xB7,             // 8013  or a
+    0x28, 0x05,       // 8014  jr z,801B
+    0xD3, 0x12,       // 8016  out (SD_DATA),a
+    0x23,             // 8018  inc hl
+    0x18, 0xF7,       // 8019  jr 8012
+    0x3E, 0x05,       // 801B  ld a,CMD_CLOSE
+    0xD3, 0x10,       // 801D  out (SD_CMD),a
+    0x21, 0x80, 0x80, // 801F  ld hl,NAME
+    0x7E,             // 8022  ld a,(hl)
+    0xD3, 0x13,       // 8023  out (SD_FNAME),a
+    0x23,             // 8025  inc hl
+    0xB7,             // 8026  or a
+    0x20, 0xF9,       // 8027  jr nz,8022
+    0x3E, 0x01,       // 8029  ld a,CMD_OPEN_READ
+    0xD3, 0x10,       // 802B  out (SD_CMD),a
+    0x21, 0x00, 0x90, // 802D  ld hl,READ_BUFFER
+    0xDB, 0x11,       // 8030  in a,(SD_STATUS) read while data is available
+    0xE6, 0x80,       // 8032  and STATUS_DATA
+    0x28, 0x06,       // 8034  jr z,803C
+    0xDB, 0x12,       // 8036  in a,(SD_DATA)
+    0x77,             // 8038  ld (hl),a
+    0x23,             // 8039  inc hl
+    0x18, 0xF4,       // 803A  jr 8030
+    0x21, 0x80, 0x80, // 803C  ld hl,NAME
+    0x7E,             // 803F  ld a,(hl)
+    0xD3, 0x13,       // 8040  out (SD_FNAME),a
+    0x23,             // 8042  inc hl
+    0xB7,             // 8043  or a
+    0x20, 0xF9,       // 8044  jr nz,803F
+    0x3E, 0x01,       // 8046  ld a,CMD_OPEN_READ
+    0xD3, 0x10,       // 8048  out (SD_CMD),a
+    0x3E, 0x00,       // 804A  ld a,<DMA_BUFFER
+    0xD3, 0x16,       // 804C  out (SD_DMA_LO),a
+    0x3E, 0xA0,       // 804E  ld a,>DMA_BUFFER
+    0xD3, 0x17,       // 8050  out (SD_DMA_HI),a
+    0xAF,             // 8052  xor a
+    0xD3, 0x18,       // 8053  out (SD_BLOCK_CMD),a  read 128 bytes
+    0xDB, 0x18,       // 8055  in a,(SD_BLOCK_CMD)   status
+    0x76,             // 8057  halt
+];
+
+#[test]
+fn sd_card_write_and_read_back() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let (profile, _) = profile::resolve(root.join("roms/mint.z80.toml").to_str(), None).unwrap();
+    let storage = std::env::temp_dir().join(format!("retroshield-test-sd-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&storage);
+
+    let mut machine = Machine::with_profile(profile, storage.clone()).unwrap();
+    let mem = &mut machine.cpu.mem;
+    mem.write(PROGRAM as i32, CODE);
+    mem.write(NAME as i32, b"T.TXT\0");
+    mem.write(DATA as i32, b"HELLO\0");
+    mem.write(READ_BUFFER as i32, &[0xFF; 0x10]);
+    mem.write(DMA_BUFFER as i32, &[0xFF; 0x100]);
+    machine.cpu.reg.set_pc(PROGRAM as i32);
+
+    while !machine.is_stopped() && machine.total_cycles < 100_000 {
+        machine.step().unwrap();
+    }
+    let written = fs::read(storage.join("T.TXT"));
+    let _ = fs::remove_dir_all(&storage);
+
+    assert!(machine.is_stopped(), "program did not finish, PC={:04X}", machine.cpu.reg.pc());
+    assert_eq!(written.unwrap(), b"HELLO");
+
+    let read = |addr: u16, len: usize| -> Vec<u8> {
+        (0..len).map(|i| machine.cpu.mem.r8(addr as i32 + i as i32) as u8).collect()
+    };
+    // Byte reads end with a 0 at end of file, when the data bit drops
+    assert_eq!(read(READ_BUFFER, 7), b"HELLO\0\xFF");
+
+    // A block read pads to 128 bytes and reports success
+    assert_eq!(machine.cpu.reg.a(), 0);
+    assert_eq!(read(DMA_BUFFER, 5), b"HELLO");
+    assert!(read(DMA_BUFFER + 5, 123).iter().all(|&b| b == 0));
+    assert_eq!(read(DMA_BUFFER + 128, 1), [0xFF]);
+}
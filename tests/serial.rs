@@ -0,0 +1,315 @@
+//! MC6850 ACIA and Intel 8251 USART registers and baud-rate timing
+
+use std::rc::Rc;
+
+use retro_z80_emulator::serial::{Console, Intel8251, Mc6850, SerialTiming};
+
+/// MC6850 status bits
+const RDRF: u8 = 0x01;
+const TDRE: u8 = 0x02;
+const OVRN: u8 = 0x20;
+const IRQ: u8 = 0x80;
+
+/// Intel 8251 status bits
+const TXRDY: u8 = 0x01;
+const RXRDY: u8 = 0x02;
+const TXE: u8 = 0x04;
+const OE: u8 = 0x10;
+const SYNDET: u8 = 0x40;
+const DSR: u8 = 0x80;
+
+/// Async, 8 data bits, no parity, 1 stop bit, x16 clock
+const ASYNC_8N1: u8 = 0x4E;
+/// Command bits
+const TXEN: u8 = 0x01;
+const RXE: u8 = 0x04;
+const ERROR_RESET: u8 = 0x10;
+const INTERNAL_RESET: u8 = 0x40;
+const ENTER_HUNT: u8 = 0x80;
+
+/// 9600 baud at 4.8 MHz: 500 cycles a bit, 5000 for a 10-bit frame
+const TIMING: SerialTiming = SerialTiming { baud: 9600, clock_hz: 4_800_000 };
+
+fn acia() -> (Rc<Console>, Mc6850) {
+    let console = Rc::new(Console::new());
+    let acia = Mc6850::new(console.clone());
+    (console, acia)
+}
+
+#[test]
+fn acia_master_reset() {
+    let (console, acia) = acia();
+    // Usable from power-on, as on the RetroShield
+    assert_eq!(acia.read_status(), TDRE);
+    acia.write_data(b'A');
+    assert_eq!(console.take_output(), b"A");
+
+    // Held in reset, with the status clear, until the next control write
+    acia.write_control(0x03);
+    console.push_input(b'x');
+    assert_eq!(acia.read_status(), 0);
+    acia.write_data(b'B');
+    assert!(console.take_output().is_empty());
+    assert!(!acia.irq_pending());
+
+    // Divide by 16, 8N1
+    acia.write_control(0x15);
+    assert_eq!(acia.read_status(), RDRF | TDRE);
+    assert_eq!(acia.read_data(), b'x');
+    assert_eq!(acia.read_status(), TDRE);
+}
+
+#[test]
+fn acia_receive_interrupt() {
+    let (console, acia) = acia();
+    acia.write_control(0x03);
+    acia.write_control(0x95);
+    assert!(!acia.irq_pending());
+    assert_eq!(acia.read_status(), TDRE);
+
+    console.push_input(b'x');
+    assert!(acia.irq_pending());
+    assert_eq!(acia.read_status(), IRQ | RDRF | TDRE);
+    assert_eq!(acia.read_data(), b'x');
+    assert_eq!(acia.read_status(), TDRE);
+    assert!(!acia.irq_pending());
+
+    // Without CR7 a received byte raises no interrupt
+    acia.write_control(0x15);
+    console.push_input(b'y');
+    assert_eq!(acia.read_status(), RDRF | TDRE);
+    assert!(!acia.irq_pending());
+}
+
+#[test]
+fn acia_transmitter_control() {
+    let (console, acia) = acia();
+
+    // CR6:5 = 00: RTS low, transmit interrupt off
+    acia.write_control(0x15);
+    assert!(!acia.irq_pending());
+
+    // 01: RTS low, transmit interrupt on while TDRE
+    acia.write_control(0x35);
+    assert!(acia.irq_pending());
+    assert_eq!(acia.read_status(), IRQ | TDRE);
+
+    // 10: RTS high, which holds input back
+    acia.write_control(0x55);
+    console.push_input(b'x');
+    assert_eq!(acia.read_status(), TDRE);
+    assert!(!acia.irq_pending());
+
+    // 11: RTS low, transmitting a break, transmit interrupt off
+    acia.write_control(0x75);
+    assert_eq!(acia.read_status(), RDRF | TDRE);
+    assert_eq!(acia.read_data(), b'x');
+    assert!(!acia.irq_pending());
+}
+
+#[test]
+fn acia_frame_timing() {
+    let (console, acia) = acia();
+    acia.set_timing(Some(TIMING));
+    // 8N1
+    acia.write_control(0x15);
+
+    // A character takes a whole frame to go out
+    acia.write_data(b'A');
+    acia.tick(4999);
+    assert!(console.take_output().is_empty());
+    acia.tick(1);
+    assert_eq!(console.take_output(), b"A");
+
+    // A second character waits in the data register for the first
+    acia.write_data(b'B');
+    acia.write_data(b'C');
+    assert_eq!(acia.read_status(), 0);
+    acia.tick(5000);
+    assert_eq!(console.take_output(), b"B");
+    assert_eq!(acia.read_status(), TDRE);
+    acia.tick(5000);
+    assert_eq!(console.take_output(), b"C");
+
+    // Input arrives back to back, a frame apart
+    console.push_input(b'x');
+    console.push_input(b'y');
+    acia.tick(4999);
+    assert_eq!(acia.read_status(), TDRE);
+    acia.tick(1);
+    assert_eq!(acia.read_status(), RDRF | TDRE);
+    assert_eq!(acia.read_data(), b'x');
+    acia.tick(5000);
+    assert_eq!(acia.read_data(), b'y');
+
+    // 8N2 frames are a bit longer
+    acia.write_control(0x11);
+    acia.write_data(b'D');
+    acia.tick(5499);
+    assert!(console.take_output().is_empty());
+    acia.tick(1);
+    assert_eq!(console.take_output(), b"D");
+}
+
+#[test]
+fn acia_overrun() {
+    let (console, acia) = acia();
+    acia.set_timing(Some(TIMING));
+    acia.write_control(0x95);
+    for &c in b"abc" {
+        console.push_input(c);
+    }
+
+    // Not read before the second frame ends: that character is lost
+    acia.tick(10_000);
+    assert_eq!(acia.read_status(), IRQ | OVRN | RDRF | TDRE);
+    assert!(acia.irq_pending());
+    assert_eq!(acia.read_data(), b'a');
+    assert_eq!(acia.read_status(), TDRE);
+    acia.tick(5000);
+    assert_eq!(acia.read_data(), b'c');
+    assert_eq!(acia.read_status(), TDRE);
+}
+
+fn usart() -> (Rc<Console>, Intel8251) {
+    let console = Rc::new(Console::new());
+    let usart = Intel8251::new(console.clone());
+    (console, usart)
+}
+
+#[test]
+fn usart_mode_then_command() {
+    let (console, usart) = usart();
+    assert_eq!(usart.read_status(), DSR | TXE | TXRDY);
+
+    // Nothing moves until a mode and a command have been written
+    console.push_input(b'x');
+    usart.write_data(b'A');
+    assert_eq!(usart.read_status(), DSR);
+    assert!(!usart.rx_ready());
+    usart.write_control(ASYNC_8N1);
+    assert_eq!(usart.read_status(), DSR);
+    usart.write_control(TXEN | RXE);
+    assert_eq!(console.take_output(), b"A");
+    assert_eq!(usart.read_status(), DSR | RXRDY | TXE | TXRDY);
+    assert!(usart.rx_ready());
+    assert_eq!(usart.read_data(), b'x');
+
+    // Internal reset: the next write is a mode instruction again
+    usart.write_control(INTERNAL_RESET);
+    console.push_input(b'y');
+    usart.write_control(ASYNC_8N1);
+    assert!(!usart.rx_ready());
+    usart.write_control(RXE);
+    assert!(usart.rx_ready());
+    assert_eq!(usart.read_data(), b'y');
+}
+
+#[test]
+fn usart_enables() {
+    let (console, usart) = usart();
+    usart.write_control(ASYNC_8N1);
+
+    // Input waits for RxE
+    usart.write_control(TXEN);
+    console.push_input(b'x');
+    assert_eq!(usart.read_status(), DSR | TXE | TXRDY);
+    assert!(!usart.rx_ready());
+
+    // Output waits in the buffer for TxEN
+    usart.write_control(RXE);
+    assert!(usart.rx_ready());
+    usart.write_data(b'B');
+    assert!(console.take_output().is_empty());
+    assert_eq!(usart.read_status() & (TXRDY | TXE), 0);
+    usart.write_control(RXE | TXEN);
+    assert_eq!(console.take_output(), b"B");
+    assert_eq!(usart.read_status() & (TXRDY | TXE), TXRDY | TXE);
+}
+
+#[test]
+fn usart_sync_mode() {
+    let (console, usart) = usart();
+    // Sync, 8 data bits, two sync characters
+    usart.write_control(0x0C);
+    usart.write_control(0x16);
+    usart.write_control(0x16);
+    usart.write_control(ENTER_HUNT | RXE);
+
+    // Hunting discards input until both sync characters arrive
+    for c in [0x41, 0x16, 0x16, b'D'] {
+        console.push_input(c);
+    }
+    assert_eq!(usart.read_status(), DSR | SYNDET | RXRDY | TXE | TXRDY);
+    // SYNDET clears when read
+    assert_eq!(usart.read_status(), DSR | RXRDY | TXE | TXRDY);
+    assert_eq!(usart.read_data(), b'D');
+
+    // With a single sync character the command follows it
+    usart.write_control(INTERNAL_RESET);
+    usart.write_control(0x8C);
+    usart.write_control(0x16);
+    usart.write_control(TXEN);
+    usart.write_data(b'E');
+    assert_eq!(console.take_output(), b"E");
+}
+
+#[test]
+fn usart_frame_timing() {
+    let (console, usart) = usart();
+    usart.set_timing(Some(TIMING));
+    usart.write_control(ASYNC_8N1);
+    usart.write_control(TXEN | RXE);
+
+    // TxRDY as soon as the shift register takes the character; TxE once
+    // it is sent
+    usart.write_data(b'A');
+    assert_eq!(usart.read_status(), DSR | TXRDY);
+    usart.tick(4999);
+    assert!(console.take_output().is_empty());
+    usart.tick(1);
+    assert_eq!(console.take_output(), b"A");
+    assert_eq!(usart.read_status(), DSR | TXE | TXRDY);
+
+    console.push_input(b'x');
+    usart.tick(4999);
+    assert!(!usart.rx_ready());
+    usart.tick(1);
+    assert!(usart.rx_ready());
+    assert_eq!(usart.read_data(), b'x');
+
+    // 1.5 and 2 stop bits
+    for (mode, cycles) in [(0x8E, 5250), (0xCE, 5500)] {
+        usart.write_control(INTERNAL_RESET);
+        usart.write_control(mode);
+        usart.write_control(TXEN);
+        usart.write_data(b'B');
+        usart.tick(cycles - 1);
+        assert!(console.take_output().is_empty());
+        usart.tick(1);
+        assert_eq!(console.take_output(), b"B");
+    }
+}
+
+#[test]
+fn usart_overrun() {
+    let (console, usart) = usart();
+    usart.set_timing(Some(TIMING));
+    usart.write_control(ASYNC_8N1);
+    usart.write_control(RXE);
+    for &c in b"abc" {
+        console.push_input(c);
+    }
+
+    // A character not read in time is overwritten by the next
+    usart.tick(10_000);
+    assert_eq!(usart.read_status(), DSR | OE | RXRDY | TXE | TXRDY);
+    assert_eq!(usart.read_data(), b'b');
+
+    // The error stays set, through reads, until an error reset
+    usart.tick(5000);
+    assert_eq!(usart.read_data(), b'c');
+    assert_eq!(usart.read_status(), DSR | OE | TXE | TXRDY);
+    usart.write_control(RXE | ERROR_RESET);
+    assert_eq!(usart.read_status(), DSR | TXE | TXRDY);
+}
@@ -0,0 +1,140 @@
+//! Symbol files from several assemblers, and symbols in expressions and
+//! listings
+
+use retro_z80_emulator::breakpoint::parse_spec_with;
+use retro_z80_emulator::console::{self, complete, Command};
+use retro_z80_emulator::expr::Expr;
+use retro_z80_emulator::symbols::{self, Kind, Symbols};
+use rz80::CPU;
+
+fn read(text: &str) -> Symbols {
+    let mut symbols = Symbols::new();
+    symbols.read(text);
+    symbols
+}
+
+#[test]
+fn z88dk_map() {
+    let symbols = read(
+        "_main                           = $0123 ; addr, public, , main_c, code_compiler, main.c:5\n\
+         _buffer                         = $8000 ; addr, public, , main_c, bss_compiler, main.c:2\n\
+         CR                              = $000D ; const, local, , , , defs.asm:1\n",
+    );
+    assert_eq!(symbols.len(), 3);
+    assert_eq!(symbols.value("_main"), Some(0x0123));
+    assert_eq!(symbols.value("cr"), Some(0x0D));
+    assert_eq!(symbols.label(0x8000), Some("_buffer"));
+    // Constants never name an address
+    assert_eq!(symbols.label(0x000D), None);
+}
+
+#[test]
+fn sjasmplus_and_pasmo() {
+    let symbols = read(
+        "main: EQU 0x00000100\n\
+         main.loop: EQU 0x00000105\n\
+         big: EQU 0x00012345\n\
+         print   EQU 0200H\n\
+         count   EQU 10\n",
+    );
+    assert_eq!(symbols.value("main"), Some(0x0100));
+    assert_eq!(symbols.value("MAIN.LOOP"), Some(0x0105));
+    assert_eq!(symbols.value("big"), None);
+    assert_eq!(symbols.value("print"), Some(0x0200));
+    assert_eq!(symbols.value("count"), Some(10));
+}
+
+#[test]
+fn listing_symbol_tables() {
+    // sjasmplus --lstlab
+    let symbols = read(
+        "    12  0105 18 FE        loop:   jr loop\n\
+         \n\
+         Value    Label\n\
+         ------ - -----------------------------------------------------------\n\
+         0x0100   start\n\
+         0x0105 X loop\n",
+    );
+    assert_eq!((symbols.value("start"), symbols.value("loop")), (Some(0x0100), Some(0x0105)));
+    assert_eq!(symbols.len(), 2);
+
+    // zmac
+    let symbols = read(
+        "  103  0100  3e 0d          start:  ld a,cr\n\
+         \n\
+         \x20   Symbol Table:\n\
+         \n\
+         cr             =   0d     lf             =   0a\n\
+         start             100     print            10a\n",
+    );
+    assert_eq!(symbols.len(), 4);
+    assert_eq!(symbols.value("print"), Some(0x010A));
+    assert_eq!(symbols.label(0x0100), Some("start"));
+    assert_eq!(symbols.label(0x000D), None);
+}
+
+#[test]
+fn lookups() {
+    let mut symbols = Symbols::new();
+    symbols.add("start", 0x0100, Kind::Label);
+    symbols.add("entry", 0x0100, Kind::Label);
+    symbols.add("start", 0x0200, Kind::Label);
+    symbols.add("table", 0x0400, Kind::Label);
+    assert_eq!(symbols.value("start"), Some(0x0100));
+    assert_eq!(symbols.label(0x0100), Some("start"));
+    assert_eq!(symbols.describe(0x0100).as_deref(), Some("start"));
+    assert_eq!(symbols.describe(0x011C).as_deref(), Some("start+$1C"));
+    assert_eq!(symbols.describe(0x0300), None);
+    assert_eq!(symbols.describe(0x00FF), None);
+    let labels: Vec<(u16, &str)> = symbols.labels().collect();
+    assert_eq!(labels, [(0x0100, "start"), (0x0400, "table")]);
+}
+
+#[test]
+fn files() {
+    let dir = std::env::temp_dir().join(format!("retroshield-test-symbols-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let rom = dir.join("prog.bin");
+    assert_eq!(symbols::sibling(&rom), None);
+    std::fs::write(dir.join("prog.map"), "main = $0100\n").unwrap();
+    std::fs::write(dir.join("prog.lst"), "no symbols here\n").unwrap();
+    assert_eq!(symbols::sibling(&rom), Some(dir.join("prog.map")));
+
+    let mut symbols = Symbols::new();
+    assert_eq!(symbols.load(&dir.join("prog.map")), Ok(1));
+    assert!(symbols.load(&dir.join("prog.lst")).unwrap_err().ends_with("no symbols found"));
+    assert!(symbols.load(&dir.join("missing.sym")).is_err());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn expressions() {
+    let symbols = read("main_loop = $0123\nbuffer = $8000\nff00 = $1234\nhl = $4000\n");
+    let cpu = CPU::new_64k();
+    let eval = |text: &str| Expr::parse_with(text, 16, &symbols).map(|e| e.eval(&cpu));
+    assert_eq!(eval("main_loop"), Ok(0x0123));
+    assert_eq!(eval("BUFFER+10"), Ok(0x8010));
+    // Registers win over symbols, and symbols over hex numbers
+    assert_eq!(eval("hl"), Ok(0));
+    assert_eq!(eval("ff00"), Ok(0x1234));
+    assert_eq!(eval("fe00"), Ok(0xFE00));
+    assert_eq!(eval("nowhere"), Err("unknown register or symbol \"nowhere\"".to_string()));
+
+    let (addr, condition, _) = parse_spec_with("main_loop if a==buffer", &cpu, 16, &symbols).unwrap();
+    assert_eq!((addr, condition.unwrap().to_string()), (0x0123, "a==buffer".to_string()));
+    assert_eq!(Command::parse("m buffer", &cpu, &symbols), Ok(Some(Command::Memory(Some(0x8000), console::DUMP_LEN))));
+}
+
+#[test]
+fn listings_and_completion() {
+    let symbols = read("start = $0100\nprint = $0105\nprompt = $2000\n");
+    let mut cpu = CPU::new_64k();
+    cpu.mem.write(0x0100, &[0xCD, 0x05, 0x01, 0x18, 0xFB, 0xC9]);
+    let (lines, _) = console::disassembly(&cpu, 0x0100, 3, &symbols);
+    assert_eq!(lines, ["start:", "0100: CD 05 01    CALL print", "0103: 18 FB       JR start", "print:", "0105: C9          RET"]);
+
+    assert_eq!(complete("b st", &symbols).line, "b start ");
+    assert_eq!(complete("m hl+pri", &symbols).line, "m hl+print ");
+    let c = complete("g pr", &symbols);
+    assert_eq!((c.line.as_str(), c.candidates), ("g pr", vec!["print".to_string(), "prompt".to_string()]));
+}
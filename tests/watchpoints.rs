@@ -0,0 +1,165 @@
+//! Memory and I/O watchpoints stopping small Z80 programs
+
+use retro_z80_emulator::retroshield::{Machine, Trap};
+use retro_z80_emulator::watchpoint::{parse_spec, Access, Space, Watch, WatchHit};
+
+const PROGRAM: u16 = 0x8000;
+
+fn machine(code: &[u8]) -> Machine {
+    let storage = std::env::temp_dir().join(format!("retroshield-test-watch-{}", std::process::id()));
+    let mut machine = Machine::new(storage);
+    machine.cpu.mem.write(PROGRAM as i32, code);
+    machine.cpu.reg.set_pc(PROGRAM as i32);
+    machine.cpu.reg.set_sp(0xF000);
+    machine
+}
+
+fn watch(space: Space, start: u16, end: u16, mode: &str) -> Watch {
+    Watch { space, start, end, read: mode.contains('r'), write: mode.contains('w') }
+}
+
+/// Step until a watchpoint stops the program, or it halts
+fn run(machine: &mut Machine) -> Option<WatchHit> {
+    for _ in 0..1000 {
+        if machine.is_halted() {
+            return None;
+        }
+        match machine.step() {
+            Ok(_) => {}
+            Err(Trap::Watch(hit)) => return Some(hit),
+            Err(trap) => panic!("{}", trap),
+        }
+    }
+    panic!("program did not finish");
+}
+
+#[test]
+fn memory_write() {
+    #[rustfmt::skip]
+    let mut m = machine(&[
+        0x3E, 0x2A,       // 8000  ld a,$2A
+        0x32, 0x00, 0x90, // 8002  ld ($9000),a
+        0x32, 0x01, 0x90, // 8005  ld ($9001),a
+        0x76,             // 8008  halt
+    ]);
+    m.cpu.mem.write(0x9001, &[0x11]);
+    m.watchpoints.add(watch(Space::Memory, 0x9001, 0x9001, "w"));
+
+    let hit = run(&mut m).expect("watchpoint");
+    assert_eq!(hit, WatchHit {
+        id: 1,
+        pc: 0x8005,
+        space: Space::Memory,
+        access: Access::Write,
+        addr: 0x9001,
+        old: Some(0x11),
+        value: 0x2A,
+    });
+    assert_eq!(hit.to_string(), "Watchpoint #1: write to 9001 at PC=8005, $11 -> $2A");
+    // Stopped after the instruction
+    assert_eq!(m.cpu.reg.pc(), 0x8008);
+    assert_eq!(run(&mut m), None);
+}
+
+#[test]
+fn memory_read_of_a_range() {
+    #[rustfmt::skip]
+    let mut m = machine(&[
+        0x2A, 0x10, 0x90, // 8000  ld hl,($9010)
+        0x2A, 0x1F, 0x90, // 8003  ld hl,($901F)   second byte in range
+        0x76,             // 8006  halt
+    ]);
+    m.cpu.mem.write(0x9020, &[0x5A]);
+    m.watchpoints.add(watch(Space::Memory, 0x9020, 0x902F, "r"));
+
+    let hit = run(&mut m).expect("watchpoint");
+    assert_eq!((hit.pc, hit.access, hit.addr, hit.old, hit.value), (0x8003, Access::Read, 0x9020, None, 0x5A));
+    assert_eq!(hit.to_string(), "Watchpoint #1: read of 9020 at PC=8003, $5A");
+}
+
+#[test]
+fn read_and_write_prediction() {
+    #[rustfmt::skip]
+    let mut m = machine(&[
+        0xDD, 0x21, 0x00, 0x90, // 8000  ld ix,$9000
+        0xDD, 0xCB, 0x05, 0x46, // 8004  bit 0,(ix+5)    read
+        0xDD, 0x34, 0x05,       // 8008  inc (ix+5)      read and write
+        0xE5,                   // 800B  push hl         write (SP-2)
+        0xE1,                   // 800C  pop hl          read (SP)
+        0x21, 0x05, 0x90,       // 800D  ld hl,$9005
+        0x11, 0x00, 0xA0,       // 8010  ld de,$A000
+        0x01, 0x01, 0x00,       // 8013  ld bc,1
+        0xED, 0xB0,             // 8016  ldir            read (HL), write (DE)
+        0x76,                   // 8018  halt
+    ]);
+    m.watchpoints.add(watch(Space::Memory, 0x9005, 0x9005, "rw"));
+    m.watchpoints.add(watch(Space::Memory, 0xEFFE, 0xEFFF, "rw"));
+    m.watchpoints.add(watch(Space::Memory, 0xA000, 0xA000, "w"));
+
+    let mut hits = Vec::new();
+    while let Some(hit) = run(&mut m) {
+        hits.push((hit.id, hit.pc, hit.access));
+    }
+    assert_eq!(hits, [
+        (1, 0x8004, Access::Read),
+        (1, 0x8008, Access::Read),
+        (2, 0x800B, Access::Write),
+        (2, 0x800C, Access::Read),
+        (1, 0x8016, Access::Read),
+    ]);
+    // Only the first hit of an instruction is reported, but all are counted
+    let counts: Vec<u64> = m.watchpoints.iter().map(|wp| wp.hits).collect();
+    assert_eq!(counts, [4, 4, 1]);
+}
+
+#[test]
+fn ports() {
+    #[rustfmt::skip]
+    let mut m = machine(&[
+        0x3E, 0x41,       // 8000  ld a,'A'
+        0xD3, 0x81,       // 8002  out ($81),a
+        0xDB, 0x80,       // 8004  in a,($80)
+        0xDB, 0x81,       // 8006  in a,($81)
+        0x76,             // 8008  halt
+    ]);
+    m.watchpoints.add(watch(Space::Io, 0x81, 0x81, "w"));
+    m.watchpoints.add(watch(Space::Io, 0x80, 0x80, "r"));
+
+    let hit = run(&mut m).expect("OUT");
+    assert_eq!((hit.id, hit.pc, hit.access, hit.addr, hit.value), (1, 0x8002, Access::Write, 0x81, 0x41));
+    assert_eq!(hit.to_string(), "Watchpoint #1: OUT to port 81 at PC=8002, $41");
+
+    let hit = run(&mut m).expect("IN");
+    assert_eq!((hit.id, hit.pc, hit.access, hit.addr), (2, 0x8004, Access::Read, 0x80));
+    assert_eq!(hit.value, m.cpu.reg.a() as u8);
+    assert_eq!(run(&mut m), None);
+}
+
+#[test]
+fn disabled_watchpoints_do_not_stop() {
+    let mut m = machine(&[0x32, 0x00, 0x90, 0x76]);
+    let id = m.watchpoints.add(watch(Space::Memory, 0x9000, 0x9000, "w"));
+    assert_eq!(m.watchpoints.toggle(id), Ok(false));
+    assert_eq!(run(&mut m), None);
+    assert_eq!(m.watchpoints.iter().next().unwrap().hits, 0);
+}
+
+#[test]
+fn specs() {
+    let cpu = rz80::CPU::new_64k();
+    assert_eq!(parse_spec("$2043", &cpu), Ok(watch(Space::Memory, 0x2043, 0x2043, "w")));
+    assert_eq!(parse_spec("$2000 $100 rw", &cpu), Ok(watch(Space::Memory, 0x2000, 0x20FF, "rw")));
+    assert_eq!(parse_spec("0x2043,r", &cpu), Ok(watch(Space::Memory, 0x2043, 0x2043, "r")));
+    assert_eq!(parse_spec("io $80", &cpu), Ok(watch(Space::Io, 0x80, 0x80, "rw")));
+    assert_eq!(parse_spec("IO,0x10,9,w", &cpu), Ok(watch(Space::Io, 0x10, 0x18, "w")));
+
+    assert!(parse_spec("", &cpu).is_err());
+    assert!(parse_spec("io $100", &cpu).is_err());
+    assert!(parse_spec("$FFFF 2", &cpu).is_err());
+    assert!(parse_spec("$2000 0", &cpu).is_err());
+    assert!(parse_spec("$2000 1 2", &cpu).is_err());
+
+    let listed = parse_spec("io $10 9", &cpu).unwrap().to_string();
+    assert_eq!(listed, "port $10-$18 rw");
+    assert_eq!(parse_spec("$2043 r", &cpu).unwrap().to_string(), "$2043 r");
+}